@@ -1,4 +1,8 @@
-use clap::{command, Parser};
+use clap::Parser;
+
+use crate::input::InputFormat;
+use crate::output::OutputFormat;
+use crate::printer::DisplayFormat;
 
 #[derive(Parser, Debug)]
 #[command(
@@ -19,7 +23,8 @@ Usage examples:
   This will pipe the output of ls in as the first bit of context and then add the user
   query to the context.
 
-"###
+"###,
+    disable_help_subcommand = true
 )]
 pub struct Args {
     /// Optional. The primary query to sent to the model.
@@ -27,10 +32,21 @@ pub struct Args {
     #[arg(index = 1)]
     pub query: Option<String>,
 
-    /// Read query from a file. If query is present this is added to the prompt after query.
-    /// If this is present stdin is ignored.
+    /// Add a file to the prompt, after the query. Can be repeated, and accepts
+    /// globs such as 'src/**/*.rs'. Each file is sent in a fenced block headed
+    /// with its path.
     #[arg(short, long)]
-    pub file: Option<String>,
+    pub file: Vec<String>,
+
+    /// Attach an image, given as a path or an http(s) URL, to the query. Can be
+    /// repeated. Images are kept in the session for follow-up questions.
+    #[arg(long, value_name = "PATH|URL")]
+    pub image: Vec<String>,
+
+    /// Add every text file under a directory to the prompt, skipping hidden
+    /// files, binaries and anything ignored by .gitignore. Can be repeated.
+    #[arg(long, value_name = "PATH")]
+    pub dir: Vec<String>,
 
     /// Specify a system prompt
     #[arg(short, long)]
@@ -40,6 +56,11 @@ pub struct Args {
     #[arg(short = 'M', long)]
     pub model: Option<String>,
 
+    /// Specify the provider API to use: openai, anthropic, ollama or gemini.
+    /// Defaults to the `provider` configuration value.
+    #[arg(short = 'P', long)]
+    pub provider: Option<String>,
+
     /// List all the available models.
     #[arg(short, long)]
     pub list_models: bool,
@@ -69,22 +90,83 @@ pub struct Args {
     #[arg(long = "search")]
     pub search: bool,
 
+    /// Wait for the full response instead of streaming it as it is generated.
+    /// Responses are only streamed when stdout is a terminal.
+    #[arg(long)]
+    pub no_stream: bool,
+
+    /// Print only the fenced code blocks of the reply. The full reply is still
+    /// saved to the session.
+    #[arg(long)]
+    pub code: bool,
+
+    /// Print only the Nth code block, counting from 1. Implies --code.
+    #[arg(long, value_name = "N")]
+    pub block: Option<usize>,
+
+    /// Print only code blocks in this language, such as `rust`. Implies --code.
+    #[arg(long)]
+    pub lang: Option<String>,
+
+    /// Save each code block to a file in this directory, named from the
+    /// file name given in the reply or the language.
+    #[arg(long, value_name = "DIR")]
+    pub write_blocks: Option<String>,
+
+    /// Print the reply as JSON with its model, finish reason, token usage,
+    /// session and timing. Errors are printed as JSON too. `jsonl` prints one
+    /// object per line, for commands with several results.
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = OutputFormat::Text,
+        conflicts_with_all = ["code", "block", "lang", "show_context"]
+    )]
+    pub output: OutputFormat,
+
+    /// How piped input is read. `auto` takes a YAML or JSON list of messages
+    /// as messages and anything else as text; the other formats always read
+    /// it that way. JSON and JSONL accept OpenAI style messages with names,
+    /// content parts and tool calls.
+    #[arg(long, value_enum, default_value_t = InputFormat::Auto)]
+    pub input_format: InputFormat,
+
+    /// Send piped input as it is: never as messages or an image, and without
+    /// reading directives from it.
+    #[arg(long, conflicts_with = "input_format")]
+    pub raw_stdin: bool,
+
+    /// How replies and the messages of `session show`, `session --view` and
+    /// `view` are shown. Defaults to colors on a terminal unless NO_COLOR is
+    /// set, plain text otherwise, and Markdown for `view`.
+    #[arg(long, value_enum)]
+    pub format: Option<DisplayFormat>,
+
     #[command(subcommand)]
     pub subcmd: Option<SubCommands>,
 }
 
 #[derive(Parser, Debug)]
 pub enum SubCommands {
+    /// Chat interactively, keeping the conversation in the current session.
+    Chat(ChatSubCommand),
     /// Render the context without running a query against the model.
     View(ViewSubCommand),
+    /// Count the tokens of each message in the context without running a query.
+    Tokens(TokensSubCommand),
     /// Set or get default configuration values with your config.toml.
     Config(ConfigSubCommand),
     /// Used for continuous chat session management and shell integration.
-    /// To enable session caching in your terminal, add the following to your .bashrc or .zshrc:
+    /// Create and select named sessions with `cgip session new <name>`, or
+    /// to enable session caching in your terminal, add the following to your .bashrc or .zshrc:
     /// export CGIP_SESSION_NAME=$(uuid) # for each new terminal session to be unique
     /// or
     /// export CGIP_SESSION_NAME=$(date -I) # for a session that will be the same for the entire day
     Session(SessionSubCommand),
+    /// Search the history of all past conversations.
+    History(HistorySubCommand),
+    /// Send the last question in the session again and replace the reply.
+    Retry(RetrySubCommand),
     /// Analyze an image using vision models. Use --file to specify the image path.
     Image(ImageSubCommand),
     /// Convert text to speech using OpenAI's TTS models.
@@ -93,8 +175,12 @@ pub enum SubCommands {
     Embedding(EmbeddingSubCommand),
     /// Execute agentic instructions using tool calls.
     Agent(AgentSubCommand),
+    /// Suggest a shell command for a request and run it once confirmed.
+    Cmd(CmdSubCommand),
     /// Upgrade cgip to the latest release.
     Upgrade(UpgradeSubCommand),
+    /// Print help for cgip, a subcommand, or `directives`.
+    Help(HelpSubCommand),
 }
 
 #[derive(Parser, Debug)]
@@ -107,6 +193,141 @@ pub struct SessionSubCommand {
     /// View the current session context
     #[arg(short, long)]
     pub view: bool,
+
+    #[command(subcommand)]
+    pub action: Option<SessionAction>,
+}
+
+#[derive(Parser, Debug)]
+pub enum SessionAction {
+    /// List all sessions, marking the current one with `*`.
+    List,
+    /// Create a session and make it the current one.
+    New { name: String },
+    /// Make an existing session the current one.
+    /// CGIP_SESSION_NAME still takes precedence when it is set.
+    Switch { name: String },
+    /// Rename a session.
+    Rename { name: String, new_name: String },
+    /// Delete a session.
+    Delete { name: String },
+    /// Print the messages of a session, the current one by default.
+    Show { name: Option<String> },
+    /// Edit a session as YAML in $VISUAL or $EDITOR, the current one by default.
+    Edit { name: Option<String> },
+    /// Remove the last messages from the current session.
+    Pop {
+        /// Number of messages to remove
+        #[arg(default_value_t = 1)]
+        count: usize,
+    },
+    /// Copy the current session into a new one and make it the current one.
+    Fork {
+        /// Name of the new session
+        new_name: String,
+
+        /// Only copy the first N messages
+        #[arg(long, value_name = "N")]
+        at: Option<usize>,
+    },
+    /// Show which sessions were forked from which.
+    Tree,
+    /// Write a session to a Markdown, JSON, YAML or HTML document.
+    Export {
+        /// Session to export, the current one by default
+        name: Option<String>,
+
+        /// Document format (md, json, yaml, html). Guessed from the --out
+        /// extension when omitted, otherwise Markdown.
+        #[arg(short, long)]
+        format: Option<String>,
+
+        /// File to write instead of standard output
+        #[arg(short, long)]
+        out: Option<String>,
+    },
+    /// Create a session from a document written by `export`.
+    Import {
+        /// File to read
+        file: String,
+
+        /// Document format (md, json, yaml, html). Guessed from the file
+        /// extension when omitted.
+        #[arg(short, long)]
+        format: Option<String>,
+
+        /// Name of the new session, the file name without extension by default
+        #[arg(short, long)]
+        name: Option<String>,
+    },
+}
+
+#[derive(Parser, Debug)]
+#[command(author, version, about = "Chat interactively with slash commands", long_about = None)]
+pub struct ChatSubCommand {}
+
+#[derive(Parser, Debug)]
+#[command(author, version, about = "Send the last question in the session again", long_about = None)]
+pub struct RetrySubCommand {
+    /// Model to ask this time, instead of the configured one
+    #[arg(short = 'M', long)]
+    pub model: Option<String>,
+
+    /// Keep the replaced reply as an alternative of the new one
+    #[arg(short, long)]
+    pub keep: bool,
+
+    /// List the current reply and its kept alternatives instead of retrying
+    #[arg(short, long, conflicts_with_all = ["model", "keep"])]
+    pub list: bool,
+}
+
+#[derive(Parser, Debug)]
+#[command(author, version, about = "Print help for cgip, a subcommand, or directives", long_about = None)]
+pub struct HelpSubCommand {
+    /// A subcommand, or `directives` for the inline directives
+    pub topic: Option<String>,
+}
+
+#[derive(Parser, Debug)]
+#[command(author, version, about = "Suggest a shell command and run it once confirmed", long_about = None)]
+pub struct CmdSubCommand {
+    /// What the command should do, such as "find files over 1GB changed this week"
+    #[arg(required = true)]
+    pub request: Vec<String>,
+
+    /// Run commands that aren't destructive without asking
+    #[arg(short, long)]
+    pub yes: bool,
+
+    /// Only print the command, with the explanation and risk on stderr
+    #[arg(short, long, conflicts_with = "yes")]
+    pub print: bool,
+}
+
+#[derive(Parser, Debug)]
+#[command(author, version, about = "Search the history of all past conversations", long_about = None)]
+pub struct HistorySubCommand {
+    #[command(subcommand)]
+    pub action: HistoryAction,
+}
+
+#[derive(Parser, Debug)]
+pub enum HistoryAction {
+    /// Find past messages containing all of the given terms.
+    Search {
+        #[arg(required = true)]
+        terms: Vec<String>,
+
+        /// Maximum number of results
+        #[arg(short = 'n', long, default_value_t = 20)]
+        limit: usize,
+    },
+    /// Print a past exchange and add it to the current session.
+    Show {
+        /// Id of any message in the exchange, as printed by `search`
+        id: i64,
+    },
 }
 
 #[derive(Parser, Debug)]
@@ -115,6 +336,10 @@ pub struct ViewSubCommand {
     // You can add options and arguments specific to the `view` subcommand here.
 }
 
+#[derive(Parser, Debug)]
+#[command(author, version, about = "Count tokens in the context", long_about = None)]
+pub struct TokensSubCommand {}
+
 #[derive(Parser, Debug)]
 #[command(author, version, about = "Set or get configuration values", long_about = None)]
 pub struct ConfigSubCommand {
@@ -0,0 +1,389 @@
+use std::collections::HashSet;
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::{Component, Path, PathBuf};
+
+use pulldown_cmark::{CodeBlockKind, Event, Parser, Tag, TagEnd};
+
+use crate::args::Args;
+use crate::error::{CgipError, Result};
+
+/// A fenced code block from a reply.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CodeBlock {
+    pub lang: Option<String>,
+    /// A file name suggested by the info string or the first line.
+    pub file_name: Option<String>,
+    pub code: String,
+}
+
+/// Whether the reply is replaced by its code blocks on stdout.
+pub fn code_only(args: &Args) -> bool {
+    args.code || args.block.is_some() || args.lang.is_some()
+}
+
+/// Finds a file name in the words after the language of an info string, as
+/// in `rust src/main.rs`, `python title="hello.py"` or `rust:src/main.rs`.
+fn name_from_info(info: &str) -> (Option<String>, Option<String>) {
+    let mut words = info.split_whitespace();
+    let first = match words.next() {
+        Some(first) => first,
+        None => return (None, None),
+    };
+    let (lang, inline_name) = match first.split_once(':') {
+        Some((lang, name)) if !name.is_empty() => (lang, Some(name.to_string())),
+        _ => (first, None),
+    };
+    let name = inline_name.or_else(|| {
+        words.find_map(|word| {
+            let value = match word.split_once('=') {
+                Some(("file" | "filename" | "title" | "path", value)) => value,
+                Some(_) => return None,
+                None => word,
+            };
+            let value = value.trim_matches(|c| c == '"' || c == '\'');
+            value.contains('.').then(|| value.to_string())
+        })
+    });
+    let lang = Some(lang.to_lowercase()).filter(|lang| !lang.is_empty());
+    (lang, name)
+}
+
+/// Finds a name given in a comment on the first line, such as
+/// `// file: src/main.rs` or `# filename: build.py`.
+fn name_from_comment(code: &str) -> Option<String> {
+    let line = code.lines().next()?.trim();
+    let comment = ["//", "#", "--", "/*", "<!--", ";"]
+        .iter()
+        .find_map(|start| line.strip_prefix(start))?;
+    let comment = comment
+        .trim_end_matches("*/")
+        .trim_end_matches("-->")
+        .trim();
+    let (label, name) = comment.split_once(':')?;
+    let name = name.trim();
+    (matches!(
+        label.trim().to_lowercase().as_str(),
+        "file" | "filename" | "path"
+    ) && !name.is_empty()
+        && !name.contains(char::is_whitespace))
+    .then(|| name.to_string())
+}
+
+/// Returns the fenced code blocks of `markdown` in order.
+pub fn extract(markdown: &str) -> Vec<CodeBlock> {
+    let mut blocks = Vec::new();
+    let mut current: Option<CodeBlock> = None;
+    for event in Parser::new(markdown) {
+        match event {
+            Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(info))) => {
+                let (lang, file_name) = name_from_info(&info);
+                current = Some(CodeBlock {
+                    lang,
+                    file_name,
+                    code: String::new(),
+                });
+            }
+            Event::Text(text) => {
+                if let Some(block) = &mut current {
+                    block.code.push_str(&text);
+                }
+            }
+            Event::End(TagEnd::CodeBlock) => {
+                if let Some(mut block) = current.take() {
+                    if block.file_name.is_none() {
+                        block.file_name = name_from_comment(&block.code);
+                    }
+                    blocks.push(block);
+                }
+            }
+            _ => {}
+        }
+    }
+    blocks
+}
+
+/// Keeps the blocks in `lang`, then picks the `number`th of them, counting
+/// from 1.
+pub fn select(
+    blocks: Vec<CodeBlock>,
+    number: Option<usize>,
+    lang: Option<&str>,
+) -> Result<Vec<CodeBlock>> {
+    let mut blocks: Vec<CodeBlock> = match lang {
+        Some(lang) => {
+            let lang = lang.to_lowercase();
+            blocks
+                .into_iter()
+                .filter(|block| block.lang.as_deref().is_some_and(|l| l == lang))
+                .collect()
+        }
+        None => blocks,
+    };
+    let kind = lang.map(|lang| format!("{} ", lang)).unwrap_or_default();
+    if blocks.is_empty() {
+        return Err(CgipError::BadResponse(format!(
+            "The reply has no {}code blocks",
+            kind
+        )));
+    }
+
+    match number {
+        Some(number) if number == 0 || number > blocks.len() => Err(CgipError::Usage(format!(
+            "There is no {}code block {}, the reply has {}",
+            kind,
+            number,
+            blocks.len()
+        ))),
+        Some(number) => Ok(vec![blocks.swap_remove(number - 1)]),
+        None => Ok(blocks),
+    }
+}
+
+/// The usual file extension for code in `lang`.
+fn extension(lang: Option<&str>) -> &str {
+    match lang.unwrap_or("") {
+        "rust" | "rs" => "rs",
+        "python" | "py" => "py",
+        "bash" | "sh" | "shell" | "zsh" | "console" => "sh",
+        "fish" => "fish",
+        "powershell" | "ps1" | "pwsh" => "ps1",
+        "javascript" | "js" | "node" => "js",
+        "typescript" | "ts" => "ts",
+        "tsx" => "tsx",
+        "jsx" => "jsx",
+        "go" | "golang" => "go",
+        "c" => "c",
+        "cpp" | "c++" | "cxx" => "cpp",
+        "csharp" | "c#" | "cs" => "cs",
+        "java" => "java",
+        "kotlin" | "kt" => "kt",
+        "swift" => "swift",
+        "ruby" | "rb" => "rb",
+        "php" => "php",
+        "lua" => "lua",
+        "sql" => "sql",
+        "html" => "html",
+        "css" => "css",
+        "json" => "json",
+        "yaml" | "yml" => "yaml",
+        "toml" => "toml",
+        "xml" => "xml",
+        "markdown" | "md" => "md",
+        "dockerfile" => "dockerfile",
+        "makefile" | "make" => "mk",
+        _ => "txt",
+    }
+}
+
+/// A suggested name is used as long as it stays inside the target
+/// directory and has no hidden parts. Names like `.git/hooks/pre-commit` or
+/// `.envrc` would let a reply run code.
+fn safe_name(name: &str) -> Option<PathBuf> {
+    let path = Path::new(name);
+    path.components()
+        .all(|component| match component {
+            Component::Normal(part) => !part.to_string_lossy().starts_with('.'),
+            _ => false,
+        })
+        .then(|| path.to_path_buf())
+}
+
+/// `name` with `-n` added to its stem, as in `run-2.sh`.
+fn numbered(name: &Path, n: usize) -> PathBuf {
+    let stem = name.file_stem().unwrap_or_default().to_string_lossy();
+    match name.extension() {
+        Some(ext) => name.with_file_name(format!("{}-{}.{}", stem, n, ext.to_string_lossy())),
+        None => name.with_file_name(format!("{}-{}", stem, n)),
+    }
+}
+
+/// Picks a file name for each block: its suggested name, or `block-N` with
+/// the extension of its language. Duplicates get a numbered suffix.
+pub fn file_names(blocks: &[CodeBlock]) -> Vec<PathBuf> {
+    let mut used = HashSet::new();
+    blocks
+        .iter()
+        .enumerate()
+        .map(|(i, block)| {
+            let name = block
+                .file_name
+                .as_deref()
+                .and_then(safe_name)
+                .unwrap_or_else(|| {
+                    let ext = extension(block.lang.as_deref());
+                    PathBuf::from(format!("block-{}.{}", i + 1, ext))
+                });
+            let mut candidate = name.clone();
+            let mut n = 2;
+            while !used.insert(candidate.clone()) {
+                candidate = numbered(&name, n);
+                n += 1;
+            }
+            candidate
+        })
+        .collect()
+}
+
+/// Writes each block to its own new file in `dir`, returning the paths
+/// written. Existing files are never overwritten: a block whose name is taken
+/// gets the next free numbered name instead.
+pub fn write(blocks: &[CodeBlock], dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut written = Vec::new();
+    for (block, name) in blocks.iter().zip(file_names(blocks)) {
+        let path = dir.join(&name);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(|e| {
+                CgipError::Io(format!("Could not create '{}': {}", parent.display(), e))
+            })?;
+        }
+        let mut candidate = path.clone();
+        let mut n = 2;
+        let mut file = loop {
+            match OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&candidate)
+            {
+                Ok(file) => break file,
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+                    candidate = numbered(&path, n);
+                    n += 1;
+                }
+                Err(e) => {
+                    return Err(CgipError::Io(format!(
+                        "Could not write '{}': {}",
+                        candidate.display(),
+                        e
+                    )))
+                }
+            }
+        };
+        file.write_all(block.code.as_bytes()).map_err(|e| {
+            CgipError::Io(format!("Could not write '{}': {}", candidate.display(), e))
+        })?;
+        written.push(candidate);
+    }
+    Ok(written)
+}
+
+/// Prints or writes the code blocks of `reply` as the flags in `args` ask.
+pub fn run(args: &Args, reply: &str) -> Result<()> {
+    if !code_only(args) && args.write_blocks.is_none() {
+        return Ok(());
+    }
+    let blocks = select(extract(reply), args.block, args.lang.as_deref())?;
+
+    if code_only(args) {
+        let code: Vec<&str> = blocks.iter().map(|block| block.code.as_str()).collect();
+        print!("{}", code.join("\n"));
+    }
+    if let Some(dir) = &args.write_blocks {
+        for path in write(&blocks, Path::new(dir))? {
+            eprintln!("Wrote {}", path.display());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const REPLY: &str = "Here you go:\n\n```rust src/main.rs\nfn main() {}\n```\n\nThen run:\n\n```sh\n# file: run.sh\ncargo run\n```\n\n```python\nprint('hi')\n```\n\n```\nplain\n```\n";
+
+    #[test]
+    fn test_extract_blocks_and_names() {
+        let blocks = extract(REPLY);
+        assert_eq!(blocks.len(), 4);
+        assert_eq!(blocks[0].lang.as_deref(), Some("rust"));
+        assert_eq!(blocks[0].file_name.as_deref(), Some("src/main.rs"));
+        assert_eq!(blocks[0].code, "fn main() {}\n");
+        assert_eq!(blocks[1].file_name.as_deref(), Some("run.sh"));
+        assert_eq!(blocks[2].file_name, None);
+        assert_eq!(blocks[3].lang, None);
+
+        assert_eq!(
+            name_from_info("python title=\"hello.py\""),
+            (Some("python".to_string()), Some("hello.py".to_string()))
+        );
+        assert_eq!(
+            name_from_info("rust:lib.rs"),
+            (Some("rust".to_string()), Some("lib.rs".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_select_filters() {
+        let picked = select(extract(REPLY), Some(2), None).unwrap();
+        assert_eq!(picked[0].code, "# file: run.sh\ncargo run\n");
+
+        let picked = select(extract(REPLY), None, Some("Python")).unwrap();
+        assert_eq!(picked.len(), 1);
+        assert_eq!(picked[0].code, "print('hi')\n");
+
+        assert!(select(extract(REPLY), Some(5), None).is_err());
+        assert!(select(extract(REPLY), Some(0), None).is_err());
+        assert!(select(extract(REPLY), None, Some("go")).is_err());
+    }
+
+    #[test]
+    fn test_file_names() {
+        let mut blocks = extract(REPLY);
+        blocks.push(CodeBlock {
+            lang: Some("sh".to_string()),
+            file_name: Some("../escape.sh".to_string()),
+            code: String::new(),
+        });
+        blocks.push(CodeBlock {
+            lang: Some("sh".to_string()),
+            file_name: Some(".git/hooks/pre-commit".to_string()),
+            code: String::new(),
+        });
+        blocks.push(CodeBlock {
+            lang: None,
+            file_name: Some("config/.envrc".to_string()),
+            code: String::new(),
+        });
+        blocks.push(CodeBlock {
+            lang: None,
+            file_name: Some("run.sh".to_string()),
+            code: String::new(),
+        });
+        let names: Vec<String> = file_names(&blocks)
+            .iter()
+            .map(|name| name.to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                "src/main.rs",
+                "run.sh",
+                "block-3.py",
+                "block-4.txt",
+                "block-5.sh",
+                "block-6.sh",
+                "block-7.txt",
+                "run-2.sh"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_write_blocks() {
+        let dir = tempfile::tempdir().unwrap();
+        let blocks = select(extract(REPLY), None, Some("rust")).unwrap();
+        let written = write(&blocks, dir.path()).unwrap();
+        assert_eq!(written, vec![dir.path().join("src/main.rs")]);
+        assert_eq!(fs::read_to_string(&written[0]).unwrap(), "fn main() {}\n");
+
+        // A second run keeps the first file and writes next to it
+        fs::write(&written[0], "// mine\n").unwrap();
+        let written = write(&blocks, dir.path()).unwrap();
+        assert_eq!(written, vec![dir.path().join("src/main-2.rs")]);
+        assert_eq!(
+            fs::read_to_string(dir.path().join("src/main.rs")).unwrap(),
+            "// mine\n"
+        );
+    }
+}
@@ -1,76 +1,178 @@
+use std::io::{self, Write};
+use std::time::Instant;
+
+use atty::Stream;
 use spinners::{Spinner, Spinners};
 
 use crate::{
     args::Args,
-    chatgpt::{GptClient, Message, Role},
-    sub::session::save_to_tty_context,
+    blocks,
+    chatgpt::{message::MessageMeta, GptClient, Message, Role},
+    error::Result,
+    output::{self, Reply},
+    printer::DisplayFormat,
+    render::{self, StreamRenderer},
+    sub::session::{save_to_tty_context, SessionStore},
     utils::markdown_from_messages,
 };
 
-pub fn run(args: &Args, client: &mut GptClient) {
-    let response_text: String;
+pub fn run(args: &Args, client: &mut GptClient) -> Result<()> {
+    apply_overrides(args, client);
+
+    // List available models
+    if args.list_models {
+        let models = client.list_models()?;
+        if args.output.is_json() {
+            output::print_all(args.output, &models);
+            return Ok(());
+        }
+        for model in models {
+            println!("{}", model);
+        }
+        return Ok(());
+    }
+
+    let response_text = match respond(args, client)? {
+        Some(text) => text,
+        None => return Ok(()),
+    };
+    let message = Message {
+        meta: MessageMeta {
+            model: Some(client.model().to_string()),
+            ..Default::default()
+        },
+        ..Message::new(Role::Assistant, response_text.clone())
+    };
+    let messages_to_save = vec![message];
 
+    if !args.no_session && !client.options.no_session {
+        save_to_tty_context(
+            &client.config_manager,
+            messages_to_save,
+            client.last_usage.as_ref(),
+        );
+    }
+    blocks::run(args, &response_text)
+}
+
+/// Overrides the configured model and provider with the ones given in `args`.
+pub fn apply_overrides(args: &Args, client: &mut GptClient) {
     // TODO: Think about whether it is better to be passing this config around
     // or go with someting more functional
-    //
-    // Override model from config if it was provided in args
     if let Some(ref model) = args.model {
         client.config_manager.config.model = model.clone();
     }
+    if let Some(ref provider) = args.provider {
+        client.config_manager.config.provider = provider.clone();
+    }
+}
 
-    // List available models
-    if args.list_models {
-        let models = client.list_models();
-        for model in models {
-            println!("{}", model);
-        }
-        return;
+/// Gets a reply to the messages in `client` and prints it the way `args`
+/// asks for. Returns `None` when the context was shown instead of a reply.
+pub fn respond(args: &Args, client: &mut GptClient) -> Result<Option<String>> {
+    if args.output.is_json() {
+        return respond_json(args, client).map(Some);
     }
+    let response_text: String;
 
     // Override show_progress from config if it was provided in args
     let show_progress = args.show_progress || client.config_manager.config.show_progress;
+    let show_context = args.show_context || client.config_manager.config.show_context;
+    let markdown = args.markdown || client.config_manager.config.markdown;
 
-    if show_progress {
+    // Replies are rendered as Markdown on a terminal, and left as they are
+    // when piped
+    let format = args.format.unwrap_or_else(DisplayFormat::detect);
+    let rich = format == DisplayFormat::Color;
+
+    // Stream straight to the terminal; piped output still gets the complete
+    // response in one write. The other formats wait for the full reply.
+    // With --code only the code blocks are printed, once the reply is complete
+    let quiet = blocks::code_only(args);
+    let stream = !args.no_stream
+        && !show_context
+        && !quiet
+        && matches!(format, DisplayFormat::Plain | DisplayFormat::Color)
+        && atty::is(Stream::Stdout);
+
+    if stream && rich {
+        let mut stdout = io::stdout();
+        let mut renderer = StreamRenderer::new(render::terminal_width());
+        response_text = client.complete_streaming(&mut |delta| {
+            let _ = write!(stdout, "{}", renderer.push(delta));
+            let _ = stdout.flush();
+        })?;
+        print!("{}", renderer.finish());
+    } else if stream {
+        let mut stdout = io::stdout();
+        response_text = client.complete_streaming(&mut |delta| {
+            let _ = write!(stdout, "{}", delta);
+            let _ = stdout.flush();
+        })?;
+        println!();
+    } else if show_progress {
         let mut spinner = Spinner::new(Spinners::Dots9, "Thinking...".into());
-        response_text = client.complete();
+        let response = client.complete();
         spinner.stop();
         print!("\x1B[2K"); // Clear the current line
         print!("\r"); // Move the cursor to the beginning of the current line
+        response_text = response?;
     } else {
-        response_text = client.complete();
+        response_text = client.complete()?;
     }
 
-    let show_context = args.show_context || client.config_manager.config.show_context;
-    let markdown = args.markdown || client.config_manager.config.markdown;
-
     if show_context {
         if markdown {
             let visible_messages = client
                 .messages
                 .iter()
+                .filter(|msg| msg.role != Role::System)
                 .cloned()
-                .filter(|msg| msg.role != "system")
                 .collect();
             let context = markdown_from_messages(visible_messages);
 
             println!("{}", context);
-            return;
+            return Ok(None);
         }
         let context = client.to_yaml(true);
         println!("{}", context);
-        return;
+        return Ok(None);
     }
-    println!("{}", response_text);
-    let message = Message {
-        role: Role::Assistant.to_string().to_lowercase(),
-        name: None,
-        tool_call_id: None,
-        tool_calls: None,
-        content: crate::chatgpt::MessageContent::Text(response_text),
-    };
-    let messages_to_save = vec![message];
-
-    if !args.no_session {
-        save_to_tty_context(&client.config_manager, messages_to_save);
+    if quiet {
+        // The caller prints the code blocks
+    } else if !stream {
+        let mut printer = format.printer();
+        printer.print_reply(&response_text);
+        printer.finish();
     }
+    Ok(Some(response_text))
+}
+
+/// Gets a reply and prints it as a JSON object with its metadata.
+fn respond_json(args: &Args, client: &mut GptClient) -> Result<String> {
+    let start = Instant::now();
+    let content = client.complete()?;
+    let elapsed_ms = start.elapsed().as_millis();
+
+    let session = if args.no_session || client.options.no_session {
+        None
+    } else {
+        SessionStore::open_default()
+            .ok()
+            .and_then(|store| store.current())
+    };
+    let reply = Reply {
+        content: content.clone(),
+        model: client
+            .last_model
+            .clone()
+            .unwrap_or_else(|| client.model().to_string()),
+        id: client.last_id.clone(),
+        finish_reason: client.last_finish_reason.clone(),
+        usage: client.last_usage.clone(),
+        session,
+        elapsed_ms,
+    };
+    output::print(args.output, &reply);
+    Ok(content)
 }
@@ -0,0 +1,446 @@
+use std::env;
+use std::io::{BufRead, BufReader};
+
+use serde_json::{json, Value};
+
+use super::{
+    error_from_response, http_client, request_timeout, split_data_url, stream_error, ChatBackend,
+    Completion, CompletionRequest,
+};
+use crate::chatgpt::message::{ContentPart, Message, MessageContent, ToolCall};
+use crate::chatgpt::response::Usage;
+use crate::chatgpt::role::Role;
+use crate::chatgpt::stream::{parse_sse_line, SseData};
+use crate::error::{CgipError, Result};
+use crate::retry::RetryPolicy;
+
+const ANTHROPIC_VERSION: &str = "2023-06-01";
+/// The Messages API requires `max_tokens`, so use this when none is given.
+const DEFAULT_MAX_TOKENS: u32 = 4096;
+
+/// Sent in the system prompt when a reply is requested as JSON.
+const JSON_INSTRUCTION: &str = "Reply with a single JSON object and nothing else.";
+
+/// The native Anthropic Messages API.
+pub struct AnthropicBackend {
+    api_key: String,
+    base_url: String,
+    retry: RetryPolicy,
+}
+
+impl AnthropicBackend {
+    pub fn from_env(retry: RetryPolicy) -> Result<Self> {
+        let api_key = env::var("ANTHROPIC_API_KEY").map_err(|_| {
+            CgipError::Config("Missing ANTHROPIC_API_KEY environment variable".into())
+        })?;
+        let base_url = env::var("ANTHROPIC_BASE_URL")
+            .unwrap_or_else(|_| "https://api.anthropic.com".to_string());
+        Ok(AnthropicBackend {
+            api_key,
+            base_url,
+            retry,
+        })
+    }
+
+    fn url(&self, path: &str) -> String {
+        let base = self.base_url.trim_end_matches('/');
+        let base = base.strip_suffix("/v1").unwrap_or(base);
+        format!("{}/v1/{}", base, path)
+    }
+
+    fn send(&self, body: &Value, stream: bool) -> Result<reqwest::blocking::Response> {
+        let timeout = if stream { None } else { request_timeout() };
+        let client = http_client(timeout)?;
+        let response = self.retry.send(|| {
+            client
+                .post(self.url("messages"))
+                .header("x-api-key", &self.api_key)
+                .header("anthropic-version", ANTHROPIC_VERSION)
+                .json(body)
+        })?;
+
+        if !response.status().is_success() {
+            return Err(error_from_response(response));
+        }
+        Ok(response)
+    }
+}
+
+fn content_blocks(content: &MessageContent) -> Vec<Value> {
+    let parts = match content {
+        MessageContent::Text(text) => vec![ContentPart::Text { text: text.clone() }],
+        MessageContent::Multi(parts) => parts.clone(),
+    };
+
+    parts
+        .iter()
+        .filter_map(|part| match part {
+            ContentPart::Text { text } if text.is_empty() => None,
+            ContentPart::Text { text } => Some(json!({"type": "text", "text": text})),
+            ContentPart::ImageUrl { image_url } => Some(match split_data_url(&image_url.url) {
+                Some((media_type, data)) => json!({
+                    "type": "image",
+                    "source": {"type": "base64", "media_type": media_type, "data": data}
+                }),
+                None => json!({
+                    "type": "image",
+                    "source": {"type": "url", "url": image_url.url}
+                }),
+            }),
+        })
+        .collect()
+}
+
+/// Splits out the system prompt and converts the remaining messages into
+/// Messages API turns. Consecutive turns with the same role are merged since
+/// tool results have to sit in the user turn that follows the tool use.
+fn translate_messages(messages: &[Message]) -> (Option<String>, Vec<Value>) {
+    let mut system = Vec::new();
+    let mut turns: Vec<(String, Vec<Value>)> = Vec::new();
+
+    for msg in messages {
+        let (role, blocks) = match msg.role {
+            Role::System | Role::Developer => {
+                system.push(msg.content.to_string());
+                continue;
+            }
+            Role::Tool => (
+                "user",
+                vec![json!({
+                    "type": "tool_result",
+                    "tool_use_id": msg.tool_call_id.clone().unwrap_or_default(),
+                    "content": msg.content.to_string(),
+                })],
+            ),
+            Role::Assistant => {
+                let mut blocks = content_blocks(&msg.content);
+                for call in msg.tool_calls.iter().flatten() {
+                    blocks.push(json!({
+                        "type": "tool_use",
+                        "id": call.id,
+                        "name": call.function.name,
+                        "input": call.arguments(),
+                    }));
+                }
+                ("assistant", blocks)
+            }
+            Role::User => ("user", content_blocks(&msg.content)),
+        };
+
+        if blocks.is_empty() {
+            continue;
+        }
+        match turns.last_mut() {
+            Some((last_role, last_blocks)) if last_role == role => last_blocks.extend(blocks),
+            _ => turns.push((role.to_string(), blocks)),
+        }
+    }
+
+    let system = if system.is_empty() {
+        None
+    } else {
+        Some(system.join("\n\n"))
+    };
+    let turns = turns
+        .into_iter()
+        .map(|(role, content)| json!({"role": role, "content": content}))
+        .collect();
+    (system, turns)
+}
+
+fn request_body(request: &CompletionRequest, stream: bool) -> Value {
+    let (system, messages) = translate_messages(request.messages);
+    let mut body = json!({
+        "model": request.model,
+        "max_tokens": request.max_tokens.unwrap_or(DEFAULT_MAX_TOKENS),
+        "messages": messages,
+    });
+
+    // Anthropic has no JSON mode, so the system prompt asks for it
+    let system = match (system, request.json) {
+        (system, false) => system,
+        (Some(system), true) => Some(format!("{}\n\n{}", system, JSON_INSTRUCTION)),
+        (None, true) => Some(JSON_INSTRUCTION.to_string()),
+    };
+    if let Some(system) = system {
+        body["system"] = json!(system);
+    }
+    if let Some(temperature) = request.temperature {
+        body["temperature"] = json!(temperature);
+    }
+    if stream {
+        body["stream"] = json!(true);
+    }
+
+    let mut tools: Vec<Value> = request
+        .tools
+        .and_then(|t| t.as_array())
+        .map(|tools| {
+            tools
+                .iter()
+                .map(|tool| {
+                    json!({
+                        "name": tool["function"]["name"],
+                        "description": tool["function"]["description"],
+                        "input_schema": tool["function"]["parameters"],
+                    })
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    if request.web_search {
+        tools.push(json!({"type": "web_search_20250305", "name": "web_search", "max_uses": 5}));
+    }
+    if !tools.is_empty() {
+        body["tools"] = json!(tools);
+    }
+
+    body
+}
+
+fn finish_reason(stop_reason: &str) -> String {
+    match stop_reason {
+        "end_turn" | "stop_sequence" => "stop".to_string(),
+        "max_tokens" => "length".to_string(),
+        "tool_use" => "tool_calls".to_string(),
+        other => other.to_string(),
+    }
+}
+
+fn usage(input_tokens: u64, output_tokens: u64) -> Usage {
+    Usage {
+        prompt_tokens: input_tokens,
+        completion_tokens: output_tokens,
+        total_tokens: input_tokens + output_tokens,
+    }
+}
+
+fn parse_completion(value: &Value) -> Completion {
+    let mut content = String::new();
+    let mut tool_calls = Vec::new();
+
+    for block in value["content"].as_array().into_iter().flatten() {
+        match block["type"].as_str() {
+            Some("text") => content.push_str(block["text"].as_str().unwrap_or("")),
+            Some("tool_use") => tool_calls.push(ToolCall::new(
+                block["id"].as_str().unwrap_or(""),
+                block["name"].as_str().unwrap_or(""),
+                &block["input"],
+            )),
+            _ => {}
+        }
+    }
+
+    Completion {
+        id: value["id"].as_str().map(|s| s.to_string()),
+        model: value["model"].as_str().map(|s| s.to_string()),
+        content,
+        tool_calls: (!tool_calls.is_empty()).then_some(tool_calls),
+        finish_reason: value["stop_reason"].as_str().map(finish_reason),
+        usage: value.get("usage").map(|u| {
+            usage(
+                u["input_tokens"].as_u64().unwrap_or(0),
+                u["output_tokens"].as_u64().unwrap_or(0),
+            )
+        }),
+    }
+}
+
+/// Reads a Messages API event stream, forwarding text deltas to `on_delta`.
+fn read_events<R: BufRead>(reader: R, on_delta: &mut dyn FnMut(&str)) -> Result<Completion> {
+    let mut completion = Completion::default();
+    let mut input_tokens = 0;
+
+    for line in reader.lines() {
+        let line = line.map_err(stream_error)?;
+        let event: Value = match parse_sse_line(&line) {
+            Some(SseData::Json(data)) => serde_json::from_str(data).unwrap_or_default(),
+            _ => continue,
+        };
+
+        match event["type"].as_str() {
+            Some("message_start") => {
+                let message = &event["message"];
+                completion.id = message["id"].as_str().map(|s| s.to_string());
+                completion.model = message["model"].as_str().map(|s| s.to_string());
+                input_tokens = message["usage"]["input_tokens"].as_u64().unwrap_or(0);
+            }
+            Some("content_block_delta") => {
+                if let Some(text) = event["delta"]["text"].as_str() {
+                    on_delta(text);
+                    completion.content.push_str(text);
+                }
+            }
+            Some("message_delta") => {
+                if let Some(reason) = event["delta"]["stop_reason"].as_str() {
+                    completion.finish_reason = Some(finish_reason(reason));
+                }
+                if let Some(output_tokens) = event["usage"]["output_tokens"].as_u64() {
+                    completion.usage = Some(usage(input_tokens, output_tokens));
+                }
+            }
+            Some("message_stop") => break,
+            Some("error") => {
+                let message = event["error"]["message"]
+                    .as_str()
+                    .unwrap_or("unknown error")
+                    .to_string();
+                return Err(match event["error"]["type"].as_str() {
+                    Some("rate_limit_error") => CgipError::RateLimit(message),
+                    Some("overloaded_error") => CgipError::Api {
+                        status: 529,
+                        message,
+                    },
+                    _ => CgipError::BadResponse(message),
+                });
+            }
+            _ => {}
+        }
+    }
+
+    Ok(completion)
+}
+
+impl ChatBackend for AnthropicBackend {
+    fn complete(&self, request: &CompletionRequest) -> Result<Completion> {
+        let response = self.send(&request_body(request, false), false)?;
+        let value: Value = response.json()?;
+        Ok(parse_completion(&value))
+    }
+
+    fn complete_streaming(
+        &self,
+        request: &CompletionRequest,
+        on_delta: &mut dyn FnMut(&str),
+    ) -> Result<Completion> {
+        let response = self.send(&request_body(request, true), true)?;
+        read_events(BufReader::new(response), on_delta)
+    }
+
+    fn list_models(&self) -> Result<Vec<String>> {
+        let client = http_client(request_timeout())?;
+        let response = self.retry.send(|| {
+            client
+                .get(self.url("models"))
+                .header("x-api-key", &self.api_key)
+                .header("anthropic-version", ANTHROPIC_VERSION)
+        })?;
+        if !response.status().is_success() {
+            return Err(error_from_response(response));
+        }
+
+        let value: Value = response.json()?;
+        Ok(value["data"]
+            .as_array()
+            .map(|arr| {
+                arr.iter()
+                    .filter_map(|m| m["id"].as_str().map(|s| s.to_string()))
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chatgpt::message::ImageUrl;
+    use crate::chatgpt::Role;
+
+    #[test]
+    fn test_translate_messages_splits_system_and_images() {
+        let messages = vec![
+            Message::new(Role::System, "be brief".to_string()),
+            Message {
+                content: MessageContent::Multi(vec![
+                    ContentPart::Text {
+                        text: "what is this?".to_string(),
+                    },
+                    ContentPart::ImageUrl {
+                        image_url: ImageUrl {
+                            url: "data:image/png;base64,AAAA".to_string(),
+                            detail: None,
+                        },
+                    },
+                ]),
+                ..Message::new(Role::User, String::new())
+            },
+        ];
+
+        let (system, turns) = translate_messages(&messages);
+        assert_eq!(system.as_deref(), Some("be brief"));
+        assert_eq!(turns.len(), 1);
+        assert_eq!(turns[0]["content"][1]["type"], "image");
+        assert_eq!(turns[0]["content"][1]["source"]["media_type"], "image/png");
+        assert_eq!(turns[0]["content"][1]["source"]["data"], "AAAA");
+    }
+
+    #[test]
+    fn test_translate_messages_tool_round_trip() {
+        let mut assistant = Message::new(Role::Assistant, String::new());
+        assistant.tool_calls = Some(vec![ToolCall::new(
+            "call_1",
+            "execute",
+            &json!({"command": "ls"}),
+        )]);
+        let mut tool = Message::new(Role::Tool, "a.txt".to_string());
+        tool.tool_call_id = Some("call_1".to_string());
+
+        let (_, turns) = translate_messages(&[
+            Message::new(Role::User, "list files".to_string()),
+            assistant,
+            tool,
+        ]);
+
+        assert_eq!(turns.len(), 3);
+        assert_eq!(turns[1]["content"][0]["type"], "tool_use");
+        assert_eq!(turns[1]["content"][0]["input"]["command"], "ls");
+        assert_eq!(turns[2]["role"], "user");
+        assert_eq!(turns[2]["content"][0]["tool_use_id"], "call_1");
+    }
+
+    #[test]
+    fn test_parse_completion() {
+        let value = json!({
+            "id": "msg_1",
+            "model": "claude-sonnet-4",
+            "content": [
+                {"type": "text", "text": "Listing"},
+                {"type": "tool_use", "id": "toolu_1", "name": "execute", "input": {"command": "ls"}}
+            ],
+            "stop_reason": "tool_use",
+            "usage": {"input_tokens": 10, "output_tokens": 5}
+        });
+
+        let completion = parse_completion(&value);
+        assert_eq!(completion.content, "Listing");
+        assert_eq!(completion.finish_reason.as_deref(), Some("tool_calls"));
+        let calls = completion.tool_calls.unwrap();
+        assert_eq!(calls[0].function.name, "execute");
+        assert_eq!(calls[0].arguments()["command"], "ls");
+        assert_eq!(completion.usage.unwrap().total_tokens, 15);
+    }
+
+    #[test]
+    fn test_read_events() {
+        let body = "\
+event: message_start
+data: {\"type\":\"message_start\",\"message\":{\"id\":\"msg_1\",\"model\":\"claude\",\"usage\":{\"input_tokens\":3}}}
+
+event: content_block_delta
+data: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"text_delta\",\"text\":\"Hi\"}}
+
+event: message_delta
+data: {\"type\":\"message_delta\",\"delta\":{\"stop_reason\":\"end_turn\"},\"usage\":{\"output_tokens\":1}}
+
+event: message_stop
+data: {\"type\":\"message_stop\"}
+";
+        let mut deltas = String::new();
+        let completion = read_events(body.as_bytes(), &mut |d| deltas.push_str(d)).unwrap();
+        assert_eq!(deltas, "Hi");
+        assert_eq!(completion.content, "Hi");
+        assert_eq!(completion.finish_reason.as_deref(), Some("stop"));
+        assert_eq!(completion.usage.unwrap().total_tokens, 4);
+    }
+}
@@ -0,0 +1,401 @@
+use std::collections::HashMap;
+use std::env;
+use std::io::{BufRead, BufReader};
+
+use serde_json::{json, Value};
+
+use super::{
+    error_from_response, http_client, request_timeout, split_data_url, stream_error, ChatBackend,
+    Completion, CompletionRequest,
+};
+use crate::chatgpt::message::{ContentPart, Message, MessageContent, ToolCall};
+use crate::chatgpt::response::Usage;
+use crate::chatgpt::role::Role;
+use crate::chatgpt::stream::{parse_sse_line, SseData};
+use crate::error::{CgipError, Result};
+use crate::retry::RetryPolicy;
+
+/// The native Gemini `generateContent` API.
+pub struct GeminiBackend {
+    api_key: String,
+    base_url: String,
+    retry: RetryPolicy,
+}
+
+impl GeminiBackend {
+    pub fn from_env(retry: RetryPolicy) -> Result<Self> {
+        let api_key = env::var("GEMINI_API_KEY")
+            .or_else(|_| env::var("GOOGLE_API_KEY"))
+            .map_err(|_| CgipError::Config("Missing GEMINI_API_KEY environment variable".into()))?;
+        let base_url = env::var("GEMINI_BASE_URL")
+            .unwrap_or_else(|_| "https://generativelanguage.googleapis.com/v1beta".to_string());
+        Ok(GeminiBackend {
+            api_key,
+            base_url,
+            retry,
+        })
+    }
+
+    fn send(&self, body: &Value, model: &str, stream: bool) -> Result<reqwest::blocking::Response> {
+        let timeout = if stream { None } else { request_timeout() };
+        let client = http_client(timeout)?;
+        let response = self.retry.send(|| {
+            client
+                .post(generate_content_url(&self.base_url, model, stream))
+                .header("x-goog-api-key", &self.api_key)
+                .json(body)
+        })?;
+
+        if !response.status().is_success() {
+            return Err(error_from_response(response));
+        }
+        Ok(response)
+    }
+}
+
+pub fn generate_content_url(base_url: &str, model: &str, stream: bool) -> String {
+    let base = base_url.trim_end_matches('/');
+    let model = model.strip_prefix("models/").unwrap_or(model);
+    if stream {
+        format!("{}/models/{}:streamGenerateContent?alt=sse", base, model)
+    } else {
+        format!("{}/models/{}:generateContent", base, model)
+    }
+}
+
+fn content_parts(content: &MessageContent) -> Vec<Value> {
+    let parts = match content {
+        MessageContent::Text(text) => vec![ContentPart::Text { text: text.clone() }],
+        MessageContent::Multi(parts) => parts.clone(),
+    };
+
+    parts
+        .iter()
+        .filter_map(|part| match part {
+            ContentPart::Text { text } if text.is_empty() => None,
+            ContentPart::Text { text } => Some(json!({"text": text})),
+            ContentPart::ImageUrl { image_url } => Some(match split_data_url(&image_url.url) {
+                Some((mime_type, data)) => {
+                    json!({"inlineData": {"mimeType": mime_type, "data": data}})
+                }
+                None => json!({"fileData": {"fileUri": image_url.url}}),
+            }),
+        })
+        .collect()
+}
+
+/// Converts messages into Gemini `contents`, returning the system prompt
+/// separately. Tool results need the function name rather than the call id,
+/// so names are looked up from the preceding assistant tool calls.
+fn translate_messages(messages: &[Message]) -> (Option<String>, Vec<Value>) {
+    let mut system = Vec::new();
+    let mut contents: Vec<(String, Vec<Value>)> = Vec::new();
+    let mut call_names: HashMap<String, String> = HashMap::new();
+
+    for msg in messages {
+        let (role, parts) = match msg.role {
+            Role::System | Role::Developer => {
+                system.push(msg.content.to_string());
+                continue;
+            }
+            Role::Assistant => {
+                let mut parts = content_parts(&msg.content);
+                for call in msg.tool_calls.iter().flatten() {
+                    call_names.insert(call.id.clone(), call.function.name.clone());
+                    parts.push(json!({
+                        "functionCall": {"name": call.function.name, "args": call.arguments()}
+                    }));
+                }
+                ("model", parts)
+            }
+            Role::Tool => {
+                let id = msg.tool_call_id.clone().unwrap_or_default();
+                let name = call_names.get(&id).cloned().unwrap_or(id);
+                (
+                    "user",
+                    vec![json!({
+                        "functionResponse": {
+                            "name": name,
+                            "response": {"content": msg.content.to_string()}
+                        }
+                    })],
+                )
+            }
+            Role::User => ("user", content_parts(&msg.content)),
+        };
+
+        if parts.is_empty() {
+            continue;
+        }
+        match contents.last_mut() {
+            Some((last_role, last_parts)) if last_role == role => last_parts.extend(parts),
+            _ => contents.push((role.to_string(), parts)),
+        }
+    }
+
+    let system = if system.is_empty() {
+        None
+    } else {
+        Some(system.join("\n\n"))
+    };
+    let contents = contents
+        .into_iter()
+        .map(|(role, parts)| json!({"role": role, "parts": parts}))
+        .collect();
+    (system, contents)
+}
+
+fn request_body(request: &CompletionRequest) -> Value {
+    let (system, contents) = translate_messages(request.messages);
+    let mut body = json!({ "contents": contents });
+
+    if let Some(system) = system {
+        body["systemInstruction"] = json!({"parts": [{"text": system}]});
+    }
+    if let Some(max_tokens) = request.max_tokens {
+        body["generationConfig"]["maxOutputTokens"] = json!(max_tokens);
+    }
+    if let Some(temperature) = request.temperature {
+        body["generationConfig"]["temperature"] = json!(temperature);
+    }
+    if request.json {
+        body["generationConfig"]["responseMimeType"] = json!("application/json");
+    }
+
+    let mut tools = Vec::new();
+    if let Some(defs) = request.tools.and_then(|t| t.as_array()) {
+        let declarations: Vec<Value> = defs.iter().map(|tool| tool["function"].clone()).collect();
+        tools.push(json!({"functionDeclarations": declarations}));
+    }
+    if request.web_search {
+        tools.push(json!({"google_search": {}}));
+    }
+    if !tools.is_empty() {
+        body["tools"] = json!(tools);
+    }
+
+    body
+}
+
+fn finish_reason(reason: &str) -> String {
+    match reason {
+        "STOP" => "stop".to_string(),
+        "MAX_TOKENS" => "length".to_string(),
+        other => other.to_lowercase(),
+    }
+}
+
+fn usage(value: &Value) -> Option<Usage> {
+    let metadata = value.get("usageMetadata")?;
+    let prompt_tokens = metadata["promptTokenCount"].as_u64().unwrap_or(0);
+    let completion_tokens = metadata["candidatesTokenCount"].as_u64().unwrap_or(0);
+    Some(Usage {
+        prompt_tokens,
+        completion_tokens,
+        total_tokens: metadata["totalTokenCount"]
+            .as_u64()
+            .unwrap_or(prompt_tokens + completion_tokens),
+    })
+}
+
+fn parse_completion(value: &Value) -> Result<Completion> {
+    let candidate = value["candidates"].get(0).ok_or_else(|| {
+        CgipError::BadResponse(format!("Response contained no candidates: {}", value))
+    })?;
+
+    let mut content = String::new();
+    let mut tool_calls = Vec::new();
+    for part in candidate["content"]["parts"]
+        .as_array()
+        .into_iter()
+        .flatten()
+    {
+        if let Some(text) = part["text"].as_str() {
+            content.push_str(text);
+        }
+        if let Some(call) = part.get("functionCall") {
+            tool_calls.push(ToolCall::new(
+                &format!("call_{}", tool_calls.len()),
+                call["name"].as_str().unwrap_or(""),
+                &call["args"],
+            ));
+        }
+    }
+
+    let finish_reason = if !tool_calls.is_empty() {
+        Some("tool_calls".to_string())
+    } else {
+        candidate["finishReason"].as_str().map(finish_reason)
+    };
+
+    Ok(Completion {
+        id: value["responseId"].as_str().map(|s| s.to_string()),
+        model: value["modelVersion"].as_str().map(|s| s.to_string()),
+        content,
+        tool_calls: (!tool_calls.is_empty()).then_some(tool_calls),
+        finish_reason,
+        usage: usage(value),
+    })
+}
+
+/// Reads a `streamGenerateContent?alt=sse` stream. Every event is a partial
+/// `GenerateContentResponse`.
+fn read_events<R: BufRead>(reader: R, on_delta: &mut dyn FnMut(&str)) -> Result<Completion> {
+    let mut completion = Completion::default();
+
+    for line in reader.lines() {
+        let line = line.map_err(stream_error)?;
+        let event: Value = match parse_sse_line(&line) {
+            Some(SseData::Json(data)) => serde_json::from_str(data).unwrap_or_default(),
+            Some(SseData::Done) => break,
+            None => continue,
+        };
+        if event.get("candidates").is_none() {
+            continue;
+        }
+
+        let chunk = parse_completion(&event)?;
+        if !chunk.content.is_empty() {
+            on_delta(&chunk.content);
+            completion.content.push_str(&chunk.content);
+        }
+        completion.id = chunk.id.or(completion.id);
+        completion.model = chunk.model.or(completion.model);
+        completion.finish_reason = chunk.finish_reason.or(completion.finish_reason);
+        completion.usage = chunk.usage.or(completion.usage);
+    }
+
+    Ok(completion)
+}
+
+impl ChatBackend for GeminiBackend {
+    fn complete(&self, request: &CompletionRequest) -> Result<Completion> {
+        let response = self.send(&request_body(request), request.model, false)?;
+        let value: Value = response.json()?;
+        parse_completion(&value)
+    }
+
+    fn complete_streaming(
+        &self,
+        request: &CompletionRequest,
+        on_delta: &mut dyn FnMut(&str),
+    ) -> Result<Completion> {
+        let response = self.send(&request_body(request), request.model, true)?;
+        read_events(BufReader::new(response), on_delta)
+    }
+
+    fn list_models(&self) -> Result<Vec<String>> {
+        let client = http_client(request_timeout())?;
+        let response = self.retry.send(|| {
+            client
+                .get(format!("{}/models", self.base_url.trim_end_matches('/')))
+                .header("x-goog-api-key", &self.api_key)
+        })?;
+        if !response.status().is_success() {
+            return Err(error_from_response(response));
+        }
+
+        let value: Value = response.json()?;
+        Ok(value["models"]
+            .as_array()
+            .map(|arr| {
+                arr.iter()
+                    .filter_map(|m| m["name"].as_str())
+                    .map(|name| name.strip_prefix("models/").unwrap_or(name).to_string())
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generation_config_merges_options() {
+        let request = CompletionRequest {
+            model: "gemini-pro",
+            messages: &[Message::new(Role::User, "colors?".to_string())],
+            max_tokens: Some(100),
+            web_search: false,
+            temperature: Some(0.5),
+            json: true,
+            tools: None,
+        };
+        let body = request_body(&request);
+        assert_eq!(
+            body["generationConfig"],
+            json!({
+                "maxOutputTokens": 100,
+                "temperature": 0.5,
+                "responseMimeType": "application/json",
+            })
+        );
+    }
+
+    #[test]
+    fn test_generate_content_url() {
+        assert_eq!(
+            generate_content_url(
+                "https://generativelanguage.googleapis.com/v1beta",
+                "gemini-pro",
+                false
+            ),
+            "https://generativelanguage.googleapis.com/v1beta/models/gemini-pro:generateContent"
+        );
+        assert_eq!(
+            generate_content_url("https://generativelanguage.googleapis.com/v1beta/", "models/gemini-pro", true),
+            "https://generativelanguage.googleapis.com/v1beta/models/gemini-pro:streamGenerateContent?alt=sse"
+        );
+    }
+
+    #[test]
+    fn test_translate_messages() {
+        let mut assistant = Message::new(Role::Assistant, String::new());
+        assistant.tool_calls = Some(vec![ToolCall::new(
+            "call_0",
+            "execute",
+            &json!({"command": "date"}),
+        )]);
+        let mut tool = Message::new(Role::Tool, "Mon Jan 1".to_string());
+        tool.tool_call_id = Some("call_0".to_string());
+
+        let (system, contents) = translate_messages(&[
+            Message::new(Role::System, "be brief".to_string()),
+            Message::new(Role::User, "what day is it?".to_string()),
+            assistant,
+            tool,
+        ]);
+
+        assert_eq!(system.as_deref(), Some("be brief"));
+        assert_eq!(contents.len(), 3);
+        assert_eq!(contents[1]["role"], "model");
+        assert_eq!(
+            contents[1]["parts"][0]["functionCall"]["args"]["command"],
+            "date"
+        );
+        assert_eq!(
+            contents[2]["parts"][0]["functionResponse"]["name"],
+            "execute"
+        );
+    }
+
+    #[test]
+    fn test_parse_completion() {
+        let value = json!({
+            "candidates": [{
+                "content": {"role": "model", "parts": [{"text": "Hello"}, {"text": " there"}]},
+                "finishReason": "STOP"
+            }],
+            "usageMetadata": {"promptTokenCount": 4, "candidatesTokenCount": 2, "totalTokenCount": 6},
+            "modelVersion": "gemini-2.0-flash"
+        });
+
+        let completion = parse_completion(&value).unwrap();
+        assert_eq!(completion.content, "Hello there");
+        assert_eq!(completion.finish_reason.as_deref(), Some("stop"));
+        assert_eq!(completion.model.as_deref(), Some("gemini-2.0-flash"));
+        assert_eq!(completion.usage.unwrap().total_tokens, 6);
+    }
+}
@@ -0,0 +1,176 @@
+use std::{fmt, str::FromStr, time::Duration};
+
+use serde_json::Value;
+
+use crate::chatgpt::message::{Message, ToolCall};
+use crate::chatgpt::response::Usage;
+use crate::config_manager::AppConfig;
+use crate::error::{CgipError, Result};
+use crate::retry::RetryPolicy;
+
+pub mod anthropic;
+pub mod gemini;
+pub mod ollama;
+pub mod openai;
+
+pub use anthropic::AnthropicBackend;
+pub use gemini::GeminiBackend;
+pub use ollama::OllamaBackend;
+pub use openai::OpenAiBackend;
+
+/// Everything a backend needs to produce one completion.
+pub struct CompletionRequest<'a> {
+    pub model: &'a str,
+    pub messages: &'a [Message],
+    pub max_tokens: Option<u32>,
+    pub web_search: bool,
+    pub temperature: Option<f32>,
+    /// Ask for the reply as a JSON object.
+    pub json: bool,
+    /// Tool definitions in the OpenAI `tools` format. Backends translate these
+    /// into their own representation.
+    pub tools: Option<&'a Value>,
+}
+
+/// A provider independent completion result.
+#[derive(Debug, Default)]
+pub struct Completion {
+    pub id: Option<String>,
+    pub model: Option<String>,
+    pub content: String,
+    pub tool_calls: Option<Vec<ToolCall>>,
+    /// Normalised to the OpenAI values: `stop`, `length` or `tool_calls`.
+    pub finish_reason: Option<String>,
+    pub usage: Option<Usage>,
+}
+
+/// A chat provider wire format. Each backend translates `Message` and
+/// `ContentPart` to and from its own API.
+pub trait ChatBackend {
+    fn complete(&self, request: &CompletionRequest) -> Result<Completion>;
+
+    /// Streams the completion, calling `on_delta` with each piece of text as it
+    /// arrives. The returned completion holds the assembled text.
+    fn complete_streaming(
+        &self,
+        request: &CompletionRequest,
+        on_delta: &mut dyn FnMut(&str),
+    ) -> Result<Completion>;
+
+    fn list_models(&self) -> Result<Vec<String>>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Provider {
+    OpenAi,
+    Anthropic,
+    Ollama,
+    Gemini,
+}
+
+impl FromStr for Provider {
+    type Err = CgipError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "openai" => Ok(Provider::OpenAi),
+            "anthropic" => Ok(Provider::Anthropic),
+            "ollama" => Ok(Provider::Ollama),
+            "gemini" => Ok(Provider::Gemini),
+            _ => Err(CgipError::Config(format!(
+                "Unknown provider '{}'. Expected one of: openai, anthropic, ollama, gemini",
+                s
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for Provider {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Provider::OpenAi => write!(f, "openai"),
+            Provider::Anthropic => write!(f, "anthropic"),
+            Provider::Ollama => write!(f, "ollama"),
+            Provider::Gemini => write!(f, "gemini"),
+        }
+    }
+}
+
+/// Creates the backend for the provider selected in the config.
+pub fn from_config(config: &AppConfig) -> Result<Box<dyn ChatBackend>> {
+    let provider = Provider::from_str(&config.provider)?;
+    let retry = RetryPolicy::from_config(config);
+    let backend: Box<dyn ChatBackend> = match provider {
+        Provider::OpenAi => Box::new(OpenAiBackend::from_env(retry)?),
+        Provider::Anthropic => Box::new(AnthropicBackend::from_env(retry)?),
+        Provider::Ollama => Box::new(OllamaBackend::from_env(retry)),
+        Provider::Gemini => Box::new(GeminiBackend::from_env(retry)?),
+    };
+    Ok(backend)
+}
+
+/// Builds the blocking HTTP client shared by all backends. A `None` timeout
+/// is used for streams, which can legitimately run for a long time.
+fn http_client(timeout: Option<Duration>) -> Result<reqwest::blocking::Client> {
+    Ok(reqwest::blocking::Client::builder()
+        .danger_accept_invalid_certs(true)
+        .timeout(timeout)
+        .build()?)
+}
+
+fn request_timeout() -> Option<Duration> {
+    Some(Duration::from_secs(60))
+}
+
+/// Turns a non-success response into an error carrying the provider's
+/// message. OpenAI, Anthropic and Gemini all nest it at `error.message`,
+/// while Ollama returns `error` as a plain string.
+fn error_from_response(response: reqwest::blocking::Response) -> CgipError {
+    let status = response.status();
+    let body = response.text().unwrap_or_default();
+    let value: Value = serde_json::from_str(&body).unwrap_or_default();
+    let message = value["error"]["message"]
+        .as_str()
+        .or_else(|| value["error"].as_str())
+        .unwrap_or(body.trim());
+    CgipError::from_status(status.as_u16(), message.to_string())
+}
+
+/// Splits a `data:<mime>;base64,<data>` URL into its mime type and payload.
+fn split_data_url(url: &str) -> Option<(&str, &str)> {
+    let rest = url.strip_prefix("data:")?;
+    let (mime, data) = rest.split_once(";base64,")?;
+    Some((mime, data))
+}
+
+/// Stream bodies fail with `io::Error`s, but these are network failures
+/// rather than local file problems.
+fn stream_error(e: std::io::Error) -> CgipError {
+    CgipError::Network(e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_provider_from_str() {
+        assert_eq!(Provider::from_str("openai").unwrap(), Provider::OpenAi);
+        assert_eq!(
+            Provider::from_str("Anthropic").unwrap(),
+            Provider::Anthropic
+        );
+        assert_eq!(Provider::from_str("ollama").unwrap(), Provider::Ollama);
+        assert_eq!(Provider::from_str("gemini").unwrap(), Provider::Gemini);
+        assert!(Provider::from_str("bard").is_err());
+    }
+
+    #[test]
+    fn test_split_data_url() {
+        assert_eq!(
+            split_data_url("data:image/png;base64,AAAA"),
+            Some(("image/png", "AAAA"))
+        );
+        assert_eq!(split_data_url("https://example.com/cat.png"), None);
+    }
+}
@@ -0,0 +1,295 @@
+use std::env;
+use std::io::{BufRead, BufReader};
+
+use serde_json::{json, Value};
+
+use super::{
+    error_from_response, http_client, request_timeout, split_data_url, stream_error, ChatBackend,
+    Completion, CompletionRequest,
+};
+use crate::chatgpt::message::{ContentPart, Message, MessageContent, ToolCall};
+use crate::chatgpt::response::Usage;
+use crate::chatgpt::role::Role;
+use crate::error::{CgipError, Result};
+use crate::retry::RetryPolicy;
+
+/// The native Ollama `/api/chat` endpoint.
+pub struct OllamaBackend {
+    host: String,
+    retry: RetryPolicy,
+}
+
+impl OllamaBackend {
+    pub fn from_env(retry: RetryPolicy) -> Self {
+        let host = env::var("OLLAMA_HOST").unwrap_or_else(|_| "http://localhost:11434".to_string());
+        // OLLAMA_HOST is commonly set without a scheme, e.g. `0.0.0.0:11434`
+        let host = if host.contains("://") {
+            host
+        } else {
+            format!("http://{}", host)
+        };
+        OllamaBackend {
+            host: host.trim_end_matches('/').to_string(),
+            retry,
+        }
+    }
+
+    fn send(&self, body: &Value, stream: bool) -> Result<reqwest::blocking::Response> {
+        let timeout = if stream { None } else { request_timeout() };
+        let client = http_client(timeout)?;
+        let response = self
+            .retry
+            .send(|| client.post(format!("{}/api/chat", self.host)).json(body))?;
+
+        if !response.status().is_success() {
+            return Err(error_from_response(response));
+        }
+        Ok(response)
+    }
+}
+
+fn translate_message(msg: &Message) -> Value {
+    let mut images = Vec::new();
+    let content = match &msg.content {
+        MessageContent::Text(text) => text.clone(),
+        MessageContent::Multi(parts) => {
+            let mut text = Vec::new();
+            for part in parts {
+                match part {
+                    ContentPart::Text { text: t } => text.push(t.as_str()),
+                    // Ollama only accepts inline base64 images
+                    ContentPart::ImageUrl { image_url } => match split_data_url(&image_url.url) {
+                        Some((_, data)) => images.push(data.to_string()),
+                        None => text.push(image_url.url.as_str()),
+                    },
+                }
+            }
+            text.join(" ")
+        }
+    };
+
+    let role = match msg.role {
+        Role::Developer => "system",
+        role => role.as_str(),
+    };
+    let mut value = json!({"role": role, "content": content});
+    if !images.is_empty() {
+        value["images"] = json!(images);
+    }
+    if let Some(calls) = &msg.tool_calls {
+        let calls: Vec<Value> = calls
+            .iter()
+            .map(|call| {
+                json!({
+                    "function": {
+                        "name": call.function.name,
+                        "arguments": call.arguments(),
+                    }
+                })
+            })
+            .collect();
+        value["tool_calls"] = json!(calls);
+    }
+    value
+}
+
+fn request_body(request: &CompletionRequest, stream: bool) -> Value {
+    let messages: Vec<Value> = request.messages.iter().map(translate_message).collect();
+    let mut body = json!({
+        "model": request.model,
+        "messages": messages,
+        "stream": stream,
+    });
+    if let Some(max_tokens) = request.max_tokens {
+        body["options"]["num_predict"] = json!(max_tokens);
+    }
+    if let Some(temperature) = request.temperature {
+        body["options"]["temperature"] = json!(temperature);
+    }
+    if request.json {
+        body["format"] = json!("json");
+    }
+    if let Some(tools) = request.tools {
+        // Ollama accepts tool definitions in the OpenAI format
+        body["tools"] = tools.clone();
+    }
+    body
+}
+
+fn usage(value: &Value) -> Option<Usage> {
+    let prompt_tokens = value["prompt_eval_count"].as_u64()?;
+    let completion_tokens = value["eval_count"].as_u64().unwrap_or(0);
+    Some(Usage {
+        prompt_tokens,
+        completion_tokens,
+        total_tokens: prompt_tokens + completion_tokens,
+    })
+}
+
+fn parse_completion(value: &Value) -> Completion {
+    let message = &value["message"];
+    let tool_calls: Vec<ToolCall> = message["tool_calls"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .enumerate()
+        .map(|(i, call)| {
+            ToolCall::new(
+                &format!("call_{}", i),
+                call["function"]["name"].as_str().unwrap_or(""),
+                &call["function"]["arguments"],
+            )
+        })
+        .collect();
+
+    let finish_reason = if !tool_calls.is_empty() {
+        Some("tool_calls".to_string())
+    } else {
+        value["done_reason"].as_str().map(|s| s.to_string())
+    };
+
+    Completion {
+        id: None,
+        model: value["model"].as_str().map(|s| s.to_string()),
+        content: message["content"].as_str().unwrap_or("").to_string(),
+        tool_calls: (!tool_calls.is_empty()).then_some(tool_calls),
+        finish_reason,
+        usage: usage(value),
+    }
+}
+
+/// Reads Ollama's newline delimited JSON stream.
+fn read_lines<R: BufRead>(reader: R, on_delta: &mut dyn FnMut(&str)) -> Result<Completion> {
+    let mut completion = Completion::default();
+
+    for line in reader.lines() {
+        let line = line.map_err(stream_error)?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let value: Value = serde_json::from_str(&line)?;
+        if let Some(error) = value["error"].as_str() {
+            return Err(CgipError::BadResponse(error.to_string()));
+        }
+
+        if let Some(text) = value["message"]["content"].as_str() {
+            if !text.is_empty() {
+                on_delta(text);
+                completion.content.push_str(text);
+            }
+        }
+
+        if value["done"].as_bool().unwrap_or(false) {
+            completion.model = value["model"].as_str().map(|s| s.to_string());
+            completion.finish_reason = value["done_reason"].as_str().map(|s| s.to_string());
+            completion.usage = usage(&value);
+            break;
+        }
+    }
+
+    Ok(completion)
+}
+
+impl ChatBackend for OllamaBackend {
+    fn complete(&self, request: &CompletionRequest) -> Result<Completion> {
+        if request.web_search {
+            eprintln!("Warning: web search is not supported by the ollama provider");
+        }
+        let response = self.send(&request_body(request, false), false)?;
+        let value: Value = response.json()?;
+        Ok(parse_completion(&value))
+    }
+
+    fn complete_streaming(
+        &self,
+        request: &CompletionRequest,
+        on_delta: &mut dyn FnMut(&str),
+    ) -> Result<Completion> {
+        if request.web_search {
+            eprintln!("Warning: web search is not supported by the ollama provider");
+        }
+        let response = self.send(&request_body(request, true), true)?;
+        read_lines(BufReader::new(response), on_delta)
+    }
+
+    fn list_models(&self) -> Result<Vec<String>> {
+        let client = http_client(request_timeout())?;
+        let response = self
+            .retry
+            .send(|| client.get(format!("{}/api/tags", self.host)))?;
+        if !response.status().is_success() {
+            return Err(error_from_response(response));
+        }
+
+        let value: Value = response.json()?;
+        Ok(value["models"]
+            .as_array()
+            .map(|arr| {
+                arr.iter()
+                    .filter_map(|m| m["name"].as_str().map(|s| s.to_string()))
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chatgpt::message::ImageUrl;
+
+    #[test]
+    fn test_translate_message_with_image() {
+        let msg = Message {
+            content: MessageContent::Multi(vec![
+                ContentPart::Text {
+                    text: "describe".to_string(),
+                },
+                ContentPart::ImageUrl {
+                    image_url: ImageUrl {
+                        url: "data:image/jpeg;base64,BBBB".to_string(),
+                        detail: None,
+                    },
+                },
+            ]),
+            ..Message::new(Role::User, String::new())
+        };
+
+        let value = translate_message(&msg);
+        assert_eq!(value["content"], "describe");
+        assert_eq!(value["images"][0], "BBBB");
+    }
+
+    #[test]
+    fn test_parse_completion_with_tool_calls() {
+        let value = json!({
+            "model": "llama3.1",
+            "message": {
+                "role": "assistant",
+                "content": "",
+                "tool_calls": [{"function": {"name": "execute", "arguments": {"command": "date"}}}]
+            },
+            "done": true,
+            "done_reason": "stop",
+            "prompt_eval_count": 20,
+            "eval_count": 4
+        });
+
+        let completion = parse_completion(&value);
+        assert_eq!(completion.finish_reason.as_deref(), Some("tool_calls"));
+        let calls = completion.tool_calls.unwrap();
+        assert_eq!(calls[0].arguments()["command"], "date");
+        assert_eq!(completion.usage.unwrap().total_tokens, 24);
+    }
+
+    #[test]
+    fn test_read_lines() {
+        let body = "{\"model\":\"llama3\",\"message\":{\"role\":\"assistant\",\"content\":\"He\"},\"done\":false}\n\
+{\"model\":\"llama3\",\"message\":{\"role\":\"assistant\",\"content\":\"y\"},\"done\":false}\n\
+{\"model\":\"llama3\",\"message\":{\"role\":\"assistant\",\"content\":\"\"},\"done\":true,\"done_reason\":\"stop\",\"prompt_eval_count\":2,\"eval_count\":2}\n";
+        let completion = read_lines(body.as_bytes(), &mut |_| {}).unwrap();
+        assert_eq!(completion.content, "Hey");
+        assert_eq!(completion.model.as_deref(), Some("llama3"));
+        assert_eq!(completion.finish_reason.as_deref(), Some("stop"));
+    }
+}
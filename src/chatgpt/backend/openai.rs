@@ -0,0 +1,198 @@
+use std::env;
+use std::io::BufReader;
+
+use reqwest::header;
+
+use super::{
+    error_from_response, http_client, request_timeout, stream_error, ChatBackend, Completion,
+    CompletionRequest,
+};
+use crate::chatgpt::request::ChatRequest;
+use crate::chatgpt::response::parse_response;
+use crate::chatgpt::stream::read_stream;
+use crate::chatgpt::Message;
+use crate::chatgpt::{get_completions_url, get_models_url};
+use crate::error::{CgipError, Result};
+use crate::retry::RetryPolicy;
+
+/// The OpenAI chat completions API, also spoken by most compatible servers
+/// reachable through `OPENAI_BASE_URL`.
+pub struct OpenAiBackend {
+    api_key: String,
+    base_url: String,
+    retry: RetryPolicy,
+}
+
+impl OpenAiBackend {
+    pub fn from_env(retry: RetryPolicy) -> Result<Self> {
+        let api_key = env::var("OPENAI_API_KEY")
+            .map_err(|_| CgipError::Config("Missing OPENAI_API_KEY environment variable".into()))?;
+        let base_url =
+            env::var("OPENAI_BASE_URL").unwrap_or_else(|_| "https://api.openai.com".to_string());
+        Ok(OpenAiBackend {
+            api_key,
+            base_url,
+            retry,
+        })
+    }
+
+    fn headers(&self) -> Result<header::HeaderMap> {
+        let mut headers = header::HeaderMap::new();
+        headers.insert(
+            header::CONTENT_TYPE,
+            header::HeaderValue::from_static("application/json"),
+        );
+        let auth_header = header::HeaderValue::from_str(&format!("Bearer {}", self.api_key))
+            .map_err(|e| CgipError::Config(format!("Invalid OPENAI_API_KEY: {}", e)))?;
+        headers.insert(header::AUTHORIZATION, auth_header);
+        Ok(headers)
+    }
+
+    fn chat_request(request: &CompletionRequest, stream: bool) -> ChatRequest {
+        let model = if request.web_search && request.model.starts_with("gpt") {
+            "gpt-4o-search-preview".to_string()
+        } else {
+            request.model.to_string()
+        };
+
+        ChatRequest {
+            model,
+            messages: request.messages.iter().map(Message::without_meta).collect(),
+            web_search_options: request.web_search.then(|| serde_json::json!({})),
+            max_tokens: request.max_tokens,
+            temperature: request.temperature,
+            response_format: request
+                .json
+                .then(|| serde_json::json!({"type": "json_object"})),
+            stream: stream.then_some(true),
+            // Without this a stream reports no token usage
+            stream_options: stream.then(|| serde_json::json!({"include_usage": true})),
+            tools: request.tools.cloned(),
+            tool_choice: request.tools.map(|_| "auto".to_string()),
+        }
+    }
+
+    fn send(
+        &self,
+        chat_request: &ChatRequest,
+        stream: bool,
+    ) -> Result<reqwest::blocking::Response> {
+        let timeout = if stream { None } else { request_timeout() };
+        let client = http_client(timeout)?;
+        let headers = self.headers()?;
+        let response = self.retry.send(|| {
+            client
+                .post(get_completions_url(&self.base_url))
+                .headers(headers.clone())
+                .json(chat_request)
+        })?;
+
+        if !response.status().is_success() {
+            return Err(error_from_response(response));
+        }
+        Ok(response)
+    }
+}
+
+impl ChatBackend for OpenAiBackend {
+    fn complete(&self, request: &CompletionRequest) -> Result<Completion> {
+        let response = self.send(&Self::chat_request(request, false), false)?;
+        let response_text = response.text()?;
+        let response_object = parse_response(&response_text).map_err(|e| {
+            CgipError::BadResponse(format!(
+                "Error while parsing response object: {}\n{}",
+                e, response_text
+            ))
+        })?;
+
+        let choice = response_object
+            .choices
+            .into_iter()
+            .next()
+            .ok_or_else(|| CgipError::BadResponse("Response contained no choices".into()))?;
+
+        Ok(Completion {
+            id: response_object.id,
+            model: Some(response_object.model),
+            content: choice.message.content.unwrap_or_default(),
+            tool_calls: choice.message.tool_calls,
+            finish_reason: choice.finish_reason,
+            usage: response_object.usage,
+        })
+    }
+
+    fn complete_streaming(
+        &self,
+        request: &CompletionRequest,
+        on_delta: &mut dyn FnMut(&str),
+    ) -> Result<Completion> {
+        let response = self.send(&Self::chat_request(request, true), true)?;
+        read_stream(BufReader::new(response), on_delta).map_err(stream_error)
+    }
+
+    fn list_models(&self) -> Result<Vec<String>> {
+        let client = http_client(request_timeout())?;
+        let headers = self.headers()?;
+        let response = self.retry.send(|| {
+            client
+                .get(get_models_url(&self.base_url))
+                .headers(headers.clone())
+        })?;
+        if !response.status().is_success() {
+            return Err(error_from_response(response));
+        }
+
+        let value: serde_json::Value = response.json()?;
+        Ok(value["data"]
+            .as_array()
+            .map(|arr| {
+                arr.iter()
+                    .filter_map(|m| m["id"].as_str().map(|s| s.to_string()))
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_search_swaps_gpt_model() {
+        let request = CompletionRequest {
+            model: "gpt-4o",
+            messages: &[],
+            max_tokens: None,
+            web_search: true,
+            temperature: None,
+            json: false,
+            tools: None,
+        };
+        let chat_request = OpenAiBackend::chat_request(&request, false);
+        assert_eq!(chat_request.model, "gpt-4o-search-preview");
+        assert!(chat_request.web_search_options.is_some());
+        assert!(chat_request.stream.is_none());
+        assert!(chat_request.stream_options.is_none());
+    }
+
+    #[test]
+    fn test_search_keeps_non_gpt_model() {
+        let request = CompletionRequest {
+            model: "llama3",
+            messages: &[],
+            max_tokens: None,
+            web_search: true,
+            temperature: None,
+            json: false,
+            tools: None,
+        };
+        let chat_request = OpenAiBackend::chat_request(&request, true);
+        assert_eq!(chat_request.model, "llama3");
+        assert_eq!(chat_request.stream, Some(true));
+        assert_eq!(
+            chat_request.stream_options,
+            Some(serde_json::json!({"include_usage": true}))
+        );
+    }
+}
@@ -1,18 +1,32 @@
 use dirs::config_dir;
-use reqwest::header;
 use serde_json;
 use serde_yaml;
+use std::borrow::Cow;
 use std::env;
+use std::path::PathBuf;
 
+use crate::chatgpt::backend::{self, Completion, CompletionRequest};
 use crate::chatgpt::message::{ContentPart, ImageUrl, Message, MessageContent};
-use crate::chatgpt::request::ChatRequest;
-use crate::chatgpt::response::{parse_error_response, parse_response};
+use crate::chatgpt::response::Usage;
 use crate::chatgpt::role::Role;
 use crate::config_manager::ConfigManager;
+use crate::directives::RequestOptions;
+use crate::error::{CgipError, Result};
 
 pub struct GptClient {
     pub config_manager: ConfigManager,
     pub messages: Vec<Message>,
+    /// Token usage reported for the most recent completion.
+    pub last_usage: Option<Usage>,
+    /// Why the most recent completion stopped, such as `stop` or `length`.
+    pub last_finish_reason: Option<String>,
+    /// The model the API reported for the most recent completion, which can
+    /// be more specific than the one asked for.
+    pub last_model: Option<String>,
+    /// The provider's id for the most recent completion.
+    pub last_id: Option<String>,
+    /// Settings for the next requests, such as those set by directives.
+    pub options: RequestOptions,
 }
 
 fn get_system_prompt(jarjar: bool) -> String {
@@ -28,54 +42,46 @@ fn get_system_prompt(jarjar: bool) -> String {
     }
 }
 
-impl GptClient {
-    pub fn new_with_system_prompt(prompt: String) -> Self {
-        let config_directory = config_dir()
-            .expect("Failed to find config directory")
-            .join("cgip");
+/// The directory holding cgip's config file.
+fn config_directory() -> Result<PathBuf> {
+    config_dir()
+        .map(|dir| dir.join("cgip"))
+        .ok_or_else(|| CgipError::Config("Could not find the config directory".to_string()))
+}
 
-        let config_manager = ConfigManager::new(config_directory);
+impl GptClient {
+    pub fn new_with_system_prompt(prompt: String) -> Result<Self> {
+        let config_manager = ConfigManager::new(config_directory()?)?;
 
-        GptClient {
+        Ok(GptClient {
             config_manager,
-            messages: vec![Message {
-                role: Role::System.to_string().to_lowercase(),
-                name: None,
-                tool_call_id: None,
-                tool_calls: None,
-                content: MessageContent::Text(prompt),
-            }],
-        }
+            last_usage: None,
+            last_finish_reason: None,
+            last_model: None,
+            last_id: None,
+            options: RequestOptions::default(),
+            messages: vec![Message::new(Role::System, prompt)],
+        })
     }
 
-    pub fn new(jarjar: bool) -> Self {
-        let config_directory = config_dir()
-            .expect("Failed to find config directory")
-            .join("cgip");
-
-        let config_manager = ConfigManager::new(config_directory);
+    pub fn new(jarjar: bool) -> Result<Self> {
+        let config_manager = ConfigManager::new(config_directory()?)?;
         let system_prompt = get_system_prompt(jarjar);
 
-        GptClient {
+        Ok(GptClient {
             config_manager,
-            messages: vec![Message {
-                role: Role::System.to_string().to_lowercase(),
-                name: None,
-                tool_call_id: None,
-                tool_calls: None,
-                content: MessageContent::Text(system_prompt.clone()),
-            }],
-        }
+            last_usage: None,
+            last_finish_reason: None,
+            last_model: None,
+            last_id: None,
+            options: RequestOptions::default(),
+            messages: vec![Message::new(Role::System, system_prompt)],
+        })
     }
 
     pub fn add_message(&mut self, role: Role, text: String) -> &mut Self {
-        self.messages.push(Message {
-            role: role.to_string(),
-            name: None,
-            tool_call_id: None,
-            tool_calls: None,
-            content: MessageContent::Text(text.trim().to_string()),
-        });
+        self.messages
+            .push(Message::new(role, text.trim().to_string()));
         self
     }
 
@@ -83,7 +89,7 @@ impl GptClient {
         &mut self,
         role: Role,
         text: Option<String>,
-        image_url: String,
+        image_urls: Vec<String>,
     ) -> &mut Self {
         let mut content_parts = Vec::new();
 
@@ -91,23 +97,19 @@ impl GptClient {
             content_parts.push(ContentPart::Text { text });
         }
 
-        content_parts.push(ContentPart::ImageUrl {
-            image_url: ImageUrl { url: image_url },
-        });
+        content_parts.extend(image_urls.into_iter().map(|url| ContentPart::ImageUrl {
+            image_url: ImageUrl { url, detail: None },
+        }));
 
         self.messages.push(Message {
-            role: role.to_string(),
-            name: None,
-            tool_call_id: None,
-            tool_calls: None,
             content: MessageContent::Multi(content_parts),
+            ..Message::new(role, String::new())
         });
         self
     }
 
-    pub fn complete_with_max_tokens(&mut self, max_tokens: Option<u32>) -> String {
-        // if the text of the last message is ping just return pong
-        let last_content_text = match &self.messages.last().unwrap().content {
+    fn last_message_text(&self) -> String {
+        match &self.messages.last().unwrap().content {
             MessageContent::Text(text) => text.clone(),
             MessageContent::Multi(parts) => parts
                 .iter()
@@ -117,142 +119,95 @@ impl GptClient {
                 })
                 .collect::<Vec<_>>()
                 .join(" "),
-        };
-
-        if last_content_text.to_lowercase().trim() == "ping" {
-            self.add_message(Role::Assistant, "pong".to_string());
-            return "pong".to_string();
         }
+    }
 
-        // Check if the last user message starts with "/search"
-        let mut use_search = false;
-        if let Some(last_message) = self.messages.last_mut() {
-            if last_message.role == "user" {
-                if let MessageContent::Text(ref mut content) = last_message.content {
-                    let trimmed_content = content.trim();
-                    if trimmed_content.starts_with("/search") {
-                        // Remove "/search" prefix from the message and trim whitespace
-                        *content = trimmed_content
-                            .strip_prefix("/search")
-                            .unwrap()
-                            .trim()
-                            .to_string();
-                        use_search = true;
-                    }
-                }
-            }
-        }
-
-        // Retrieve the API key from the environment variable
-        let api_key =
-            env::var("OPENAI_API_KEY").expect("Missing OPENAI_API_KEY environment variable");
-
-        let client = reqwest::blocking::Client::builder()
-            .danger_accept_invalid_certs(true)
-            .build()
-            .expect("Failed to build client");
-
-        let url =
-            env::var("OPENAI_BASE_URL").unwrap_or_else(|_| "https://api.openai.com".to_string());
-        let url = super::get_completions_url(&url);
-
-        let mut headers = header::HeaderMap::new();
-        headers.insert(
-            header::CONTENT_TYPE,
-            header::HeaderValue::from_static("application/json"),
-        );
-
-        let auth_header = match header::HeaderValue::from_str(&format!("Bearer {}", api_key)) {
-            Ok(header) => header,
-            Err(e) => panic!("Error while assigning auth header: {}", e),
-        };
-        headers.insert(header::AUTHORIZATION, auth_header);
-
-        let model = if use_search && self.config_manager.config.model.starts_with("gpt") {
-            "gpt-4o-search-preview".to_string()
-        } else {
-            self.config_manager.config.model.clone()
-        };
+    /// The model requests are sent to, which a `/model` directive can
+    /// override.
+    pub fn model(&self) -> &str {
+        self.options
+            .model
+            .as_deref()
+            .unwrap_or(&self.config_manager.config.model)
+    }
 
-        let web_search_options = if use_search {
-            Some(serde_json::json!({}))
-        } else {
-            None
-        };
+    /// The messages as sent, with the system prompt replaced when a
+    /// `/system` directive asks for it.
+    pub fn request_messages(&self) -> Cow<'_, [Message]> {
+        let system = match &self.options.system {
+            Some(system) => system,
+            None => return Cow::Borrowed(&self.messages),
+        };
+        let prompt = Message::new(Role::System, system.clone());
+        let mut messages = self.messages.clone();
+        match messages.first_mut() {
+            Some(msg) if msg.role == Role::System => *msg = prompt,
+            _ => messages.insert(0, prompt),
+        }
+        Cow::Owned(messages)
+    }
 
-        let chat_request = ChatRequest {
-            model,
-            messages: self.messages.clone(),
-            web_search_options,
+    fn complete_request(
+        &mut self,
+        max_tokens: Option<u32>,
+        tools: Option<&serde_json::Value>,
+        on_delta: Option<&mut dyn FnMut(&str)>,
+    ) -> Result<Completion> {
+        let backend = backend::from_config(&self.config_manager.config)?;
+        let messages = self.request_messages();
+        let request = CompletionRequest {
+            model: self.model(),
+            messages: &messages,
             max_tokens,
-        };
-
-        let request_body = match serde_json::to_string(&chat_request) {
-            Ok(body) => body,
-            Err(e) => panic!("Error while serializing request body: {}", e),
-        };
-
-        let response = client
-            .post(url)
-            .headers(headers)
-            .body(request_body)
-            .timeout(std::time::Duration::from_secs(60))
-            .send();
-
-        let response = match response {
-            Ok(response) => response.text(),
-            Err(e) => {
-                if e.is_timeout() {
-                    eprintln!("The request timed out.");
-                } else if e.is_connect() {
-                    eprintln!("Failed to connect to the server: {}", e);
-                } else if e.is_status() {
-                    if let Some(status) = e.status() {
-                        eprintln!("Received HTTP status code: {}", status);
-                    }
-                }
-
-                if let Some(url) = e.url() {
-                    eprintln!("URL: {}", url);
-                }
+            web_search: self.options.web_search,
+            temperature: self.options.temperature,
+            json: self.options.json,
+            tools,
+        };
+
+        let completion = match on_delta {
+            Some(on_delta) => backend.complete_streaming(&request, on_delta),
+            None => backend.complete(&request),
+        }?;
+        self.last_usage = completion.usage.clone();
+        self.last_finish_reason = completion.finish_reason.clone();
+        self.last_model = completion.model.clone();
+        self.last_id = completion.id.clone();
+        Ok(completion)
+    }
 
-                return "".to_string();
-            }
-        };
+    pub fn complete_with_max_tokens(&mut self, max_tokens: Option<u32>) -> Result<String> {
+        // if the text of the last message is ping just return pong
+        if self.last_message_text().to_lowercase().trim() == "ping" {
+            self.add_message(Role::Assistant, "pong".to_string());
+            return Ok("pong".to_string());
+        }
 
-        let response_text = match response {
-            Ok(response) => response,
-            Err(e) => panic!("Error in response text: {}", e),
-        };
+        let completion = self.complete_request(max_tokens, None, None)?;
+        self.add_message(Role::Assistant, completion.content.clone());
+        Ok(completion.content)
+    }
 
-        let response_object = match parse_response(&response_text) {
-            Ok(response) => response,
-            Err(e) => {
-                if let Ok(error_response) = parse_error_response(&response_text) {
-                    panic!(
-                        "Error while parsing response object: {}",
-                        error_response.error.message
-                    )
-                } else {
-                    panic!(
-                        "Error while parsing error response object: {}\n{}",
-                        e, response_text
-                    );
-                }
-            }
-        };
+    /// Like `complete`, but requests a streamed response and calls `on_delta`
+    /// with each piece of content as soon as it arrives. The assembled reply
+    /// is added to the messages and returned.
+    pub fn complete_streaming(&mut self, on_delta: &mut dyn FnMut(&str)) -> Result<String> {
+        if self.last_message_text().to_lowercase().trim() == "ping" {
+            on_delta("pong");
+            self.add_message(Role::Assistant, "pong".to_string());
+            return Ok("pong".to_string());
+        }
 
-        let result = response_object.choices[0].message.content.clone();
-        let result_text = result.to_string();
-        self.add_message(Role::Assistant, result_text.clone());
-        result_text
+        let completion = self.complete_request(None, None, Some(on_delta))?;
+        self.add_message(Role::Assistant, completion.content.clone());
+        Ok(completion.content)
     }
 
     pub fn to_yaml(&self, exclude_system: bool) -> String {
         let filtered_messages: Vec<Message> = if exclude_system {
             self.messages
                 .iter()
-                .filter(|msg| msg.role.to_lowercase() != "system")
+                .filter(|msg| msg.role != Role::System)
                 .cloned()
                 .collect()
         } else {
@@ -263,138 +218,31 @@ impl GptClient {
     }
 
     //complete method, generates response text in cli.rs within run
-    pub fn complete(&mut self) -> String {
+    pub fn complete(&mut self) -> Result<String> {
         self.complete_with_max_tokens(None)
     }
 
-    pub fn complete_with_tools(&mut self, tools: serde_json::Value) -> serde_json::Value {
-        let last_content_text = match &self.messages.last().unwrap().content {
-            MessageContent::Text(text) => text.clone(),
-            MessageContent::Multi(parts) => parts
-                .iter()
-                .filter_map(|part| match part {
-                    ContentPart::Text { text } => Some(text.as_str()),
-                    _ => None,
-                })
-                .collect::<Vec<_>>()
-                .join(" "),
-        };
-
-        if last_content_text.to_lowercase().trim() == "ping" {
+    /// Requests a completion that may call the given tools. The reply, with
+    /// any tool calls, is added to the messages.
+    pub fn complete_with_tools(&mut self, tools: serde_json::Value) -> Result<Completion> {
+        if self.last_message_text().to_lowercase().trim() == "ping" {
             self.add_message(Role::Assistant, "pong".to_string());
-            return serde_json::json!({"choices": [{"message": {"content": "pong"}, "finish_reason": "stop"}]});
+            return Ok(Completion {
+                content: "pong".to_string(),
+                finish_reason: Some("stop".to_string()),
+                ..Default::default()
+            });
         }
 
-        let api_key = match env::var("OPENAI_API_KEY") {
-            Ok(key) => key,
-            Err(_) => {
-                eprintln!("Missing OPENAI_API_KEY environment variable");
-                return serde_json::json!({});
-            }
-        };
-
-        let client = reqwest::blocking::Client::builder()
-            .danger_accept_invalid_certs(true)
-            .build()
-            .expect("Failed to build client");
-
-        let url =
-            env::var("OPENAI_BASE_URL").unwrap_or_else(|_| "https://api.openai.com".to_string());
-        let url = super::get_completions_url(&url);
-
-        let mut headers = header::HeaderMap::new();
-        headers.insert(
-            header::CONTENT_TYPE,
-            header::HeaderValue::from_static("application/json"),
-        );
-        let auth_header =
-            header::HeaderValue::from_str(&format!("Bearer {}", api_key)).expect("auth header");
-        headers.insert(header::AUTHORIZATION, auth_header);
-
-        let model = self.config_manager.config.model.clone();
-
-        let chat_request = serde_json::json!({
-            "model": model,
-            "messages": self.messages,
-            "tools": tools,
-            "tool_choice": "auto"
-        });
-
-        let response = client
-            .post(url)
-            .headers(headers)
-            .json(&chat_request)
-            .timeout(std::time::Duration::from_secs(60))
-            .send();
-
-        let response = match response {
-            Ok(resp) => resp.text(),
-            Err(e) => {
-                if e.is_timeout() {
-                    eprintln!("The request timed out.");
-                }
-                return serde_json::json!({});
-            }
-        };
-
-        let response_text = response.expect("response text");
-        let value: serde_json::Value = serde_json::from_str(&response_text).expect("parse json");
-
-        let message = &value["choices"][0]["message"];
-        let role = message["role"].as_str().unwrap_or("assistant").to_string();
-        let content = message["content"].as_str().unwrap_or("").to_string();
-        let tool_calls = message.get("tool_calls").cloned();
-
+        let completion = self.complete_request(None, Some(&tools), None)?;
         self.messages.push(Message {
-            role,
-            name: None,
-            tool_call_id: None,
-            tool_calls,
-            content: MessageContent::Text(content),
+            tool_calls: completion.tool_calls.clone(),
+            ..Message::new(Role::Assistant, completion.content.clone())
         });
-
-        value
+        Ok(completion)
     }
 
-    pub fn list_models(&self) -> Vec<String> {
-        let api_key = match env::var("OPENAI_API_KEY") {
-            Ok(key) => key,
-            Err(_) => return Vec::new(),
-        };
-
-        let client = reqwest::blocking::Client::builder()
-            .danger_accept_invalid_certs(true)
-            .build()
-            .expect("Failed to build client");
-
-        let url =
-            env::var("OPENAI_BASE_URL").unwrap_or_else(|_| "https://api.openai.com".to_string());
-        let url = super::get_models_url(&url);
-
-        let mut headers = header::HeaderMap::new();
-        let auth_header =
-            header::HeaderValue::from_str(&format!("Bearer {}", api_key)).expect("auth header");
-        headers.insert(header::AUTHORIZATION, auth_header);
-
-        let response = client.get(url).headers(headers).send();
-
-        let response_text = match response {
-            Ok(resp) => resp.text(),
-            Err(_) => return Vec::new(),
-        };
-
-        let response_text = match response_text {
-            Ok(t) => t,
-            Err(_) => return Vec::new(),
-        };
-
-        let value: serde_json::Value = serde_json::from_str(&response_text).unwrap_or_default();
-        if let Some(arr) = value.get("data").and_then(|d| d.as_array()) {
-            arr.iter()
-                .filter_map(|m| m.get("id").and_then(|i| i.as_str()).map(|s| s.to_string()))
-                .collect()
-        } else {
-            Vec::new()
-        }
+    pub fn list_models(&self) -> Result<Vec<String>> {
+        backend::from_config(&self.config_manager.config)?.list_models()
     }
 }
@@ -1,17 +1,124 @@
 use std::fmt;
+
+use serde::{Deserialize, Deserializer, Serialize};
 use serde_json::Value;
-use serde::{Deserialize, Serialize};
+
+use crate::chatgpt::role::Role;
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Message {
-    pub role: String,
+    pub role: Role,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub name: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub tool_call_id: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub tool_calls: Option<Value>,
+    pub tool_calls: Option<Vec<ToolCall>>,
+    /// OpenAI leaves out the content, or sends `null`, for assistant messages
+    /// that only call tools.
+    #[serde(default = "empty_content", deserialize_with = "content_or_null")]
     pub content: MessageContent,
+    #[serde(flatten)]
+    pub meta: MessageMeta,
+}
+
+/// Bookkeeping kept with messages stored in a session. It is never sent to
+/// the API.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct MessageMeta {
+    /// When the message was added to the session, in RFC 3339 format.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<String>,
+    /// The model that wrote an assistant message.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    /// Earlier replies to the same question kept by `cgip retry --keep`,
+    /// oldest first.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub alternatives: Vec<Message>,
+}
+
+impl Message {
+    /// A text message with no tool calls or bookkeeping.
+    pub fn new(role: Role, text: String) -> Self {
+        Message {
+            role,
+            name: None,
+            tool_call_id: None,
+            tool_calls: None,
+            content: MessageContent::Text(text),
+            meta: MessageMeta::default(),
+        }
+    }
+
+    /// A copy without the session bookkeeping, for request bodies that are
+    /// built by serializing messages directly.
+    pub fn without_meta(&self) -> Message {
+        Message {
+            meta: MessageMeta::default(),
+            ..self.clone()
+        }
+    }
+}
+
+/// A function call requested by an assistant message, in the OpenAI
+/// `tool_calls` format.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ToolCall {
+    /// Matched by the `tool_call_id` of the tool message with the result.
+    #[serde(default)]
+    pub id: String,
+    /// Always `function`.
+    #[serde(rename = "type", default = "function_type")]
+    pub kind: String,
+    pub function: FunctionCall,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct FunctionCall {
+    pub name: String,
+    /// The arguments as a JSON encoded object. An object is accepted too, as
+    /// some providers and hand written YAML give one.
+    #[serde(default, deserialize_with = "arguments_string")]
+    pub arguments: String,
+}
+
+fn empty_content() -> MessageContent {
+    MessageContent::Text(String::new())
+}
+
+fn content_or_null<'de, D: Deserializer<'de>>(deserializer: D) -> Result<MessageContent, D::Error> {
+    Ok(Option::deserialize(deserializer)?.unwrap_or_else(empty_content))
+}
+
+fn function_type() -> String {
+    "function".to_string()
+}
+
+fn arguments_string<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    Ok(match Value::deserialize(deserializer)? {
+        Value::String(arguments) => arguments,
+        Value::Null => String::new(),
+        other => other.to_string(),
+    })
+}
+
+impl ToolCall {
+    pub fn new(id: &str, name: &str, arguments: &Value) -> Self {
+        ToolCall {
+            id: id.to_string(),
+            kind: function_type(),
+            function: FunctionCall {
+                name: name.to_string(),
+                arguments: arguments.to_string(),
+            },
+        }
+    }
+
+    /// The parsed arguments, or `null` when they aren't valid JSON.
+    pub fn arguments(&self) -> Value {
+        serde_json::from_str(&self.function.arguments).unwrap_or_default()
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -36,18 +143,23 @@ impl MessageContent {
         }
     }
 
+    /// The text with images shown as `[Image]`, unlike `Display`, which
+    /// shows their URLs.
+    #[allow(
+        clippy::inherent_to_string_shadow_display,
+        clippy::unnecessary_filter_map
+    )]
     pub fn to_string(&self) -> String {
         match self {
             MessageContent::Text(text) => text.clone(),
-            MessageContent::Multi(parts) => {
-                parts.iter()
-                    .filter_map(|part| match part {
-                        ContentPart::Text { text } => Some(text.as_str()),
-                        ContentPart::ImageUrl { .. } => Some("[Image]"),
-                    })
-                    .collect::<Vec<_>>()
-                    .join(" ")
-            }
+            MessageContent::Multi(parts) => parts
+                .iter()
+                .filter_map(|part| match part {
+                    ContentPart::Text { text } => Some(text.as_str()),
+                    ContentPart::ImageUrl { .. } => Some("[Image]"),
+                })
+                .collect::<Vec<_>>()
+                .join(" "),
         }
     }
 }
@@ -58,10 +170,14 @@ impl fmt::Display for MessageContent {
             MessageContent::Text(text) => write!(f, "{}", text),
             MessageContent::Multi(parts) => {
                 for (i, part) in parts.iter().enumerate() {
-                    if i > 0 { write!(f, " ")?; }
+                    if i > 0 {
+                        write!(f, " ")?;
+                    }
                     match part {
                         ContentPart::Text { text } => write!(f, "{}", text)?,
-                        ContentPart::ImageUrl { image_url } => write!(f, "[Image: {}]", image_url.url)?,
+                        ContentPart::ImageUrl { image_url } => {
+                            write!(f, "[Image: {}]", image_url.url)?
+                        }
                     }
                 }
                 Ok(())
@@ -82,6 +198,9 @@ pub enum ContentPart {
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct ImageUrl {
     pub url: String,
+    /// How closely the model looks at the image: `low`, `high` or `auto`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
 }
 
 impl fmt::Display for Message {
@@ -91,14 +210,37 @@ impl fmt::Display for Message {
             MessageContent::Multi(parts) => {
                 write!(f, "{}: ", self.role)?;
                 for (i, part) in parts.iter().enumerate() {
-                    if i > 0 { write!(f, " + ")?; }
+                    if i > 0 {
+                        write!(f, " + ")?;
+                    }
                     match part {
                         ContentPart::Text { text } => write!(f, "{}", text)?,
-                        ContentPart::ImageUrl { image_url } => write!(f, "[Image: {}]", image_url.url)?,
+                        ContentPart::ImageUrl { image_url } => {
+                            write!(f, "[Image: {}]", image_url.url)?
+                        }
                     }
                 }
                 Ok(())
             }
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tool_calls_from_yaml() {
+        let yaml = "- role: assistant\n  content: ''\n  tool_calls:\n  - id: call_1\n    function:\n      name: execute\n      arguments: {command: ls}\n- role: tool\n  tool_call_id: call_1\n  content: a.txt\n";
+        let messages: Vec<Message> = serde_yaml::from_str(yaml).unwrap();
+        let call = &messages[0].tool_calls.as_ref().unwrap()[0];
+        assert_eq!(call.kind, "function");
+        assert_eq!(call.function.arguments, r#"{"command":"ls"}"#);
+        assert_eq!(call.arguments()["command"], "ls");
+        assert_eq!(messages[1].role, Role::Tool);
+
+        let json = serde_json::to_value(&messages[0]).unwrap();
+        assert_eq!(json["tool_calls"][0]["type"], "function");
+    }
+}
@@ -1,121 +1,96 @@
+pub mod backend;
 pub mod client;
 pub mod message;
 pub mod request;
 pub mod response;
 pub mod role;
+pub mod stream;
 
 pub use client::GptClient;
 pub use message::{Message, MessageContent};
 pub use role::Role;
 
-fn get_completions_url(base_url: &str) -> String {
-    let base = base_url.trim_end_matches('/');
-
-    // If the URL already contains "/chat/completions", use it as-is
-    if base.contains("/chat/completions") {
-        return base.to_string();
-    }
+/// Path of the OpenAI chat completions endpoint below the API root.
+const COMPLETIONS_ENDPOINT: &str = "chat/completions";
+/// Path of the OpenAI models endpoint below the API root.
+const MODELS_ENDPOINT: &str = "models";
 
-    // If the URL ends with "/v1" or contains a version pattern, just add "/chat/completions"
-    if base.ends_with("/v1") || base.contains("/v1beta") || base.contains("/v2") {
-        format!("{}/chat/completions", base)
+/// The URL of an OpenAI `endpoint` for `base_url`. A bare host, such as the
+/// default `https://api.openai.com`, gets OpenAI's `/v1` prefix. A base URL
+/// with a path, such as `http://localhost:11434/v1`, is the API root itself.
+/// A base URL that names one of the endpoints is cut back to its root.
+fn endpoint_url(base_url: &str, endpoint: &str) -> String {
+    let base = base_url.trim_end_matches('/');
+    let base = [COMPLETIONS_ENDPOINT, MODELS_ENDPOINT]
+        .iter()
+        .find_map(|known| base.strip_suffix(known)?.strip_suffix('/'))
+        .unwrap_or(base);
+    let has_path = reqwest::Url::parse(base).is_ok_and(|url| url.path() != "/");
+    if has_path {
+        format!("{}/{}", base, endpoint)
     } else {
-        // Default OpenAI pattern: add "/v1/chat/completions"
-        format!("{}/v1/chat/completions", base)
+        format!("{}/v1/{}", base, endpoint)
     }
 }
 
-fn get_models_url(base_url: &str) -> String {
-    let base = base_url.trim_end_matches('/');
-
-    if base.contains("/models") {
-        return base.to_string();
-    }
+fn get_completions_url(base_url: &str) -> String {
+    endpoint_url(base_url, COMPLETIONS_ENDPOINT)
+}
 
-    if base.ends_with("/v1") || base.contains("/v1beta") || base.contains("/v2") {
-        format!("{}/models", base)
-    } else {
-        format!("{}/v1/models", base)
-    }
+fn get_models_url(base_url: &str) -> String {
+    endpoint_url(base_url, MODELS_ENDPOINT)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::chatgpt::message::MessageContent;
+    use crate::directives;
 
     #[test]
     fn test_get_system_prompt() {
-        let client = GptClient::new(false);
+        let client = GptClient::new(false).unwrap();
         assert!(!client.messages.is_empty());
     }
 
     #[test]
     fn test_search_prefix_detection() {
-        let mut client = GptClient::new(false);
-        client.add_message(Role::User, "/search what is the weather today?".to_string());
-
-        // Simulate the search detection logic
-        let mut use_search = false;
-        if let Some(last_message) = client.messages.last_mut() {
-            if let MessageContent::Text(ref content) = last_message.content {
-                let trimmed_content = content.trim();
-                if last_message.role == "user" && trimmed_content.starts_with("/search") {
-                    use_search = true;
-                }
-            }
-        }
-
-        assert!(use_search, "Search prefix should be detected");
+        let (options, text) = directives::parse("/search what is the weather today?").unwrap();
+        assert!(options.web_search, "Search prefix should be detected");
+        assert_eq!(text, "what is the weather today?");
     }
 
     #[test]
     fn test_no_search_prefix() {
-        let mut client = GptClient::new(false);
-        client.add_message(Role::User, "what is the weather today?".to_string());
-
-        // Simulate the search detection logic
-        let mut use_search = false;
-        if let Some(last_message) = client.messages.last_mut() {
-            if let MessageContent::Text(ref content) = last_message.content {
-                let trimmed_content = content.trim();
-                if last_message.role == "user" && trimmed_content.starts_with("/search") {
-                    use_search = true;
-                }
-            }
-        }
-
-        assert!(!use_search, "Search prefix should not be detected");
+        let (options, _) = directives::parse("what is the weather today?").unwrap();
+        assert!(!options.web_search, "Search prefix should not be detected");
     }
 
     #[test]
     fn test_search_prefix_with_whitespace() {
-        let mut client = GptClient::new(false);
-        client.add_message(
-            Role::User,
-            "  /search what is the weather today?  ".to_string(),
-        );
-
-        // Simulate the search detection logic
-        let mut use_search = false;
-        if let Some(last_message) = client.messages.last_mut() {
-            if let MessageContent::Text(ref content) = last_message.content {
-                let trimmed_content = content.trim();
-                if last_message.role == "user" && trimmed_content.starts_with("/search") {
-                    use_search = true;
-                }
-            }
-        }
-
+        let (options, text) =
+            directives::parse("  /search what is the weather today?  ").unwrap();
         assert!(
-            use_search,
+            options.web_search,
             "Search prefix should be detected even with whitespace"
         );
+        assert_eq!(text.trim(), "what is the weather today?");
+    }
+
+    #[test]
+    fn test_system_directive_replaces_prompt_for_request() {
+        let mut client = GptClient::new(false).unwrap();
+        client.add_message(Role::User, "hi".to_string());
+        client.options.system = Some("Be terse".to_string());
+
+        let messages = client.request_messages();
+        assert_eq!(messages[0].content.to_string(), "Be terse");
+        assert_eq!(messages.len(), 2);
+        assert_ne!(client.messages[0].content.to_string(), "Be terse");
     }
 
     #[test]
     fn test_search_model_selection_with_gpt() {
-        let client = GptClient::new(false);
+        let client = GptClient::new(false).unwrap();
         let model = &client.config_manager.config.model;
 
         let use_search = true;
@@ -133,7 +108,7 @@ mod tests {
     #[test]
     fn test_search_model_selection_with_non_gpt() {
         // This test assumes the model is not gpt-based
-        let mut client = GptClient::new(false);
+        let mut client = GptClient::new(false).unwrap();
         client.config_manager.config.model = "claude-3".to_string();
 
         let use_search = true;
@@ -149,7 +124,7 @@ mod tests {
 
     #[test]
     fn test_search_model_selection_with_gpt_in_middle() {
-        let mut client = GptClient::new(false);
+        let mut client = GptClient::new(false).unwrap();
         client.config_manager.config.model = "anthropic-gpt-4".to_string();
 
         let use_search = true;
@@ -188,11 +163,12 @@ mod tests {
     }
 
     #[test]
-    fn test_get_completions_url_gemini_style() {
-        let url = get_completions_url(
-            "https://generativelanguage.googleapis.com/v1beta/models/gemini-pro:generateContent",
+    fn test_get_completions_url_gemini_openai_compatible() {
+        let url = get_completions_url("https://generativelanguage.googleapis.com/v1beta/openai");
+        assert_eq!(
+            url,
+            "https://generativelanguage.googleapis.com/v1beta/openai/chat/completions"
         );
-        assert_eq!(url, "https://generativelanguage.googleapis.com/v1beta/models/gemini-pro:generateContent/chat/completions");
     }
 
     #[test]
@@ -225,6 +201,18 @@ mod tests {
         assert_eq!(url, "https://api.openai.com/v1/models");
     }
 
+    #[test]
+    fn test_get_completions_url_with_unversioned_path() {
+        let url = get_completions_url("https://gateway.example.com/openai");
+        assert_eq!(url, "https://gateway.example.com/openai/chat/completions");
+    }
+
+    #[test]
+    fn test_get_models_url_with_existing_endpoint() {
+        let url = get_models_url("https://api.example.com/v1/chat/completions");
+        assert_eq!(url, "https://api.example.com/v1/models");
+    }
+
     #[test]
     fn test_get_models_url_with_v2() {
         let url = get_models_url("https://api.example.com/v2");
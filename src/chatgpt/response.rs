@@ -1,28 +1,20 @@
 use serde::{Deserialize, Serialize};
 use serde_json::Result;
-use crate::chatgpt::message::Message;
+
+use crate::chatgpt::message::ToolCall;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ChatResponse {
     pub id: Option<String>,
     pub object: Option<String>,
+    #[serde(default)]
     pub created: u64,
     pub model: String,
-    pub usage: Usage,
+    pub usage: Option<Usage>,
     pub choices: Vec<Choice>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct ErrorResponse {
-    pub error: ErrorDetail,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-pub struct ErrorDetail {
-    pub message: String,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Usage {
     pub prompt_tokens: u64,
     pub completion_tokens: u64,
@@ -31,15 +23,21 @@ pub struct Usage {
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Choice {
-    pub message: Message,
-    pub finish_reason: String,
+    pub message: ResponseMessage,
+    pub finish_reason: Option<String>,
+    #[serde(default)]
     pub index: u64,
 }
 
-pub fn parse_response(json_str: &str) -> Result<ChatResponse> {
-    serde_json::from_str(json_str)
+/// The assistant message of a choice. `content` is null when the model only
+/// returns tool calls.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ResponseMessage {
+    pub role: Option<String>,
+    pub content: Option<String>,
+    pub tool_calls: Option<Vec<ToolCall>>,
 }
 
-pub fn parse_error_response(json_str: &str) -> Result<ErrorResponse> {
+pub fn parse_response(json_str: &str) -> Result<ChatResponse> {
     serde_json::from_str(json_str)
-}
\ No newline at end of file
+}
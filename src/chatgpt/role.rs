@@ -1,11 +1,37 @@
 use std::{fmt, str::FromStr};
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// The author of a message, named as in the OpenAI chat API.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum Role {
     System,
+    /// Instructions from the developer, which newer OpenAI models take in
+    /// place of a system prompt.
+    Developer,
     User,
     Assistant,
+    /// The result of a tool call, answering the call named by the message's
+    /// `tool_call_id`.
+    Tool,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::System => "system",
+            Role::Developer => "developer",
+            Role::User => "user",
+            Role::Assistant => "assistant",
+            Role::Tool => "tool",
+        }
+    }
+
+    /// Whether the message instructs the model rather than being part of the
+    /// conversation.
+    pub fn is_instruction(&self) -> bool {
+        matches!(self, Role::System | Role::Developer)
+    }
 }
 
 impl FromStr for Role {
@@ -14,8 +40,10 @@ impl FromStr for Role {
     fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
         match s {
             "system" => Ok(Role::System),
+            "developer" => Ok(Role::Developer),
             "user" => Ok(Role::User),
             "assistant" => Ok(Role::Assistant),
+            "tool" => Ok(Role::Tool),
             _ => Err("Invalid role"),
         }
     }
@@ -23,10 +51,20 @@ impl FromStr for Role {
 
 impl fmt::Display for Role {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match self {
-            Role::System => write!(f, "system"),
-            Role::User => write!(f, "user"),
-            Role::Assistant => write!(f, "assistant"),
+        write!(f, "{}", self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_role_names_round_trip() {
+        for role in [Role::System, Role::Developer, Role::User, Role::Assistant, Role::Tool] {
+            assert_eq!(role.as_str().parse::<Role>(), Ok(role));
+            assert_eq!(serde_json::to_value(role).unwrap(), role.as_str());
         }
+        assert!("robot".parse::<Role>().is_err());
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,156 @@
+use std::io::BufRead;
+
+use serde::{Deserialize, Serialize};
+
+use crate::chatgpt::backend::Completion;
+use crate::chatgpt::response::Usage;
+
+/// A single `chat.completion.chunk` object sent by the server when a request
+/// is made with `stream: true`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ChatCompletionChunk {
+    pub id: Option<String>,
+    pub model: Option<String>,
+    #[serde(default)]
+    pub choices: Vec<ChunkChoice>,
+    /// Sent in a last chunk with no choices when the request asks for it
+    /// with `stream_options`.
+    pub usage: Option<Usage>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ChunkChoice {
+    #[serde(default)]
+    pub delta: Delta,
+    pub finish_reason: Option<String>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Delta {
+    pub role: Option<String>,
+    pub content: Option<String>,
+}
+
+/// The payload of one server-sent event line.
+#[derive(Debug, PartialEq)]
+pub enum SseData<'a> {
+    Json(&'a str),
+    Done,
+}
+
+/// Extracts the data payload from a line of an SSE stream. Comments,
+/// blank keep-alive lines and non-data fields yield `None`.
+pub fn parse_sse_line(line: &str) -> Option<SseData<'_>> {
+    let data = line.strip_prefix("data:")?.trim();
+    if data.is_empty() {
+        return None;
+    }
+    if data == "[DONE]" {
+        return Some(SseData::Done);
+    }
+    Some(SseData::Json(data))
+}
+
+/// Reads an OpenAI style chat completion stream, calling `on_delta` with
+/// each piece of content as it arrives, and returns the assembled completion.
+pub fn read_stream<R: BufRead>(
+    reader: R,
+    on_delta: &mut dyn FnMut(&str),
+) -> std::io::Result<Completion> {
+    let mut completion = Completion::default();
+
+    for line in reader.lines() {
+        let line = line?;
+        let data = match parse_sse_line(&line) {
+            Some(SseData::Done) => break,
+            Some(SseData::Json(data)) => data,
+            None => continue,
+        };
+
+        let chunk: ChatCompletionChunk = match serde_json::from_str(data) {
+            Ok(chunk) => chunk,
+            Err(_) => continue,
+        };
+
+        if completion.id.is_none() {
+            completion.id = chunk.id;
+        }
+        if completion.model.is_none() {
+            completion.model = chunk.model;
+        }
+        if chunk.usage.is_some() {
+            completion.usage = chunk.usage;
+        }
+
+        for choice in chunk.choices {
+            if let Some(content) = choice.delta.content {
+                if !content.is_empty() {
+                    on_delta(&content);
+                    completion.content.push_str(&content);
+                }
+            }
+            if choice.finish_reason.is_some() {
+                completion.finish_reason = choice.finish_reason;
+            }
+        }
+    }
+
+    Ok(completion)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_sse_line() {
+        assert_eq!(
+            parse_sse_line("data: {\"a\":1}"),
+            Some(SseData::Json("{\"a\":1}"))
+        );
+        assert_eq!(
+            parse_sse_line("data:{\"a\":1}"),
+            Some(SseData::Json("{\"a\":1}"))
+        );
+        assert_eq!(parse_sse_line("data: [DONE]"), Some(SseData::Done));
+        assert_eq!(parse_sse_line(": keep-alive"), None);
+        assert_eq!(parse_sse_line("event: message"), None);
+        assert_eq!(parse_sse_line(""), None);
+    }
+
+    #[test]
+    fn test_read_stream_assembles_deltas() {
+        let body = "\
+data: {\"id\":\"1\",\"object\":\"chat.completion.chunk\",\"choices\":[{\"index\":0,\"delta\":{\"role\":\"assistant\",\"content\":\"\"},\"finish_reason\":null}]}
+
+data: {\"id\":\"1\",\"object\":\"chat.completion.chunk\",\"choices\":[{\"index\":0,\"delta\":{\"content\":\"Hel\"},\"finish_reason\":null}]}
+
+: keep-alive
+
+data: {\"id\":\"1\",\"object\":\"chat.completion.chunk\",\"choices\":[{\"index\":0,\"delta\":{\"content\":\"lo\"},\"finish_reason\":null}]}
+
+data: {\"id\":\"1\",\"object\":\"chat.completion.chunk\",\"choices\":[{\"index\":0,\"delta\":{},\"finish_reason\":\"stop\"}],\"usage\":null}
+
+data: {\"id\":\"1\",\"object\":\"chat.completion.chunk\",\"choices\":[],\"usage\":{\"prompt_tokens\":9,\"completion_tokens\":2,\"total_tokens\":11}}
+
+data: [DONE]
+";
+        let mut deltas = Vec::new();
+        let completion = read_stream(body.as_bytes(), &mut |d| deltas.push(d.to_string())).unwrap();
+
+        assert_eq!(completion.content, "Hello");
+        assert_eq!(completion.finish_reason.as_deref(), Some("stop"));
+        assert_eq!(deltas, vec!["Hel", "lo"]);
+        let usage = completion.usage.unwrap();
+        assert_eq!((usage.prompt_tokens, usage.total_tokens), (9, 11));
+    }
+
+    #[test]
+    fn test_read_stream_stops_at_done() {
+        let body = "data: {\"choices\":[{\"delta\":{\"content\":\"a\"}}]}\n\
+data: [DONE]\n\
+data: {\"choices\":[{\"delta\":{\"content\":\"b\"}}]}\n";
+        let completion = read_stream(body.as_bytes(), &mut |_| {}).unwrap();
+        assert_eq!(completion.content, "a");
+    }
+}
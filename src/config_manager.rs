@@ -3,27 +3,46 @@ use std::{fs::File, io::Write, path::PathBuf};
 use config::{Config, File as ConfigFile, FileFormat};
 use serde::{Deserialize, Serialize};
 
+use crate::error::{CgipError, Result};
 use crate::utils::ensure_config_file;
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct AppConfig {
     pub model: String,
+    pub provider: String,
     pub show_progress: bool,
     pub show_context: bool,
     pub markdown: bool,
     pub jarjar: bool,
-    pub stored_context_length: usize
+    pub stored_context_length: usize,
+    pub max_retries: u32,
+    pub max_retry_wait_secs: u64,
+    pub max_input_tokens: usize,
+    pub stored_context_tokens: usize,
+    pub summarize_evicted: bool,
+    pub max_file_bytes: u64,
+    pub max_total_file_bytes: u64,
+    pub history: bool,
 }
 
 impl Default for AppConfig {
     fn default() -> Self {
         Self {
             model: "gpt-4o".to_string(),
+            provider: "openai".to_string(),
             show_progress: false,
             show_context: false,
             markdown: false,
             jarjar: false,
-            stored_context_length: 20
+            stored_context_length: 20,
+            max_retries: 3,
+            max_retry_wait_secs: 60,
+            max_input_tokens: 0,
+            stored_context_tokens: 0,
+            summarize_evicted: false,
+            max_file_bytes: 256 * 1024,
+            max_total_file_bytes: 1024 * 1024,
+            history: true,
         }
     }
 }
@@ -34,84 +53,119 @@ pub struct ConfigManager {
 }
 
 impl ConfigManager {
-    pub fn new(config_directory: PathBuf) -> Self {
-        Self::setup_config(&config_directory);
-        let config = Self::load_config(&config_directory);
-        ConfigManager {
+    pub fn new(config_directory: PathBuf) -> Result<Self> {
+        Self::setup_config(&config_directory)?;
+        let config = Self::load_config(&config_directory)?;
+        Ok(ConfigManager {
             config,
             config_directory,
-        }
+        })
     }
 
-    pub fn setup_config(dir: &PathBuf) {
-        if let Err(e) = ensure_config_file(dir) {
-            panic!("Failed to ensure config file exists: {}", e);
-        }
+    pub fn setup_config(dir: &PathBuf) -> Result<()> {
+        ensure_config_file(dir).map_err(|e| {
+            CgipError::Config(format!(
+                "Could not create the config file in {}: {}",
+                dir.display(),
+                e
+            ))
+        })?;
+        Ok(())
     }
 
-    pub fn load_config(dir: &PathBuf) -> AppConfig {
+    #[allow(clippy::ptr_arg)]
+    pub fn load_config(dir: &PathBuf) -> Result<AppConfig> {
         let config_path = dir.join("config.toml");
-        let defaults = Config::try_from(&AppConfig::default()).unwrap();
+        let invalid = |e: config::ConfigError| CgipError::Config(format!("Invalid config: {}", e));
+        let defaults = Config::try_from(&AppConfig::default()).map_err(invalid)?;
         let config = Config::builder() // sources will be merged by priority
             .add_source(defaults)
-            .add_source(ConfigFile::new(
-                config_path.to_str().unwrap(),
-                FileFormat::Toml,
-            ))
+            .add_source(ConfigFile::from(config_path.as_path()).format(FileFormat::Toml))
             .build()
-            .unwrap();
-        let loaded_config = config
-            .try_deserialize::<AppConfig>()
-            .expect("Failed to deserialize config");
-
-        loaded_config
+            .map_err(invalid)?;
+        config.try_deserialize::<AppConfig>().map_err(invalid)
     }
 
-    pub fn set_config_value(&mut self, key: &str, value: &str) {
-        let config_path = match ensure_config_file(&self.config_directory) {
-            Ok(path) => path,
-            Err(e) => panic!("Failed to ensure config file exists: {}", e),
-        };
+    pub fn set_config_value(&mut self, key: &str, value: &str) -> Result<()> {
+        let config_path = ensure_config_file(&self.config_directory)?;
+        let invalid =
+            |key: &str| CgipError::Config(format!("Invalid value for {}: {}", key, value));
 
         let mut config = if self.config_directory.exists() {
-            Self::load_config(&self.config_directory)
+            Self::load_config(&self.config_directory)?
         } else {
             AppConfig::default()
         };
 
         match key {
             "model" => config.model = value.to_string(),
-            "show_progress" => {
-                config.show_progress = value.parse().expect("Invalid value for show_progress")
+            "provider" => config.provider = value.to_string(),
+            "show_progress" => config.show_progress = value.parse().map_err(|_| invalid(key))?,
+            "show_context" => config.show_context = value.parse().map_err(|_| invalid(key))?,
+            "markdown" => config.markdown = value.parse().map_err(|_| invalid(key))?,
+            "jarjar" => config.jarjar = value.parse().map_err(|_| invalid(key))?,
+            "max_retries" => config.max_retries = value.parse().map_err(|_| invalid(key))?,
+            "max_retry_wait_secs" => {
+                config.max_retry_wait_secs = value.parse().map_err(|_| invalid(key))?
+            }
+            "max_input_tokens" => {
+                config.max_input_tokens = value.parse().map_err(|_| invalid(key))?
+            }
+            "stored_context_length" => {
+                config.stored_context_length = value.parse().map_err(|_| invalid(key))?
+            }
+            "stored_context_tokens" => {
+                config.stored_context_tokens = value.parse().map_err(|_| invalid(key))?
+            }
+            "summarize_evicted" => {
+                config.summarize_evicted = value.parse().map_err(|_| invalid(key))?
+            }
+            "max_file_bytes" => {
+                config.max_file_bytes = value.parse().map_err(|_| invalid(key))?
             }
-            "show_context" => {
-                config.show_context = value.parse().expect("Invalid value for show_context")
+            "max_total_file_bytes" => {
+                config.max_total_file_bytes = value.parse().map_err(|_| invalid(key))?
+            }
+            "history" => config.history = value.parse().map_err(|_| invalid(key))?,
+            _ => {
+                return Err(CgipError::Config(format!(
+                    "Invalid configuration key: {}",
+                    key
+                )))
             }
-            "markdown" => config.markdown = value.parse().expect("Invalid value for markdown"),
-            "jarjar" => config.jarjar = value.parse().expect("Invalid value for jarjar"),
-            _ => eprintln!("Invalid configuration key"),
         }
 
-        let contents = toml::to_string(&config).expect("Failed to serialize config");
-        let mut file = File::create(&config_path).expect("Failed to create config file");
-        file.write_all(contents.as_bytes())
-            .expect("Failed to write to config file");
+        let contents = toml::to_string(&config)
+            .map_err(|e| CgipError::Config(format!("Failed to serialize config: {}", e)))?;
+        let mut file = File::create(&config_path)?;
+        file.write_all(contents.as_bytes())?;
+        Ok(())
     }
 
     pub fn get_config_value(&self, key: &str) -> String {
         match key {
             "model" => self.config.model.clone(),
+            "provider" => self.config.provider.clone(),
             "show_progress" => self.config.show_progress.to_string(),
             "show_context" => self.config.show_context.to_string(),
             "markdown" => self.config.markdown.to_string(),
             "jarjar" => self.config.jarjar.to_string(),
             "stored_context_length" => self.config.stored_context_length.to_string(),
+            "max_retries" => self.config.max_retries.to_string(),
+            "max_retry_wait_secs" => self.config.max_retry_wait_secs.to_string(),
+            "max_input_tokens" => self.config.max_input_tokens.to_string(),
+            "stored_context_tokens" => self.config.stored_context_tokens.to_string(),
+            "summarize_evicted" => self.config.summarize_evicted.to_string(),
+            "max_file_bytes" => self.config.max_file_bytes.to_string(),
+            "max_total_file_bytes" => self.config.max_total_file_bytes.to_string(),
+            "history" => self.config.history.to_string(),
             _ => "Invalid configuration key".to_string(),
         }
     }
 }
 
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
 mod tests {
     use super::*;
     use std::fs::File;
@@ -124,16 +178,25 @@ mod tests {
     fn test_custom_config() {
         let temp_dir = TempDir::new().unwrap();
         let config_dir_path = temp_dir.path().join("cgip");
-        let mut config_manager = ConfigManager::new(config_dir_path.clone());
+        let mut config_manager = ConfigManager::new(config_dir_path.clone()).unwrap();
 
         // Manually create a custom config
         let custom_config = AppConfig {
             model: "gpt-4o".to_string(),
+            provider: "openai".to_string(),
             show_progress: true,
             show_context: false,
             markdown: false,
             stored_context_length: 20,
             jarjar: false,
+            max_retries: 3,
+            max_retry_wait_secs: 60,
+            max_input_tokens: 0,
+            stored_context_tokens: 0,
+            summarize_evicted: false,
+            max_file_bytes: 256 * 1024,
+            max_total_file_bytes: 1024 * 1024,
+            history: true,
         };
 
         // Serialize and save this custom config
@@ -144,7 +207,7 @@ mod tests {
             .expect("Failed to write custom config to file");
 
         // Reload config from file
-        config_manager.config = ConfigManager::load_config(&config_dir_path);
+        config_manager.config = ConfigManager::load_config(&config_dir_path).unwrap();
 
         assert_eq!(
             config_manager.config.model, "gpt-4o",
@@ -160,7 +223,7 @@ mod tests {
     fn test_custom_config_with_missing() {
         let temp_dir = TempDir::new().unwrap();
         let config_dir_path = temp_dir.path().join("cgip");
-        let mut config_manager = ConfigManager::new(config_dir_path.clone());
+        let mut config_manager = ConfigManager::new(config_dir_path.clone()).unwrap();
 
         // Create a partial config file manually
         let config_path = config_dir_path.join("config.toml");
@@ -170,7 +233,7 @@ mod tests {
             .expect("Failed to write partial config to file");
 
         // Reload config from file
-        config_manager.config = ConfigManager::load_config(&config_dir_path);
+        config_manager.config = ConfigManager::load_config(&config_dir_path).unwrap();
 
         assert_eq!(
             config_manager.config.model, "gpt-4o",
@@ -186,7 +249,7 @@ mod tests {
     fn test_default_config() {
         let temp_dir = TempDir::new().unwrap();
         let config_dir_path = temp_dir.path().join("cgip");
-        let config_manager = ConfigManager::new(config_dir_path);
+        let config_manager = ConfigManager::new(config_dir_path).unwrap();
 
         assert_eq!(
             config_manager.config.model, "gpt-4o",
@@ -197,4 +260,21 @@ mod tests {
             "show_progress should default to false"
         );
     }
+
+    #[test]
+    fn test_malformed_config() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_dir_path = temp_dir.path().join("cgip");
+        ConfigManager::setup_config(&config_dir_path).unwrap();
+
+        let config_path = config_dir_path.join("config.toml");
+        std::fs::write(&config_path, "max_retries = \"many\"").unwrap();
+
+        let err = ConfigManager::load_config(&config_dir_path).unwrap_err();
+        assert_eq!(
+            err.exit_code(),
+            3,
+            "Malformed config should be a config error"
+        );
+    }
 }
@@ -0,0 +1,205 @@
+use std::fmt;
+
+/// Errors that end a cgip invocation. Each variant maps to its own process
+/// exit code so scripts can tell failures apart:
+///
+/// | Code | Variant       | Meaning                                          |
+/// |------|---------------|--------------------------------------------------|
+/// | 0    |               | Success                                          |
+/// | 2    | `Usage`       | Invalid command line usage or missing input      |
+/// | 3    | `Config`      | Missing API key, unknown provider, bad config    |
+/// | 4    | `Auth`        | The API rejected the credentials                 |
+/// | 5    | `RateLimit`   | The API rate limit or quota was exceeded         |
+/// | 6    | `Network`     | The server could not be reached                  |
+/// | 7    | `Timeout`     | The request timed out                            |
+/// | 8    | `BadResponse` | The response could not be understood             |
+/// | 9    | `Api`         | The API returned any other error                 |
+/// | 10   | `Io`          | Reading or writing a local file failed           |
+/// | 101  |               | Internal error (a Rust panic, a bug in cgip)     |
+#[derive(Debug)]
+pub enum CgipError {
+    Usage(String),
+    Config(String),
+    Auth(String),
+    RateLimit(String),
+    Network(String),
+    Timeout(String),
+    BadResponse(String),
+    Api { status: u16, message: String },
+    Io(String),
+}
+
+pub type Result<T> = std::result::Result<T, CgipError>;
+
+impl CgipError {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            // Same code clap uses for invalid arguments
+            CgipError::Usage(_) => 2,
+            CgipError::Config(_) => 3,
+            CgipError::Auth(_) => 4,
+            CgipError::RateLimit(_) => 5,
+            CgipError::Network(_) => 6,
+            CgipError::Timeout(_) => 7,
+            CgipError::BadResponse(_) => 8,
+            CgipError::Api { .. } => 9,
+            CgipError::Io(_) => 10,
+        }
+    }
+
+    /// A stable name for the variant, used in JSON output.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            CgipError::Usage(_) => "usage",
+            CgipError::Config(_) => "config",
+            CgipError::Auth(_) => "auth",
+            CgipError::RateLimit(_) => "rate_limit",
+            CgipError::Network(_) => "network",
+            CgipError::Timeout(_) => "timeout",
+            CgipError::BadResponse(_) => "bad_response",
+            CgipError::Api { .. } => "api",
+            CgipError::Io(_) => "io",
+        }
+    }
+
+    /// The error as a JSON object for `--output json`.
+    pub fn to_json(&self) -> serde_json::Value {
+        let mut error = serde_json::json!({
+            "kind": self.kind(),
+            "message": self.to_string(),
+            "exit_code": self.exit_code(),
+        });
+        if let CgipError::Api { status, .. } = self {
+            error["status"] = serde_json::json!(status);
+        }
+        serde_json::json!({ "error": error })
+    }
+
+    /// Maps an unsuccessful HTTP status and the provider's error message to
+    /// the matching variant.
+    pub fn from_status(status: u16, message: String) -> Self {
+        match status {
+            401 | 403 => CgipError::Auth(message),
+            429 => CgipError::RateLimit(message),
+            408 | 504 => CgipError::Timeout(message),
+            _ => CgipError::Api { status, message },
+        }
+    }
+}
+
+impl fmt::Display for CgipError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Messages are kept on one line so they read well on stderr
+        let one_line = |s: &str| s.split_whitespace().collect::<Vec<_>>().join(" ");
+        match self {
+            CgipError::Usage(msg) => write!(f, "{}", one_line(msg)),
+            CgipError::Config(msg) => write!(f, "configuration error: {}", one_line(msg)),
+            CgipError::Auth(msg) => write!(f, "authentication failed: {}", one_line(msg)),
+            CgipError::RateLimit(msg) => write!(f, "rate limited: {}", one_line(msg)),
+            CgipError::Network(msg) => write!(f, "network error: {}", one_line(msg)),
+            CgipError::Timeout(msg) => write!(f, "request timed out: {}", one_line(msg)),
+            CgipError::BadResponse(msg) => write!(f, "unexpected response: {}", one_line(msg)),
+            CgipError::Api { status, message } => {
+                write!(f, "API error ({}): {}", status, one_line(message))
+            }
+            CgipError::Io(msg) => write!(f, "I/O error: {}", one_line(msg)),
+        }
+    }
+}
+
+impl std::error::Error for CgipError {}
+
+impl From<reqwest::Error> for CgipError {
+    fn from(e: reqwest::Error) -> Self {
+        if e.is_timeout() {
+            CgipError::Timeout(e.to_string())
+        } else if e.is_decode() || e.is_body() {
+            CgipError::BadResponse(e.to_string())
+        } else if let Some(status) = e.status() {
+            CgipError::from_status(status.as_u16(), e.to_string())
+        } else {
+            CgipError::Network(e.to_string())
+        }
+    }
+}
+
+impl From<std::io::Error> for CgipError {
+    fn from(e: std::io::Error) -> Self {
+        CgipError::Io(e.to_string())
+    }
+}
+
+impl From<serde_json::Error> for CgipError {
+    fn from(e: serde_json::Error) -> Self {
+        CgipError::BadResponse(e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_status() {
+        assert!(matches!(
+            CgipError::from_status(401, String::new()),
+            CgipError::Auth(_)
+        ));
+        assert!(matches!(
+            CgipError::from_status(429, String::new()),
+            CgipError::RateLimit(_)
+        ));
+        assert!(matches!(
+            CgipError::from_status(400, String::new()),
+            CgipError::Api { status: 400, .. }
+        ));
+    }
+
+    #[test]
+    fn test_to_json() {
+        let json = CgipError::Api {
+            status: 400,
+            message: "bad\nrequest".into(),
+        }
+        .to_json();
+        assert_eq!(json["error"]["kind"], "api");
+        assert_eq!(json["error"]["status"], 400);
+        assert_eq!(json["error"]["exit_code"], 9);
+        assert_eq!(json["error"]["message"], "API error (400): bad request");
+        assert!(CgipError::Usage(String::new()).to_json()["error"]
+            .get("status")
+            .is_none());
+    }
+
+    #[test]
+    fn test_exit_codes_are_distinct() {
+        let errors = [
+            CgipError::Usage(String::new()),
+            CgipError::Config(String::new()),
+            CgipError::Auth(String::new()),
+            CgipError::RateLimit(String::new()),
+            CgipError::Network(String::new()),
+            CgipError::Timeout(String::new()),
+            CgipError::BadResponse(String::new()),
+            CgipError::Api {
+                status: 500,
+                message: String::new(),
+            },
+            CgipError::Io(String::new()),
+        ];
+        let mut codes: Vec<i32> = errors.iter().map(|e| e.exit_code()).collect();
+        codes.sort();
+        codes.dedup();
+        assert_eq!(codes.len(), errors.len());
+        assert!(codes.iter().all(|c| *c >= 2));
+    }
+
+    #[test]
+    fn test_display_is_single_line() {
+        let e = CgipError::BadResponse("expected value\n{\"oops\": true}".to_string());
+        assert_eq!(
+            e.to_string(),
+            "unexpected response: expected value {\"oops\": true}"
+        );
+    }
+}
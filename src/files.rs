@@ -0,0 +1,311 @@
+use std::collections::HashSet;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use ignore::WalkBuilder;
+
+use crate::config_manager::AppConfig;
+use crate::error::{CgipError, Result};
+
+/// How much of a file is checked for NUL bytes when telling binary files
+/// from text.
+const SNIFF_LEN: usize = 8192;
+
+/// Size limits for the files found by a glob or under `--dir`. Zero means no
+/// limit.
+#[derive(Debug, Clone, Copy)]
+pub struct FileLimits {
+    pub max_file_bytes: u64,
+    pub max_total_bytes: u64,
+}
+
+impl FileLimits {
+    pub fn from_config(config: &AppConfig) -> Self {
+        FileLimits {
+            max_file_bytes: config.max_file_bytes,
+            max_total_bytes: config.max_total_file_bytes,
+        }
+    }
+}
+
+/// A text file to add to the context.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InputFile {
+    /// The path relative to the current directory where possible.
+    pub path: String,
+    pub content: String,
+}
+
+impl InputFile {
+    /// The content in a fenced block headed with the path, so the model can
+    /// tell which content came from where. The fence is longer than any run
+    /// of backticks in the content.
+    pub fn to_markdown(&self) -> String {
+        let longest_run = self
+            .content
+            .split(|c| c != '`')
+            .map(str::len)
+            .max()
+            .unwrap_or(0);
+        let fence = "`".repeat(longest_run.max(2) + 1);
+        let lang = Path::new(&self.path)
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        let newline = if self.content.ends_with('\n') {
+            ""
+        } else {
+            "\n"
+        };
+        format!(
+            "{}\n{}{}\n{}{}{}",
+            self.path, fence, lang, self.content, newline, fence
+        )
+    }
+}
+
+/// Whether `pattern` is to be expanded. A file that exists is taken as
+/// named, so paths like `app/[id]/page.tsx` work without escaping.
+fn is_glob(pattern: &str) -> bool {
+    pattern.contains(['*', '?', '[']) && !Path::new(pattern).exists()
+}
+
+/// Text content of a file, or `None` for binary files: those with a NUL byte
+/// near the start or that aren't valid UTF-8.
+fn text_content(bytes: Vec<u8>) -> Option<String> {
+    if bytes[..bytes.len().min(SNIFF_LEN)].contains(&0) {
+        return None;
+    }
+    String::from_utf8(bytes).ok()
+}
+
+/// `path` relative to the current directory when it is inside it, without
+/// a leading `./`.
+fn display_path(path: &Path) -> String {
+    let path = env::current_dir()
+        .ok()
+        .and_then(|cwd| path.strip_prefix(cwd).ok())
+        .unwrap_or(path);
+    let path = path.strip_prefix(".").unwrap_or(path);
+    path.display().to_string()
+}
+
+/// Files in `dir` and below, leaving out hidden files and those ignored by
+/// `.gitignore`, `.ignore` and global git excludes, in a stable order.
+fn walk(dir: &str) -> Result<Vec<PathBuf>> {
+    if !Path::new(dir).is_dir() {
+        return Err(CgipError::Usage(format!("'{}' is not a directory", dir)));
+    }
+    let mut paths = Vec::new();
+    let walker = WalkBuilder::new(dir)
+        .require_git(false)
+        .sort_by_file_name(|a, b| a.cmp(b))
+        .build();
+    for entry in walker {
+        match entry {
+            Ok(entry) if entry.file_type().is_some_and(|t| t.is_file()) => {
+                paths.push(entry.into_path())
+            }
+            Ok(_) => {}
+            Err(e) => eprintln!("Warning: {}", e),
+        }
+    }
+    Ok(paths)
+}
+
+/// Reads the files named by `patterns`, which are paths or globs, and the
+/// files under `dirs`. Named files have to be readable text and are read
+/// whatever their size, though they count towards the total. Files found by
+/// a glob or a walk are skipped when they are binary or too big, and once the
+/// total limit is reached.
+pub fn collect(patterns: &[String], dirs: &[String], limits: FileLimits) -> Result<Vec<InputFile>> {
+    // The flag says whether the file was named rather than found
+    let mut candidates: Vec<(PathBuf, bool)> = Vec::new();
+    for pattern in patterns {
+        if !is_glob(pattern) {
+            candidates.push((PathBuf::from(pattern), true));
+            continue;
+        }
+        let matches = glob::glob(pattern)
+            .map_err(|e| CgipError::Usage(format!("Invalid pattern '{}': {}", pattern, e)))?;
+        let before = candidates.len();
+        candidates.extend(
+            matches
+                .flatten()
+                .filter(|path| path.is_file())
+                .map(|path| (path, false)),
+        );
+        if candidates.len() == before {
+            return Err(CgipError::Usage(format!("No files match '{}'", pattern)));
+        }
+    }
+    for dir in dirs {
+        candidates.extend(walk(dir)?.into_iter().map(|path| (path, false)));
+    }
+
+    let mut files = Vec::new();
+    let mut seen = HashSet::new();
+    let mut total = 0;
+    let mut binary = 0;
+    let mut full = false;
+    for (path, named) in candidates {
+        // `src/a.rs` and `./src/a.rs` are the same file
+        if !seen.insert(fs::canonicalize(&path).unwrap_or_else(|_| path.clone())) {
+            continue;
+        }
+        if full && !named {
+            continue;
+        }
+        let read_error = |e: std::io::Error| {
+            CgipError::Io(format!("Could not read '{}': {}", path.display(), e))
+        };
+
+        let size = fs::metadata(&path).map_err(read_error)?.len();
+        if !named && limits.max_file_bytes > 0 && size > limits.max_file_bytes {
+            eprintln!(
+                "Warning: skipping '{}', it is {} bytes, over the max_file_bytes limit of {}",
+                path.display(),
+                size,
+                limits.max_file_bytes
+            );
+            continue;
+        }
+        if !named && limits.max_total_bytes > 0 && total + size > limits.max_total_bytes {
+            eprintln!(
+                "Warning: stopped at '{}', the files add up to more than the max_total_file_bytes limit of {}",
+                path.display(),
+                limits.max_total_bytes
+            );
+            full = true;
+            continue;
+        }
+
+        let content = match text_content(fs::read(&path).map_err(read_error)?) {
+            Some(content) => content,
+            None if named => {
+                return Err(CgipError::Usage(format!(
+                    "'{}' is not a text file",
+                    path.display()
+                )))
+            }
+            None => {
+                binary += 1;
+                continue;
+            }
+        };
+        total += size;
+        files.push(InputFile {
+            path: display_path(&path),
+            content,
+        });
+    }
+    if binary > 0 {
+        let noun = if binary == 1 { "file" } else { "files" };
+        eprintln!("Skipped {} binary {}", binary, noun);
+    }
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NO_LIMITS: FileLimits = FileLimits {
+        max_file_bytes: 0,
+        max_total_bytes: 0,
+    };
+
+    fn tree() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::create_dir_all(root.join("target")).unwrap();
+        fs::write(root.join(".gitignore"), "target/\n*.log\n").unwrap();
+        fs::write(root.join("src/main.rs"), "fn main() {}\n").unwrap();
+        fs::write(root.join("src/lib.rs"), "pub mod a;\n").unwrap();
+        fs::write(root.join("target/out.rs"), "built").unwrap();
+        fs::write(root.join("run.log"), "log").unwrap();
+        fs::write(root.join("logo.png"), b"\x89PNG\r\n\x1a\n\0\0").unwrap();
+        dir
+    }
+
+    fn names(files: &[InputFile], root: &Path) -> Vec<String> {
+        files
+            .iter()
+            .map(|file| {
+                Path::new(&file.path)
+                    .strip_prefix(root)
+                    .unwrap()
+                    .display()
+                    .to_string()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_walk_respects_gitignore_and_skips_binaries() {
+        let dir = tree();
+        let root = dir.path().to_str().unwrap().to_string();
+        let files = collect(&[], &[root], NO_LIMITS).unwrap();
+        assert_eq!(names(&files, dir.path()), vec!["src/lib.rs", "src/main.rs"]);
+    }
+
+    #[test]
+    fn test_globs_and_named_files() {
+        let dir = tree();
+        let pattern = format!("{}/src/*.rs", dir.path().display());
+        let files = collect(&[pattern.clone(), pattern], &[], NO_LIMITS).unwrap();
+        assert_eq!(names(&files, dir.path()), vec!["src/lib.rs", "src/main.rs"]);
+
+        let missing = format!("{}/*.toml", dir.path().display());
+        assert!(collect(&[missing], &[], NO_LIMITS).is_err());
+        let png = format!("{}/logo.png", dir.path().display());
+        assert!(collect(&[png], &[], NO_LIMITS).is_err());
+    }
+
+    #[test]
+    fn test_existing_paths_are_not_globs() {
+        let dir = tree();
+        fs::create_dir_all(dir.path().join("app/[id]")).unwrap();
+        fs::write(dir.path().join("app/[id]/page.tsx"), "export {}\n").unwrap();
+        let page = format!("{}/app/[id]/page.tsx", dir.path().display());
+        let files = collect(&[page], &[], NO_LIMITS).unwrap();
+        assert_eq!(names(&files, dir.path()), vec!["app/[id]/page.tsx"]);
+    }
+
+    #[test]
+    fn test_size_limits() {
+        let dir = tree();
+        let root = dir.path().to_str().unwrap().to_string();
+        let limits = FileLimits {
+            max_file_bytes: 12,
+            max_total_bytes: 0,
+        };
+        let files = collect(&[], std::slice::from_ref(&root), limits).unwrap();
+        assert_eq!(names(&files, dir.path()), vec!["src/lib.rs"]);
+        // Named files are read whatever their size
+        let main = format!("{}/src/main.rs", root);
+        let files = collect(&[main], &[], limits).unwrap();
+        assert_eq!(files[0].content, "fn main() {}\n");
+
+        let limits = FileLimits {
+            max_file_bytes: 0,
+            max_total_bytes: 15,
+        };
+        let files = collect(&[], &[root], limits).unwrap();
+        assert_eq!(files.len(), 1);
+    }
+
+    #[test]
+    fn test_to_markdown() {
+        let file = InputFile {
+            path: "README.md".to_string(),
+            content: "Run:\n```sh\ncargo run\n```".to_string(),
+        };
+        assert_eq!(
+            file.to_markdown(),
+            "README.md\n````md\nRun:\n```sh\ncargo run\n```\n````"
+        );
+    }
+}
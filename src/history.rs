@@ -0,0 +1,281 @@
+use std::path::Path;
+use std::sync::OnceLock;
+
+use rusqlite::{params, Connection, OptionalExtension};
+
+use crate::chatgpt::response::Usage;
+use crate::chatgpt::Message;
+use crate::error::{CgipError, Result};
+
+const SCHEMA: &str = r#"
+CREATE TABLE IF NOT EXISTS messages (
+    id INTEGER PRIMARY KEY,
+    created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%SZ', 'now')),
+    exchange TEXT NOT NULL,
+    session TEXT,
+    role TEXT NOT NULL,
+    content TEXT NOT NULL,
+    message TEXT NOT NULL,
+    model TEXT,
+    prompt_tokens INTEGER,
+    completion_tokens INTEGER,
+    cwd TEXT
+);
+CREATE INDEX IF NOT EXISTS messages_exchange ON messages (exchange);
+
+CREATE VIRTUAL TABLE IF NOT EXISTS messages_fts
+    USING fts5(content, content='messages', content_rowid='id');
+
+CREATE TRIGGER IF NOT EXISTS messages_ai AFTER INSERT ON messages BEGIN
+    INSERT INTO messages_fts (rowid, content) VALUES (new.id, new.content);
+END;
+CREATE TRIGGER IF NOT EXISTS messages_ad AFTER DELETE ON messages BEGIN
+    INSERT INTO messages_fts (messages_fts, rowid, content) VALUES ('delete', old.id, old.content);
+END;
+"#;
+
+/// One stored message.
+#[derive(Debug)]
+pub struct Entry {
+    pub id: i64,
+    pub created_at: String,
+    pub session: Option<String>,
+    pub model: Option<String>,
+    pub message: Message,
+}
+
+/// A search hit with a short excerpt around the matched terms.
+#[derive(Debug)]
+pub struct SearchHit {
+    pub id: i64,
+    pub created_at: String,
+    pub session: Option<String>,
+    pub role: String,
+    pub snippet: String,
+}
+
+/// The messages of one cgip invocation share an exchange id, which ties a
+/// question to the answer that was given to it.
+fn exchange_id() -> &'static str {
+    static EXCHANGE: OnceLock<String> = OnceLock::new();
+    EXCHANGE.get_or_init(|| uuid::Uuid::new_v4().to_string())
+}
+
+fn db_error(e: rusqlite::Error) -> CgipError {
+    CgipError::Io(format!("history database: {}", e))
+}
+
+/// Every message sent and received, kept in a SQLite database with full text
+/// search. Unlike session files it is never trimmed.
+pub struct HistoryStore {
+    conn: Connection,
+}
+
+impl HistoryStore {
+    pub fn open(path: &Path) -> Result<Self> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let conn = Connection::open(path).map_err(db_error)?;
+        conn.execute_batch(SCHEMA).map_err(db_error)?;
+        Ok(HistoryStore { conn })
+    }
+
+    /// The database at `<data dir>/cgip/history.db`.
+    pub fn open_default() -> Result<Self> {
+        let dir = dirs::data_dir()
+            .ok_or_else(|| CgipError::Io("Could not find the data directory".into()))?;
+        Self::open(&dir.join("cgip").join("history.db"))
+    }
+
+    /// Stores `messages` as part of the current exchange.
+    pub fn record(
+        &self,
+        session: Option<&str>,
+        messages: &[Message],
+        model: &str,
+        usage: Option<&Usage>,
+    ) -> Result<()> {
+        let cwd = std::env::current_dir()
+            .ok()
+            .map(|dir| dir.to_string_lossy().to_string());
+
+        for msg in messages {
+            self.conn
+                .execute(
+                    "INSERT INTO messages
+                        (exchange, session, role, content, message, model,
+                         prompt_tokens, completion_tokens, cwd)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+                    params![
+                        exchange_id(),
+                        session,
+                        msg.role.as_str(),
+                        msg.content.to_string(),
+                        serde_json::to_string(msg)
+                            .map_err(|e| CgipError::Io(format!("history row: {}", e)))?,
+                        model,
+                        usage.map(|u| u.prompt_tokens as i64),
+                        usage.map(|u| u.completion_tokens as i64),
+                        cwd,
+                    ],
+                )
+                .map_err(db_error)?;
+        }
+        Ok(())
+    }
+
+    /// Finds messages containing all of `terms`, newest first.
+    pub fn search(&self, terms: &[String], limit: usize) -> Result<Vec<SearchHit>> {
+        let mut stmt = self
+            .conn
+            .prepare(
+                "SELECT m.id, m.created_at, m.session, m.role,
+                        snippet(messages_fts, 0, '[', ']', '...', 12)
+                 FROM messages_fts
+                 JOIN messages m ON m.id = messages_fts.rowid
+                 WHERE messages_fts MATCH ?1
+                 ORDER BY m.id DESC
+                 LIMIT ?2",
+            )
+            .map_err(db_error)?;
+
+        let hits = stmt
+            .query_map(params![match_query(terms), limit as i64], |row| {
+                Ok(SearchHit {
+                    id: row.get(0)?,
+                    created_at: row.get(1)?,
+                    session: row.get(2)?,
+                    role: row.get(3)?,
+                    snippet: row.get(4)?,
+                })
+            })
+            .map_err(db_error)?
+            .collect::<rusqlite::Result<Vec<_>>>()
+            .map_err(db_error)?;
+        Ok(hits)
+    }
+
+    /// The message with `id` together with the rest of its exchange, in the
+    /// order they were stored.
+    pub fn exchange(&self, id: i64) -> Result<Vec<Entry>> {
+        let exchange: Option<String> = self
+            .conn
+            .query_row(
+                "SELECT exchange FROM messages WHERE id = ?1",
+                params![id],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(db_error)?;
+        let exchange =
+            exchange.ok_or_else(|| CgipError::Usage(format!("No history entry with id {}", id)))?;
+
+        let mut stmt = self
+            .conn
+            .prepare(
+                "SELECT id, created_at, session, model, message FROM messages
+                 WHERE exchange = ?1 ORDER BY id",
+            )
+            .map_err(db_error)?;
+        let rows = stmt
+            .query_map(params![exchange], |row| {
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, Option<String>>(2)?,
+                    row.get::<_, Option<String>>(3)?,
+                    row.get::<_, String>(4)?,
+                ))
+            })
+            .map_err(db_error)?
+            .collect::<rusqlite::Result<Vec<_>>>()
+            .map_err(db_error)?;
+
+        rows.into_iter()
+            .map(|(id, created_at, session, model, message)| {
+                Ok(Entry {
+                    id,
+                    created_at,
+                    session,
+                    model,
+                    message: serde_json::from_str(&message)
+                        .map_err(|e| CgipError::Io(format!("history row {}: {}", id, e)))?,
+                })
+            })
+            .collect()
+    }
+}
+
+/// Quotes every term so punctuation in a search isn't read as FTS5 syntax.
+fn match_query(terms: &[String]) -> String {
+    terms
+        .iter()
+        .flat_map(|term| term.split_whitespace())
+        .map(|term| format!("\"{}\"", term.replace('"', "\"\"")))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chatgpt::Role;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_record_search_and_show() {
+        let temp_dir = TempDir::new().unwrap();
+        let store = HistoryStore::open(&temp_dir.path().join("history.db")).unwrap();
+
+        let usage = Usage {
+            prompt_tokens: 10,
+            completion_tokens: 5,
+            total_tokens: 15,
+        };
+        store
+            .record(
+                Some("work"),
+                &[Message::new(
+                    Role::User,
+                    "how do I undo a git rebase?".to_string(),
+                )],
+                "gpt-4o",
+                None,
+            )
+            .unwrap();
+        store
+            .record(
+                Some("work"),
+                &[Message::new(
+                    Role::Assistant,
+                    "Use git reflog to find the old HEAD.".to_string(),
+                )],
+                "gpt-4o",
+                Some(&usage),
+            )
+            .unwrap();
+
+        let hits = store.search(&["reflog".to_string()], 10).unwrap();
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].role, "assistant");
+        assert!(hits[0].snippet.contains("[reflog]"));
+
+        let exchange = store.exchange(hits[0].id).unwrap();
+        assert_eq!(exchange.len(), 2);
+        assert_eq!(exchange[0].message.role, Role::User);
+        assert_eq!(exchange[1].session.as_deref(), Some("work"));
+
+        assert!(store.search(&["rebase\"".to_string()], 10).is_ok());
+        assert!(store.exchange(999).is_err());
+    }
+
+    #[test]
+    fn test_match_query_quotes_terms() {
+        let terms = vec!["C++ AND".to_string(), "say \"hi\"".to_string()];
+        assert_eq!(
+            match_query(&terms),
+            "\"C++\" \"AND\" \"say\" \"\"\"hi\"\"\""
+        );
+    }
+}
@@ -0,0 +1,97 @@
+use std::fs;
+use std::path::Path;
+
+use base64::{engine::general_purpose, Engine as _};
+
+use crate::error::{CgipError, Result};
+
+/// The mime type of an image file going by its extension, JPEG when it is
+/// unknown.
+pub fn mime_from_extension(path: &str) -> &'static str {
+    let extension = Path::new(path)
+        .extension()
+        .and_then(|s| s.to_str())
+        .unwrap_or("jpeg")
+        .to_lowercase();
+
+    match extension.as_str() {
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        _ => "image/jpeg", // default fallback
+    }
+}
+
+/// The mime type of `bytes` when they start with the magic number of a PNG,
+/// JPEG, GIF or WebP image.
+pub fn sniff(bytes: &[u8]) -> Option<&'static str> {
+    if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some("image/png")
+    } else if bytes.starts_with(&[0xFF, 0xD8, 0xFF]) {
+        Some("image/jpeg")
+    } else if bytes.starts_with(b"GIF87a") || bytes.starts_with(b"GIF89a") {
+        Some("image/gif")
+    } else if bytes.len() >= 12 && bytes.starts_with(b"RIFF") && &bytes[8..12] == b"WEBP" {
+        Some("image/webp")
+    } else {
+        None
+    }
+}
+
+/// `bytes` as a `data:` URL, the way images are sent inline.
+pub fn data_url(mime: &str, bytes: &[u8]) -> String {
+    format!(
+        "data:{};base64,{}",
+        mime,
+        general_purpose::STANDARD.encode(bytes)
+    )
+}
+
+/// The URL to send for an image given as a path or a URL. `http(s)` and
+/// `data:` URLs are passed on as they are, files are read and sent inline.
+pub fn image_url(source: &str) -> Result<String> {
+    if ["http://", "https://", "data:"]
+        .iter()
+        .any(|scheme| source.starts_with(scheme))
+    {
+        return Ok(source.to_string());
+    }
+    if !Path::new(source).exists() {
+        return Err(CgipError::Io(format!("Image file '{}' not found", source)));
+    }
+    let bytes = fs::read(source)
+        .map_err(|e| CgipError::Io(format!("Error reading image file '{}': {}", source, e)))?;
+    Ok(data_url(mime_from_extension(source), &bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sniff() {
+        assert_eq!(sniff(b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR"), Some("image/png"));
+        assert_eq!(sniff(&[0xFF, 0xD8, 0xFF, 0xE0]), Some("image/jpeg"));
+        assert_eq!(sniff(b"GIF89a\x01\0"), Some("image/gif"));
+        assert_eq!(sniff(b"RIFF\x24\0\0\0WEBPVP8 "), Some("image/webp"));
+        assert_eq!(sniff(b"RIFF\x24\0\0\0WAVEfmt "), None);
+        assert_eq!(sniff(b"plain text"), None);
+        assert_eq!(sniff(b""), None);
+    }
+
+    #[test]
+    fn test_image_url() {
+        let url = "https://example.com/cat.png";
+        assert_eq!(image_url(url).unwrap(), url);
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("dot.PNG");
+        fs::write(&path, b"\x89PNG").unwrap();
+        assert_eq!(
+            image_url(path.to_str().unwrap()).unwrap(),
+            "data:image/png;base64,iVBORw=="
+        );
+        assert!(image_url("missing.png").is_err());
+    }
+}
@@ -0,0 +1,133 @@
+use clap::ValueEnum;
+
+use crate::chatgpt::Message;
+use crate::error::{CgipError, Result};
+use crate::utils::is_valid_yaml;
+
+/// How piped input is read.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum InputFormat {
+    /// Messages when the input is a YAML or JSON message list, text otherwise
+    #[default]
+    Auto,
+    /// Text to ask about, even when it looks like messages
+    Text,
+    /// A YAML list of messages
+    Yaml,
+    /// A JSON array of messages, as in an OpenAI request
+    Json,
+    /// One JSON message per line
+    Jsonl,
+}
+
+impl InputFormat {
+    /// Whether the input may be an image rather than text.
+    pub fn allows_images(self) -> bool {
+        matches!(self, InputFormat::Auto | InputFormat::Text)
+    }
+}
+
+/// The messages in `text` when `format` reads it as messages, `None` when it
+/// is to be sent as text. The explicit message formats fail on anything else
+/// rather than falling back to text.
+pub fn parse_messages(format: InputFormat, text: &str) -> Result<Option<Vec<Message>>> {
+    let invalid = |e: &dyn std::fmt::Display| {
+        CgipError::Usage(format!(
+            "stdin is not a list of messages in {} format: {}",
+            format_name(format),
+            e
+        ))
+    };
+    match format {
+        InputFormat::Auto if is_valid_yaml(text).unwrap_or(false) => serde_yaml::from_str(text)
+            .map(Some)
+            .map_err(|e| invalid(&e)),
+        InputFormat::Auto | InputFormat::Text => Ok(None),
+        InputFormat::Yaml => serde_yaml::from_str(text)
+            .map(Some)
+            .map_err(|e| invalid(&e)),
+        InputFormat::Json => serde_json::from_str(text)
+            .map(Some)
+            .map_err(|e| invalid(&e)),
+        InputFormat::Jsonl => text
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(i, line)| {
+                serde_json::from_str(line).map_err(|e| invalid(&format!("line {}: {}", i + 1, e)))
+            })
+            .collect::<Result<Vec<_>>>()
+            .map(Some),
+    }
+}
+
+fn format_name(format: InputFormat) -> String {
+    format
+        .to_possible_value()
+        .map(|value| value.get_name().to_uppercase())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chatgpt::{MessageContent, Role};
+
+    const OPENAI_MESSAGES: &str = r#"[
+        {"role": "developer", "content": "Answer briefly."},
+        {"role": "user", "name": "ana", "content": [
+            {"type": "text", "text": "What is this?"},
+            {"type": "image_url", "image_url": {"url": "https://example.com/a.png", "detail": "low"}}
+        ]},
+        {"role": "assistant", "content": null, "tool_calls": [
+            {"id": "call_1", "type": "function", "function": {"name": "execute", "arguments": "{\"command\":\"ls\"}"}}
+        ]},
+        {"role": "tool", "tool_call_id": "call_1", "content": "a.png"}
+    ]"#;
+
+    #[test]
+    fn test_openai_json_messages() {
+        let messages = parse_messages(InputFormat::Json, OPENAI_MESSAGES)
+            .unwrap()
+            .unwrap();
+        assert_eq!(messages.len(), 4);
+        assert_eq!(messages[0].role, Role::Developer);
+        assert_eq!(messages[1].name.as_deref(), Some("ana"));
+        assert!(matches!(&messages[1].content, MessageContent::Multi(parts) if parts.len() == 2));
+        // Settings cgip doesn't use itself are sent on unchanged
+        let resent = serde_json::to_value(&messages[1]).unwrap();
+        assert_eq!(resent["content"][1]["image_url"]["detail"], "low");
+        assert!(messages[2].content.is_empty());
+        assert_eq!(messages[2].tool_calls.as_ref().unwrap()[0].id, "call_1");
+        assert_eq!(messages[3].tool_call_id.as_deref(), Some("call_1"));
+
+        // The same messages are found without asking for JSON
+        let auto = parse_messages(InputFormat::Auto, OPENAI_MESSAGES).unwrap();
+        assert_eq!(auto.unwrap().len(), 4);
+    }
+
+    #[test]
+    fn test_jsonl_messages() {
+        let text = "{\"role\": \"user\", \"content\": \"hi\"}\n\n{\"role\": \"assistant\", \"content\": \"hello\"}\n";
+        let messages = parse_messages(InputFormat::Jsonl, text).unwrap().unwrap();
+        assert_eq!(messages[1].content.to_string(), "hello");
+
+        let err = parse_messages(
+            InputFormat::Jsonl,
+            "{\"role\": \"user\", \"content\": \"hi\"}\nnot json",
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("JSONL format: line 2"));
+    }
+
+    #[test]
+    fn test_text_is_never_messages() {
+        let yaml = "- role: user\n  content: What does this list do?\n";
+        assert!(parse_messages(InputFormat::Auto, yaml).unwrap().is_some());
+        assert!(parse_messages(InputFormat::Text, yaml).unwrap().is_none());
+        assert!(parse_messages(InputFormat::Auto, "just a question")
+            .unwrap()
+            .is_none());
+        assert!(parse_messages(InputFormat::Yaml, "just a question").is_err());
+    }
+}
@@ -1,152 +1,212 @@
-use std::str::FromStr;
-
 use args::{Args, SubCommands};
-use chatgpt::{GptClient, Message, MessageContent, Role};
+use chatgpt::{GptClient, Message, Role};
 use clap::Parser;
+use directives::RequestOptions;
+use error::Result;
+use files::FileLimits;
+use printer::DisplayFormat;
 use sub::session::{read_from_tty_context, save_to_tty_context};
-use utils::{get_file_contents_from_path, get_stdin, is_valid_yaml};
+use utils::{read_stdin_bytes, stdin_text};
 
 mod args;
+mod blocks;
 mod chat;
 mod chatgpt;
 mod config_manager;
+mod directives;
+mod error;
+mod files;
+mod history;
+mod images;
+mod input;
+mod output;
 mod printer;
+mod render;
+mod retry;
+mod shell;
 mod sub;
+mod tokens;
 mod utils;
 
-fn select_and_execute(args: Args, client: &mut GptClient) {
+fn select_and_execute(args: Args, client: &mut GptClient) -> Result<()> {
     if let Some(SubCommands::Config(config_sc)) = &args.subcmd {
-        sub::config::run(client, config_sc);
-        return;
+        return sub::config::run(client, config_sc);
+    }
+
+    if let Some(SubCommands::Help(help_sc)) = &args.subcmd {
+        return sub::help::run(help_sc);
     }
 
     if let Some(SubCommands::Upgrade(_)) = &args.subcmd {
-        if let Err(e) = sub::upgrade::run() {
-            eprintln!("Upgrade Error: {}", e);
-            std::process::exit(1);
-        }
-        return;
+        return sub::upgrade::run();
     }
 
     // Handle TTS subcommand before consuming stdin
     if let Some(SubCommands::Tts(tts_sc)) = &args.subcmd {
-        if let Err(e) = sub::tts::run(tts_sc) {
-            eprintln!("TTS Error: {}", e);
-            std::process::exit(1);
-        }
-        return;
+        return sub::tts::run(tts_sc, &client.config_manager.config);
     }
 
     if let Some(SubCommands::Embedding(embed_sc)) = &args.subcmd {
-        if let Err(e) = sub::embedding::run(embed_sc) {
-            eprintln!("Embedding Error: {}", e);
-            std::process::exit(1);
-        }
-        return;
+        return sub::embedding::run(embed_sc, &client.config_manager.config);
+    }
+
+    if let Some(SubCommands::History(history_sc)) = &args.subcmd {
+        return sub::history::run(history_sc, &client.config_manager);
+    }
+
+    if let Some(SubCommands::Chat(chat_sc)) = &args.subcmd {
+        return sub::chat::run(chat_sc, &args, client);
+    }
+
+    if let Some(SubCommands::Retry(retry_sc)) = &args.subcmd {
+        return sub::retry::run(retry_sc, &args, client);
+    }
+
+    if let Some(SubCommands::Cmd(cmd_sc)) = &args.subcmd {
+        return sub::cmd::run(cmd_sc, &args, client);
     }
 
     if let Some(SubCommands::Agent(agent_sc)) = &args.subcmd {
-        sub::agent::run(agent_sc, client);
-        return;
+        return sub::agent::run(agent_sc, client);
     }
 
-    if !args.no_session {
+    // Directives may start the piped input and the query, the query's
+    // taking precedence
+    let mut options = RequestOptions::default();
+    // Images piped in are sent with the query, messages are added to the
+    // context and anything else is sent as text
+    let stdin_bytes = read_stdin_bytes();
+    let stdin_image = (!args.raw_stdin && args.input_format.allows_images())
+        .then(|| images::sniff(&stdin_bytes))
+        .flatten()
+        .map(|mime| images::data_url(mime, &stdin_bytes));
+    let mut stdin_text = match stdin_image {
+        Some(_) => String::new(),
+        None => stdin_text(&stdin_bytes),
+    };
+    let stdin_messages = if args.raw_stdin || stdin_text.is_empty() {
+        None
+    } else {
+        input::parse_messages(args.input_format, &stdin_text)?
+    };
+    if stdin_messages.is_none() && !args.raw_stdin {
+        let (stdin_options, text) = directives::parse_piped(&stdin_text)?;
+        options.merge(stdin_options);
+        stdin_text = text;
+    }
+    let mut query = None;
+    if let Some(text) = &args.query {
+        let (query_options, text) = directives::parse(text)?;
+        options.merge(query_options);
+        query = Some(text).filter(|text| !text.is_empty());
+    }
+    options.web_search |= args.search;
+    let no_session = args.no_session || options.no_session;
+    let option_files = std::mem::take(&mut options.files);
+    client.options = options;
+
+    let session_start = client.messages.len();
+    if !no_session {
+        // Tool calls and their results are kept along with the text
         let tty_context = read_from_tty_context();
-        for msg in tty_context {
-            let role = Role::from_str(msg.role.as_str()).expect("Could not convert role");
-            client.add_message(role, msg.content.to_string());
-        }
+        client
+            .messages
+            .extend(tty_context.iter().map(Message::without_meta));
     }
 
+    let session_end = client.messages.len();
+
     let mut messages_to_save = Vec::new();
-    let stdin_text = get_stdin();
-    if !stdin_text.is_empty() {
-        if is_valid_yaml(&stdin_text).unwrap() {
-            let messages: Vec<Message> = serde_yaml::from_str(&stdin_text).unwrap();
-            for msg in messages {
-                let role = Role::from_str(msg.role.as_str()).expect("could not convert role");
-                let content_text = msg.content.to_string();
-                client.add_message(role, content_text);
-                messages_to_save.push(msg);
-            }
-        } else {
-            client.add_message(chatgpt::Role::User, stdin_text.clone());
-            messages_to_save.push(Message {
-                role: Role::User.to_string().to_lowercase(),
-                name: None,
-                tool_call_id: None,
-                tool_calls: None,
-                content: crate::chatgpt::MessageContent::Text(stdin_text),
-            });
-        }
+    if let Some(messages) = stdin_messages {
+        client
+            .messages
+            .extend(messages.iter().map(Message::without_meta));
+        messages_to_save.extend(messages);
+    } else if !stdin_text.is_empty() {
+        client.add_message(chatgpt::Role::User, stdin_text.clone());
+        messages_to_save.push(Message::new(Role::User, stdin_text));
     }
 
     if let Some(SubCommands::Session(subcmd)) = &args.subcmd {
-        let mut printer = printer::Printer::Console(printer::ConsolePrinter {});
-        sub::session::run(subcmd, &client.messages, &mut printer);
-        return;
+        let mut printer = args.format.unwrap_or_else(DisplayFormat::detect).printer();
+        return sub::session::run(subcmd, &client.messages, printer.as_mut());
     }
 
     if let Some(SubCommands::Image(image_sc)) = &args.subcmd {
-        sub::image::run(image_sc, client);
-        return;
+        return sub::image::run(image_sc, client);
     }
 
-    if let Some(query) = args.query.clone() {
+    if stdin_image.is_some() || !args.image.is_empty() {
+        // The images go in one message along with the query
+        let image_urls = stdin_image
+            .into_iter()
+            .map(Ok)
+            .chain(args.image.iter().map(|source| images::image_url(source)))
+            .collect::<Result<Vec<_>>>()?;
+        client.add_image_message(chatgpt::Role::User, query, image_urls);
+        let message = client.messages.last().unwrap().clone();
+        messages_to_save.push(message);
+    } else if let Some(query) = query {
         client.add_message(chatgpt::Role::User, query.clone());
         // save message to context
-        let message = Message {
-            role: Role::User.to_string().to_lowercase(),
-            name: None,
-            tool_call_id: None,
-            tool_calls: None,
-            content: crate::chatgpt::MessageContent::Text(query.clone()),
-        };
-        messages_to_save.push(message);
+        messages_to_save.push(Message::new(Role::User, query));
     }
 
-    if let Some(file) = args.file.clone() {
-        let question = get_file_contents_from_path(file);
+    let patterns: Vec<String> = args.file.iter().chain(&option_files).cloned().collect();
+    let limits = FileLimits::from_config(&client.config_manager.config);
+    for file in files::collect(&patterns, &args.dir, limits)? {
+        let question = file.to_markdown();
         client.add_message(chatgpt::Role::User, question.clone());
         // save message to context
-        let message = Message {
-            role: Role::User.to_string().to_lowercase(),
-            name: None,
-            tool_call_id: None,
-            tool_calls: None,
-            content: crate::chatgpt::MessageContent::Text(question.clone()),
-        };
-        messages_to_save.push(message);
+        messages_to_save.push(Message::new(Role::User, question));
     }
 
-    if args.search {
-        if let Some(last_msg) = client.messages.last_mut() {
-            if last_msg.role == "user" {
-                if let MessageContent::Text(ref mut content) = last_msg.content {
-                    *content = format!("/search {}", content.trim());
-                }
-            }
-        }
+    // Make room for the new input by dropping the oldest session messages
+    let model = client
+        .options
+        .model
+        .clone()
+        .or_else(|| args.model.clone())
+        .unwrap_or_else(|| client.config_manager.config.model.clone());
+    let budget = tokens::fit_to_budget(
+        &mut client.messages,
+        session_start..session_end,
+        &model,
+        client.config_manager.config.max_input_tokens,
+    );
+
+    if let Some(SubCommands::Tokens(_)) = &args.subcmd {
+        sub::tokens::run(&client.messages, &model, budget);
+        return Ok(());
     }
 
     if let Some(SubCommands::View(_v_sc)) = &args.subcmd {
-        sub::view::run(&client.messages);
-        return;
+        let mut printer = args.format.unwrap_or(DisplayFormat::Markdown).printer();
+        sub::view::run(&client.messages, printer.as_mut());
+        return Ok(());
     }
 
-    if !args.no_session {
-        save_to_tty_context(&client.config_manager, messages_to_save);
+    if !no_session {
+        save_to_tty_context(&client.config_manager, messages_to_save, None);
     }
 
-    chat::run(&args, client);
+    chat::run(&args, client)
 }
 
 fn main() {
     let args = Args::parse();
-    let mut client = match &args.system_prompt {
+    let output = args.output;
+    let client = match &args.system_prompt {
         Some(prompt) => GptClient::new_with_system_prompt(prompt.clone()),
         None => GptClient::new(args.jarjar),
     };
 
-    select_and_execute(args, &mut client)
+    if let Err(e) = client.and_then(|mut client| select_and_execute(args, &mut client)) {
+        if output.is_json() {
+            output::print(output, &e.to_json());
+        } else {
+            eprintln!("cgip: {}", e);
+        }
+        std::process::exit(e.exit_code());
+    }
 }
@@ -0,0 +1,88 @@
+use clap::ValueEnum;
+use serde::Serialize;
+
+use crate::chatgpt::response::Usage;
+
+/// How results are written to stdout.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// The reply as text, rendered on a terminal
+    #[default]
+    Text,
+    /// One pretty printed JSON document
+    Json,
+    /// One compact JSON object per line
+    Jsonl,
+}
+
+impl OutputFormat {
+    pub fn is_json(self) -> bool {
+        self != OutputFormat::Text
+    }
+}
+
+/// A reply and what is known about how it was produced.
+#[derive(Debug, Serialize)]
+pub struct Reply {
+    pub content: String,
+    pub model: String,
+    /// The provider's id for the completion, when it reports one.
+    pub id: Option<String>,
+    pub finish_reason: Option<String>,
+    pub usage: Option<Usage>,
+    /// The session the exchange was saved to, if any.
+    pub session: Option<String>,
+    pub elapsed_ms: u128,
+}
+
+/// Serializes a single result. `Text` is treated like `Jsonl`.
+pub fn to_string<T: Serialize>(format: OutputFormat, value: &T) -> String {
+    let json = match format {
+        OutputFormat::Json => serde_json::to_string_pretty(value),
+        OutputFormat::Text | OutputFormat::Jsonl => serde_json::to_string(value),
+    };
+    json.expect("results always serialize")
+}
+
+/// Prints a single result.
+pub fn print<T: Serialize>(format: OutputFormat, value: &T) {
+    println!("{}", to_string(format, value));
+}
+
+/// Prints a list of results, as an array for `Json` and one line each for
+/// `Jsonl`.
+pub fn print_all<T: Serialize>(format: OutputFormat, values: &[T]) {
+    match format {
+        OutputFormat::Json => print(format, &values),
+        OutputFormat::Text | OutputFormat::Jsonl => {
+            for value in values {
+                print(format, value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reply_json() {
+        let reply = Reply {
+            content: "hi".to_string(),
+            model: "gpt-4o-2024-08-06".to_string(),
+            id: Some("chatcmpl-1".to_string()),
+            finish_reason: Some("stop".to_string()),
+            usage: None,
+            session: None,
+            elapsed_ms: 12,
+        };
+        assert_eq!(
+            to_string(OutputFormat::Jsonl, &reply),
+            r#"{"content":"hi","model":"gpt-4o-2024-08-06","id":"chatcmpl-1","finish_reason":"stop","usage":null,"session":null,"elapsed_ms":12}"#
+        );
+        assert!(
+            to_string(OutputFormat::Json, &reply).contains("\n  \"model\": \"gpt-4o-2024-08-06\"")
+        );
+    }
+}
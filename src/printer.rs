@@ -1,66 +1,324 @@
-use std::str::FromStr;
+use std::io::{self, Write};
 
-use crate::chatgpt::Role;
+use clap::ValueEnum;
+
+use crate::chatgpt::{Message, Role};
+use crate::render;
+use crate::utils::{escape_html, markdown_to_html};
 
 enum Colors {
     Blue,
     Red,
     Green,
+    Yellow,
 }
 
 impl Colors {
-    fn get_color_prefix(&self) -> &str {
+    fn get_color_prefix(&self) -> &'static str {
         match self {
             Colors::Blue => "\x1b[34m",
             Colors::Red => "\x1b[31m",
             Colors::Green => "\x1b[32m",
+            Colors::Yellow => "\x1b[33m",
         }
     }
+
+    fn for_role(role: Role) -> Self {
+        match role {
+            Role::User => Colors::Blue,
+            Role::System | Role::Developer => Colors::Green,
+            Role::Assistant => Colors::Red,
+            Role::Tool => Colors::Yellow,
+        }
+    }
+}
+
+/// How messages and replies are shown.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum DisplayFormat {
+    /// `role: content` lines without colors
+    Plain,
+    /// Colored roles, with replies rendered for the terminal
+    Color,
+    /// `**role**: content` paragraphs
+    Markdown,
+    /// A JSON array of `{role, content}` objects
+    Json,
+    /// An HTML fragment with one article per message
+    Html,
 }
 
+impl DisplayFormat {
+    /// Colored when stdout is a terminal that allows colors, see
+    /// `render::is_enabled`, and plain otherwise.
+    pub fn detect() -> Self {
+        if render::is_enabled() {
+            DisplayFormat::Color
+        } else {
+            DisplayFormat::Plain
+        }
+    }
 
-pub struct ConsolePrinter {
+    /// A printer for this format that writes to stdout.
+    pub fn printer(self) -> Box<dyn Printer> {
+        let out = io::stdout();
+        match self {
+            DisplayFormat::Plain => Box::new(PlainPrinter { out }),
+            DisplayFormat::Color => Box::new(ColoredPrinter { out }),
+            DisplayFormat::Markdown => Box::new(MarkdownPrinter { out }),
+            DisplayFormat::Json => Box::new(JsonPrinter::new(out)),
+            DisplayFormat::Html => Box::new(HtmlPrinter { out }),
+        }
+    }
+}
+
+/// The name shown for the author of a message. Tool results also name the
+/// call they answer.
+fn role_label(msg: &Message) -> String {
+    match &msg.tool_call_id {
+        Some(id) if msg.role == Role::Tool => format!("{} ({})", msg.role, id),
+        _ => msg.role.to_string(),
+    }
+}
+
+/// The text shown for a message: its content followed by its tool calls, one
+/// per line.
+fn message_text(msg: &Message) -> String {
+    let mut text = msg.content.to_string();
+    for call in msg.tool_calls.iter().flatten() {
+        if !text.is_empty() {
+            text.push('\n');
+        }
+        text.push_str(&format!(
+            "-> {}({})",
+            call.function.name, call.function.arguments
+        ));
+    }
+    text
+}
+
+/// Writes messages in one of the `DisplayFormat`s. Write errors, such as a
+/// closed pipe, are ignored.
+pub trait Printer {
+    fn print_message(&mut self, msg: &Message);
+
+    /// Prints a reply on its own, as `cgip` does after a query.
+    fn print_reply(&mut self, content: &str) {
+        self.print_message(&Message::new(Role::Assistant, content.to_string()));
+    }
+
+    /// Writes anything held back until all messages are known.
+    fn finish(&mut self) {}
+}
+
+pub struct PlainPrinter<W: Write> {
+    pub out: W,
+}
+
+impl<W: Write> Printer for PlainPrinter<W> {
+    fn print_message(&mut self, msg: &Message) {
+        let _ = writeln!(self.out, "{}: {}", role_label(msg), message_text(msg));
+    }
+
+    fn print_reply(&mut self, content: &str) {
+        let _ = writeln!(self.out, "{}", content);
+    }
+}
+
+pub struct ColoredPrinter<W: Write> {
+    pub out: W,
+}
+
+impl<W: Write> Printer for ColoredPrinter<W> {
+    fn print_message(&mut self, msg: &Message) {
+        let color_prefix = Colors::for_role(msg.role).get_color_prefix();
+        let _ = writeln!(
+            self.out,
+            "{}{}\x1b[0m: {}",
+            color_prefix,
+            role_label(msg),
+            message_text(msg)
+        );
+    }
+
+    fn print_reply(&mut self, content: &str) {
+        let _ = write!(
+            self.out,
+            "{}",
+            render::render(content, render::terminal_width())
+        );
+    }
 }
 
-impl ConsolePrinter {
-    fn print_message(&self, role: &str, content: &str) {
-        let role = Role::from_str(role).expect("could not convert role");
-        let role_str = role.to_string();
-        let color_prefix = match role {
-            Role::User => Colors::Blue.get_color_prefix(),
-            Role::System => Colors::Green.get_color_prefix(),
-            Role::Assistant => Colors::Red.get_color_prefix(),
+pub struct MarkdownPrinter<W: Write> {
+    pub out: W,
+}
+
+impl<W: Write> Printer for MarkdownPrinter<W> {
+    fn print_message(&mut self, msg: &Message) {
+        let _ = write!(
+            self.out,
+            "**{}**: {}\n\n",
+            role_label(msg),
+            message_text(msg)
+        );
+    }
+
+    fn print_reply(&mut self, content: &str) {
+        let _ = writeln!(self.out, "{}", content);
+    }
+}
+
+/// Collects the messages and prints them as one array when finished, in the
+/// same shape cgip reads as input.
+pub struct JsonPrinter<W: Write> {
+    pub out: W,
+    messages: Vec<Message>,
+}
+
+impl<W: Write> JsonPrinter<W> {
+    pub fn new(out: W) -> Self {
+        JsonPrinter {
+            out,
+            messages: Vec::new(),
+        }
+    }
+}
+
+impl<W: Write> Printer for JsonPrinter<W> {
+    fn print_message(&mut self, msg: &Message) {
+        self.messages.push(msg.without_meta());
+    }
+
+    fn finish(&mut self) {
+        let messages = std::mem::take(&mut self.messages);
+        let json = serde_json::to_string_pretty(&messages).expect("messages always serialize");
+        let _ = writeln!(self.out, "{}", json);
+    }
+}
+
+pub struct HtmlPrinter<W: Write> {
+    pub out: W,
+}
+
+impl<W: Write> Printer for HtmlPrinter<W> {
+    /// Replies are rendered as Markdown, other messages are shown as
+    /// preformatted text, as in `session export`.
+    fn print_message(&mut self, msg: &Message) {
+        let content = msg.content.to_string();
+        let mut html = if msg.role == Role::Assistant {
+            markdown_to_html(&content)
+        } else {
+            format!("<pre class=\"plain\">{}</pre>\n", escape_html(&content))
         };
-        println!("{}{}\x1b[0m: {}", color_prefix, role_str, content);
+        for call in msg.tool_calls.iter().flatten() {
+            html.push_str(&format!(
+                "<pre class=\"tool-call\">{}({})</pre>\n",
+                escape_html(&call.function.name),
+                escape_html(&call.function.arguments)
+            ));
+        }
+        let _ = write!(
+            self.out,
+            "<article class=\"message {role}\">\n<div class=\"role\">{label}</div>\n<div class=\"content\">\n{html}</div>\n</article>\n",
+            role = msg.role,
+            label = escape_html(&role_label(msg)),
+            html = html
+        );
     }
 }
 
+#[cfg(test)]
 pub struct MockPrinter {
     pub messages: Vec<(String, String)>,
 }
 
-#[allow(dead_code)]
+#[cfg(test)]
 impl MockPrinter {
     pub fn new() -> Self {
-        MockPrinter { messages: Vec::new() }
+        MockPrinter {
+            messages: Vec::new(),
+        }
     }
+}
 
-    fn print_message(&mut self, role: &str, content: &str) {
-        self.messages.push((role.to_string(), content.to_string()));
+#[cfg(test)]
+impl Printer for MockPrinter {
+    fn print_message(&mut self, msg: &Message) {
+        self.messages
+            .push((msg.role.to_string(), msg.content.to_string()));
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
 
-pub enum Printer<'a> {
-    Console(ConsolePrinter),
-    #[allow(dead_code)]
-    Mock(&'a mut MockPrinter),
-}
-impl <'a> Printer<'a> {
-    pub fn print_message(&mut self, role: &str, content: &str) {
-        match self {
-            Printer::Console(console_printer) => console_printer.print_message(role, content),
-            Printer::Mock(mock_printer) => mock_printer.print_message(role, content),
-        }
+    use super::*;
+    use crate::chatgpt::message::ToolCall;
+
+    fn print(printer: &mut dyn Printer) {
+        printer.print_message(&Message::new(Role::User, "<b>hi</b>".to_string()));
+        let mut call = Message::new(Role::Assistant, String::new());
+        call.tool_calls = Some(vec![ToolCall::new(
+            "call_1",
+            "execute",
+            &json!({"command": "ls"}),
+        )]);
+        printer.print_message(&call);
+        let mut result = Message::new(Role::Tool, "42".to_string());
+        result.tool_call_id = Some("call_1".to_string());
+        printer.print_message(&result);
+        printer.finish();
+    }
+
+    #[test]
+    fn test_formats() {
+        let mut plain = PlainPrinter { out: Vec::new() };
+        print(&mut plain);
+        assert_eq!(
+            String::from_utf8(plain.out).unwrap(),
+            "user: <b>hi</b>\nassistant: -> execute({\"command\":\"ls\"})\ntool (call_1): 42\n"
+        );
+
+        let mut colored = ColoredPrinter { out: Vec::new() };
+        print(&mut colored);
+        let colored = String::from_utf8(colored.out).unwrap();
+        assert!(colored.starts_with("\x1b[34muser\x1b[0m: <b>hi</b>\n"));
+        assert!(colored.ends_with("\x1b[33mtool (call_1)\x1b[0m: 42\n"));
+
+        let mut markdown = MarkdownPrinter { out: Vec::new() };
+        print(&mut markdown);
+        assert!(String::from_utf8(markdown.out)
+            .unwrap()
+            .starts_with("**user**: <b>hi</b>\n\n**assistant**: -> execute("));
+
+        let mut json = JsonPrinter::new(Vec::new());
+        print(&mut json);
+        let messages: Vec<Message> = serde_json::from_slice(&json.out).unwrap();
+        assert_eq!(messages[1].tool_calls.as_ref().unwrap()[0].id, "call_1");
+        assert_eq!(messages[2].role, Role::Tool);
+
+        let mut html = HtmlPrinter { out: Vec::new() };
+        print(&mut html);
+        let html = String::from_utf8(html.out).unwrap();
+        assert!(html.contains("<article class=\"message user\">"));
+        assert!(html.contains("<pre class=\"plain\">&lt;b&gt;hi&lt;/b&gt;</pre>"));
+        assert!(html.contains(
+            "<pre class=\"tool-call\">execute({&quot;command&quot;:&quot;ls&quot;})</pre>"
+        ));
+    }
+
+    #[test]
+    fn test_replies() {
+        let mut plain = PlainPrinter { out: Vec::new() };
+        plain.print_reply("# Title");
+        assert_eq!(String::from_utf8(plain.out).unwrap(), "# Title\n");
+
+        let mut json = JsonPrinter::new(Vec::new());
+        json.print_reply("ok");
+        json.finish();
+        let value: serde_json::Value = serde_json::from_slice(&json.out).unwrap();
+        assert_eq!(value, json!([{ "role": "assistant", "content": "ok" }]));
     }
 }
@@ -0,0 +1,725 @@
+use std::env;
+use std::sync::OnceLock;
+
+use atty::Stream;
+use pulldown_cmark::{Alignment, CodeBlockKind, Event, HeadingLevel, Options, Parser, Tag, TagEnd};
+use syntect::easy::HighlightLines;
+use syntect::highlighting::{Theme, ThemeSet};
+use syntect::parsing::SyntaxSet;
+use syntect::util::{as_24_bit_terminal_escaped, LinesWithEndings};
+use unicode_width::UnicodeWidthStr;
+
+const RESET: &str = "\x1b[0m";
+const BOLD: &str = "\x1b[1m";
+const ITALIC: &str = "\x1b[3m";
+const STRIKE: &str = "\x1b[9m";
+const DIM: &str = "\x1b[2m";
+const CODE: &str = "\x1b[36m";
+const LINK: &str = "\x1b[4;34m";
+const HEADING: &str = "\x1b[1;35m";
+const MAIN_HEADING: &str = "\x1b[1;4;35m";
+
+/// Used when the terminal size can't be read.
+const DEFAULT_WIDTH: usize = 80;
+/// Table columns are never squeezed below this width.
+const MIN_COLUMN_WIDTH: usize = 6;
+
+/// Whether replies should be rendered for the terminal: stdout has to be a
+/// terminal that isn't `dumb`, and `NO_COLOR` must not be set.
+pub fn is_enabled() -> bool {
+    atty::is(Stream::Stdout)
+        && env::var_os("NO_COLOR").is_none_or(|value| value.is_empty())
+        && env::var("TERM").map_or(true, |term| term != "dumb")
+}
+
+pub fn terminal_width() -> usize {
+    terminal_size::terminal_size()
+        .map(|(width, _)| width.0 as usize)
+        .filter(|width| *width > 0)
+        .unwrap_or(DEFAULT_WIDTH)
+}
+
+fn syntaxes() -> &'static SyntaxSet {
+    static SYNTAXES: OnceLock<SyntaxSet> = OnceLock::new();
+    SYNTAXES.get_or_init(SyntaxSet::load_defaults_newlines)
+}
+
+fn theme() -> &'static Theme {
+    static THEME: OnceLock<Theme> = OnceLock::new();
+    THEME.get_or_init(|| {
+        let mut themes = ThemeSet::load_defaults();
+        themes
+            .themes
+            .remove("base16-ocean.dark")
+            .unwrap_or_default()
+    })
+}
+
+/// Renders Markdown as styled text for a terminal of the given width.
+pub fn render(markdown: &str, width: usize) -> String {
+    let mut options = Options::empty();
+    options.insert(Options::ENABLE_TABLES);
+    options.insert(Options::ENABLE_STRIKETHROUGH);
+    options.insert(Options::ENABLE_TASKLISTS);
+
+    let mut writer = Writer::new(width);
+    for event in Parser::new_ext(markdown, options) {
+        writer.event(event);
+    }
+    writer.finish()
+}
+
+/// A run of text with one style, possibly part of a link.
+#[derive(Debug, Clone)]
+struct Piece {
+    text: String,
+    style: String,
+    link: Option<String>,
+}
+
+impl Piece {
+    fn styled(&self, text: &str) -> String {
+        let styled = if self.style.is_empty() {
+            text.to_string()
+        } else {
+            format!("{}{}{}", self.style, text, RESET)
+        };
+        match &self.link {
+            // OSC 8 makes the text a clickable link in terminals that know it
+            Some(url) => format!("\x1b]8;;{}\x1b\\{}\x1b]8;;\x1b\\", url, styled),
+            None => styled,
+        }
+    }
+}
+
+/// Splits pieces into words and wraps them to `width` columns. `first` and
+/// `rest` are the prefixes of the first and following lines, with their
+/// display widths.
+fn wrap(pieces: &[Piece], width: usize, first: (&str, usize), rest: (&str, usize)) -> Vec<String> {
+    // Each word is a list of (text, piece) parts, as one word may change style
+    enum Token {
+        Word(Vec<(String, usize)>),
+        Break,
+    }
+
+    let mut tokens = Vec::new();
+    let mut word: Vec<(String, usize)> = Vec::new();
+    for (i, piece) in pieces.iter().enumerate() {
+        let mut part = String::new();
+        for c in piece.text.chars() {
+            if c.is_whitespace() {
+                if !part.is_empty() {
+                    word.push((std::mem::take(&mut part), i));
+                }
+                if !word.is_empty() {
+                    tokens.push(Token::Word(std::mem::take(&mut word)));
+                }
+                if c == '\n' {
+                    tokens.push(Token::Break);
+                }
+            } else {
+                part.push(c);
+            }
+        }
+        if !part.is_empty() {
+            word.push((part, i));
+        }
+    }
+    if !word.is_empty() {
+        tokens.push(Token::Word(word));
+    }
+
+    let mut lines = Vec::new();
+    let mut line = first.0.to_string();
+    let mut line_width = first.1;
+    let mut empty = true;
+    for token in tokens {
+        match token {
+            Token::Break => {
+                lines.push(std::mem::replace(&mut line, rest.0.to_string()));
+                line_width = rest.1;
+                empty = true;
+            }
+            Token::Word(parts) => {
+                let word_width: usize = parts.iter().map(|(text, _)| text.width()).sum();
+                if !empty && line_width + 1 + word_width > width {
+                    lines.push(std::mem::replace(&mut line, rest.0.to_string()));
+                    line_width = rest.1;
+                    empty = true;
+                }
+                if !empty {
+                    line.push(' ');
+                    line_width += 1;
+                }
+                for (text, piece) in parts {
+                    line.push_str(&pieces[piece].styled(&text));
+                }
+                line_width += word_width;
+                empty = false;
+            }
+        }
+    }
+    if !empty || lines.is_empty() {
+        lines.push(line);
+    }
+    lines
+}
+
+fn visible_width(pieces: &[Piece]) -> usize {
+    wrap(pieces, usize::MAX, ("", 0), ("", 0))
+        .iter()
+        .map(|line| strip_ansi(line).width())
+        .max()
+        .unwrap_or(0)
+}
+
+/// Removes escape sequences, leaving the text as it appears on screen.
+pub fn strip_ansi(text: &str) -> String {
+    let mut plain = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\x1b' {
+            plain.push(c);
+            continue;
+        }
+        match chars.next() {
+            // CSI: parameters up to a final letter
+            Some('[') => {
+                for c in chars.by_ref() {
+                    if c.is_ascii_alphabetic() {
+                        break;
+                    }
+                }
+            }
+            // OSC: up to the string terminator ESC \ or BEL
+            Some(']') => {
+                while let Some(c) = chars.next() {
+                    if c == '\x07' || (c == '\x1b' && chars.next_if_eq(&'\\').is_some()) {
+                        break;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    plain
+}
+
+struct Table {
+    alignments: Vec<Alignment>,
+    rows: Vec<Vec<Vec<Piece>>>,
+    head_rows: usize,
+}
+
+/// Turns Markdown events into terminal lines.
+struct Writer {
+    width: usize,
+    lines: Vec<String>,
+    /// Set when the next line starts a new block and needs a blank line
+    /// above it.
+    gap: bool,
+    /// Inline text of the block being read.
+    pieces: Vec<Piece>,
+    styles: Vec<&'static str>,
+    link: Option<String>,
+    quote_depth: usize,
+    /// The next number of each open list, `None` for bullet lists.
+    lists: Vec<Option<u64>>,
+    /// Marker widths of the open list items.
+    items: Vec<usize>,
+    /// The marker of the innermost item, until its first line is written.
+    marker: Option<String>,
+    code: Option<(String, String)>,
+    table: Option<Table>,
+}
+
+impl Writer {
+    fn new(width: usize) -> Self {
+        Writer {
+            width: width.max(20),
+            lines: Vec::new(),
+            gap: false,
+            pieces: Vec::new(),
+            styles: Vec::new(),
+            link: None,
+            quote_depth: 0,
+            lists: Vec::new(),
+            items: Vec::new(),
+            marker: None,
+            code: None,
+            table: None,
+        }
+    }
+
+    fn start_block(&mut self) {
+        if !self.lines.is_empty() && self.lists.is_empty() {
+            self.gap = true;
+        }
+    }
+
+    /// The prefixes of the first and following lines of the current block.
+    fn prefixes(&mut self) -> ((String, usize), (String, usize)) {
+        let quote = format!("{}│{} ", DIM, RESET).repeat(self.quote_depth);
+        let quote_width = 2 * self.quote_depth;
+        let indent: usize = self.items.iter().sum();
+        let rest = (
+            format!("{}{}", quote, " ".repeat(indent)),
+            quote_width + indent,
+        );
+        let first = match self.marker.take() {
+            Some(marker) => {
+                let outer = indent - self.items.last().copied().unwrap_or(0);
+                (
+                    format!("{}{}{}", quote, " ".repeat(outer), marker),
+                    quote_width + indent,
+                )
+            }
+            None => rest.clone(),
+        };
+        (first, rest)
+    }
+
+    fn push_line(&mut self, line: String) {
+        if self.gap {
+            self.lines.push(String::new());
+            self.gap = false;
+        }
+        self.lines.push(line);
+    }
+
+    fn flush_text(&mut self) {
+        if self.pieces.iter().all(|piece| piece.text.trim().is_empty()) {
+            self.pieces.clear();
+            return;
+        }
+        let pieces = std::mem::take(&mut self.pieces);
+        let (first, rest) = self.prefixes();
+        for line in wrap(&pieces, self.width, (&first.0, first.1), (&rest.0, rest.1)) {
+            self.push_line(line);
+        }
+    }
+
+    fn text(&mut self, text: &str) {
+        if let Some((_, code)) = &mut self.code {
+            code.push_str(text);
+            return;
+        }
+        let piece = Piece {
+            text: text.to_string(),
+            style: self.styles.concat(),
+            link: self.link.clone(),
+        };
+        self.pieces.push(piece);
+    }
+
+    fn event(&mut self, event: Event) {
+        match event {
+            Event::Start(tag) => self.start(tag),
+            Event::End(tag) => self.end(tag),
+            Event::Text(text) => self.text(&text),
+            Event::Code(code) => {
+                self.styles.push(CODE);
+                self.text(&code);
+                self.styles.pop();
+            }
+            Event::InlineHtml(html) | Event::Html(html) => self.text(&html),
+            Event::SoftBreak => self.text(" "),
+            Event::HardBreak => self.text("\n"),
+            Event::Rule => {
+                self.flush_text();
+                self.start_block();
+                let line = format!("{}{}{}", DIM, "─".repeat(self.width), RESET);
+                self.push_line(line);
+            }
+            Event::TaskListMarker(done) => self.text(if done { "[x] " } else { "[ ] " }),
+            _ => {}
+        }
+    }
+
+    fn start(&mut self, tag: Tag) {
+        match tag {
+            Tag::Paragraph => self.start_block(),
+            Tag::Heading { level, .. } => {
+                self.flush_text();
+                self.start_block();
+                let style = if level <= HeadingLevel::H2 {
+                    MAIN_HEADING
+                } else {
+                    HEADING
+                };
+                self.styles.push(style);
+            }
+            Tag::BlockQuote(_) => {
+                self.flush_text();
+                self.start_block();
+                self.quote_depth += 1;
+            }
+            Tag::CodeBlock(kind) => {
+                self.flush_text();
+                self.start_block();
+                let lang = match kind {
+                    CodeBlockKind::Fenced(info) => {
+                        info.split_whitespace().next().unwrap_or("").to_string()
+                    }
+                    CodeBlockKind::Indented => String::new(),
+                };
+                self.code = Some((lang, String::new()));
+            }
+            Tag::List(start) => {
+                // Text of the enclosing item goes before the nested list
+                self.flush_text();
+                self.start_block();
+                self.lists.push(start);
+            }
+            Tag::Item => {
+                self.flush_text();
+                let marker = match self.lists.last_mut() {
+                    Some(Some(number)) => {
+                        *number += 1;
+                        format!("{}. ", *number - 1)
+                    }
+                    _ => "• ".to_string(),
+                };
+                self.items.push(marker.width());
+                self.marker = Some(marker);
+            }
+            Tag::Table(alignments) => {
+                self.flush_text();
+                self.start_block();
+                self.table = Some(Table {
+                    alignments,
+                    rows: Vec::new(),
+                    head_rows: 0,
+                });
+            }
+            Tag::TableHead | Tag::TableRow => {
+                if let Some(table) = &mut self.table {
+                    table.rows.push(Vec::new());
+                }
+            }
+            Tag::TableCell => self.pieces.clear(),
+            Tag::Emphasis => self.styles.push(ITALIC),
+            Tag::Strong => self.styles.push(BOLD),
+            Tag::Strikethrough => self.styles.push(STRIKE),
+            Tag::Link { dest_url, .. } => {
+                self.styles.push(LINK);
+                self.link = Some(dest_url.to_string());
+            }
+            Tag::Image { dest_url, .. } => {
+                self.styles.push(LINK);
+                self.link = Some(dest_url.to_string());
+                self.text("🖼 ");
+            }
+            _ => {}
+        }
+    }
+
+    fn end(&mut self, tag: TagEnd) {
+        match tag {
+            TagEnd::Paragraph => self.flush_text(),
+            TagEnd::Heading(_) => {
+                self.flush_text();
+                self.styles.pop();
+            }
+            TagEnd::BlockQuote(_) => {
+                self.flush_text();
+                self.quote_depth -= 1;
+            }
+            TagEnd::CodeBlock => {
+                if let Some((lang, code)) = self.code.take() {
+                    self.code_block(&lang, &code);
+                }
+            }
+            TagEnd::List(_) => {
+                self.flush_text();
+                self.lists.pop();
+            }
+            TagEnd::Item => {
+                self.flush_text();
+                self.items.pop();
+                self.marker = None;
+            }
+            TagEnd::TableHead => {
+                if let Some(table) = &mut self.table {
+                    table.head_rows = table.rows.len();
+                }
+            }
+            TagEnd::TableCell => {
+                let cell = std::mem::take(&mut self.pieces);
+                if let Some(row) = self.table.as_mut().and_then(|table| table.rows.last_mut()) {
+                    row.push(cell);
+                }
+            }
+            TagEnd::Table => {
+                if let Some(table) = self.table.take() {
+                    self.render_table(table);
+                }
+            }
+            TagEnd::Emphasis | TagEnd::Strong | TagEnd::Strikethrough => {
+                self.styles.pop();
+            }
+            TagEnd::Link | TagEnd::Image => {
+                self.styles.pop();
+                self.link = None;
+            }
+            _ => {}
+        }
+    }
+
+    fn code_block(&mut self, lang: &str, code: &str) {
+        let syntaxes = syntaxes();
+        let syntax = syntaxes
+            .find_syntax_by_token(lang)
+            .unwrap_or_else(|| syntaxes.find_syntax_plain_text());
+        let mut highlighter = HighlightLines::new(syntax, theme());
+
+        let (_, (prefix, _)) = self.prefixes();
+        for line in LinesWithEndings::from(code) {
+            let highlighted = match highlighter.highlight_line(line, syntaxes) {
+                Ok(ranges) => as_24_bit_terminal_escaped(&ranges, false),
+                Err(_) => line.to_string(),
+            };
+            let highlighted = highlighted.trim_end_matches(['\n', '\r']);
+            self.push_line(format!("{}{}{}", prefix, highlighted, RESET));
+        }
+    }
+
+    /// Lays out a table, narrowing the widest columns and wrapping their
+    /// cells when it doesn't fit the terminal.
+    fn render_table(&mut self, table: Table) {
+        let columns = table.rows.iter().map(Vec::len).max().unwrap_or(0);
+        if columns == 0 {
+            return;
+        }
+        let mut widths = vec![0; columns];
+        for row in &table.rows {
+            for (i, cell) in row.iter().enumerate() {
+                widths[i] = widths[i].max(visible_width(cell));
+            }
+        }
+
+        let (_, (prefix, prefix_width)) = self.prefixes();
+        let available = self.width.saturating_sub(prefix_width + 3 * (columns - 1));
+        while widths.iter().sum::<usize>() > available {
+            let widest = (0..columns).max_by_key(|i| widths[*i]).unwrap_or(0);
+            if widths[widest] <= MIN_COLUMN_WIDTH {
+                break;
+            }
+            widths[widest] -= 1;
+        }
+
+        let separator = format!(" {}│{} ", DIM, RESET);
+        for (r, row) in table.rows.iter().enumerate() {
+            let cells: Vec<Vec<String>> = (0..columns)
+                .map(|i| match row.get(i) {
+                    Some(cell) => wrap(cell, widths[i], ("", 0), ("", 0)),
+                    None => vec![String::new()],
+                })
+                .collect();
+            let height = cells.iter().map(Vec::len).max().unwrap_or(1);
+            for l in 0..height {
+                let parts: Vec<String> = cells
+                    .iter()
+                    .enumerate()
+                    .map(|(i, lines)| {
+                        let text = lines.get(l).map(String::as_str).unwrap_or("");
+                        let alignment = table.alignments.get(i).copied().unwrap_or(Alignment::None);
+                        let text = pad(text, widths[i], alignment);
+                        if r < table.head_rows {
+                            format!("{}{}{}", BOLD, text, RESET)
+                        } else {
+                            text
+                        }
+                    })
+                    .collect();
+                let line = format!("{}{}", prefix, parts.join(&separator));
+                self.push_line(line.trim_end().to_string());
+            }
+            if r + 1 == table.head_rows {
+                let rule: Vec<String> = widths.iter().map(|w| "─".repeat(*w)).collect();
+                let line = format!("{}{}{}{}", prefix, DIM, rule.join("─┼─"), RESET);
+                self.push_line(line);
+            }
+        }
+    }
+
+    fn finish(mut self) -> String {
+        self.flush_text();
+        let mut text = self.lines.join("\n");
+        text.push('\n');
+        text
+    }
+}
+
+fn pad(text: &str, width: usize, alignment: Alignment) -> String {
+    let space = width.saturating_sub(strip_ansi(text).width());
+    match alignment {
+        Alignment::Right => format!("{}{}", " ".repeat(space), text),
+        Alignment::Center => format!(
+            "{}{}{}",
+            " ".repeat(space / 2),
+            text,
+            " ".repeat(space - space / 2)
+        ),
+        Alignment::Left | Alignment::None => format!("{}{}", text, " ".repeat(space)),
+    }
+}
+
+/// Renders a streamed reply block by block. Text is held back until the
+/// block it belongs to is complete, so each block is rendered once.
+pub struct StreamRenderer {
+    width: usize,
+    buffer: String,
+    /// The fence of the code block the buffer ends in, if any.
+    fence: Option<String>,
+    /// Length of the buffer already scanned for block ends.
+    scanned: usize,
+    started: bool,
+}
+
+impl StreamRenderer {
+    pub fn new(width: usize) -> Self {
+        StreamRenderer {
+            width,
+            buffer: String::new(),
+            fence: None,
+            scanned: 0,
+            started: false,
+        }
+    }
+
+    /// Adds a piece of the reply, returning the rendering of any blocks it
+    /// completed.
+    pub fn push(&mut self, delta: &str) -> String {
+        self.buffer.push_str(delta);
+        let mut end = None;
+        while let Some(newline) = self.buffer[self.scanned..].find('\n') {
+            let line_end = self.scanned + newline + 1;
+            let line = self.buffer[self.scanned..line_end].trim();
+            match &self.fence {
+                Some(fence) => {
+                    if line.starts_with(fence.as_str())
+                        && line.chars().all(|c| fence.starts_with(c))
+                    {
+                        self.fence = None;
+                        end = Some(line_end);
+                    }
+                }
+                None => {
+                    let marker: String = line
+                        .chars()
+                        .take_while(|c| *c == '`' || *c == '~')
+                        .collect();
+                    if marker.len() >= 3 && marker.chars().all(|c| marker.starts_with(c)) {
+                        self.fence = Some(marker);
+                    } else if line.is_empty() {
+                        end = Some(line_end);
+                    }
+                }
+            }
+            self.scanned = line_end;
+        }
+
+        match end {
+            Some(end) => {
+                let block: String = self.buffer.drain(..end).collect();
+                self.scanned -= end;
+                self.render(&block)
+            }
+            None => String::new(),
+        }
+    }
+
+    /// Renders whatever is left once the reply is complete.
+    pub fn finish(&mut self) -> String {
+        let block = std::mem::take(&mut self.buffer);
+        self.scanned = 0;
+        self.fence = None;
+        self.render(&block)
+    }
+
+    fn render(&mut self, block: &str) -> String {
+        if block.trim().is_empty() {
+            return String::new();
+        }
+        let rendered = render(block, self.width);
+        if std::mem::replace(&mut self.started, true) {
+            format!("\n{}", rendered)
+        } else {
+            rendered
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn plain(markdown: &str, width: usize) -> String {
+        strip_ansi(&render(markdown, width))
+    }
+
+    #[test]
+    fn test_render_blocks() {
+        let markdown = "# Title\n\nSome **bold** and *italic* text.\n\n- one\n- two\n  1. nested\n\n> quoted\n";
+        assert_eq!(
+            plain(markdown, 80),
+            "Title\n\nSome bold and italic text.\n\n• one\n• two\n  1. nested\n\n│ quoted\n"
+        );
+    }
+
+    #[test]
+    fn test_wraps_paragraphs_and_list_items() {
+        let rendered = plain("- alpha beta gamma delta epsilon zeta eta theta", 20);
+        assert_eq!(
+            rendered,
+            "• alpha beta gamma\n  delta epsilon zeta\n  eta theta\n"
+        );
+    }
+
+    #[test]
+    fn test_links_are_clickable() {
+        let rendered = render("see [docs](https://example.com)", 80);
+        assert!(rendered.contains("\x1b]8;;https://example.com\x1b\\"));
+        assert_eq!(strip_ansi(&rendered), "see docs\n");
+    }
+
+    #[test]
+    fn test_code_blocks_keep_their_lines() {
+        let rendered = render(
+            "```rust\nfn main() {\n    let long_line_that_is_not_wrapped = 1;\n}\n```\n",
+            20,
+        );
+        assert!(
+            rendered.contains("\x1b[38;2;"),
+            "code should be highlighted"
+        );
+        assert_eq!(
+            strip_ansi(&rendered),
+            "fn main() {\n    let long_line_that_is_not_wrapped = 1;\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_tables_fit_the_width() {
+        let markdown = "| Name | Description |\n|------|------:|\n| a | a rather long description of the row |\n";
+        let rendered = plain(markdown, 30);
+        for line in rendered.lines() {
+            assert!(line.width() <= 30, "too wide: {:?}", line);
+        }
+        assert!(rendered.starts_with("Name │ "));
+        assert!(rendered.contains("─┼─"));
+    }
+
+    #[test]
+    fn test_stream_matches_full_render() {
+        let markdown = "Intro paragraph.\n\n```sh\necho one\n\necho two\n```\n| a | b |\n|---|---|\n| 1 | 2 |\n\nLast *words*";
+        let mut stream = StreamRenderer::new(40);
+        let mut output = String::new();
+        for chunk in markdown.as_bytes().chunks(3) {
+            output.push_str(&stream.push(std::str::from_utf8(chunk).unwrap()));
+        }
+        output.push_str(&stream.finish());
+        assert_eq!(strip_ansi(&output), plain(markdown, 40));
+    }
+}
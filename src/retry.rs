@@ -0,0 +1,185 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::thread;
+use std::time::Duration;
+
+use reqwest::blocking::{RequestBuilder, Response};
+use reqwest::header::HeaderMap;
+
+use crate::config_manager::AppConfig;
+use crate::error::Result;
+
+/// First backoff delay, doubled on every further attempt.
+const BASE_DELAY: Duration = Duration::from_millis(500);
+
+/// Retries requests that failed with a rate limit, a server error or a
+/// dropped connection. Shared by the chat backends, embeddings and TTS.
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub max_wait: Duration,
+}
+
+impl RetryPolicy {
+    pub fn from_config(config: &AppConfig) -> Self {
+        RetryPolicy {
+            max_retries: config.max_retries,
+            max_wait: Duration::from_secs(config.max_retry_wait_secs),
+        }
+    }
+
+    /// Sends the request built by `build`, retrying retryable failures. Once
+    /// the retries run out the last response is returned as is, so callers
+    /// still turn unsuccessful statuses into errors themselves.
+    pub fn send(&self, build: impl Fn() -> RequestBuilder) -> Result<Response> {
+        let mut attempt = 0;
+        loop {
+            let (reason, delay) = match build().send() {
+                Ok(response) if !is_retryable_status(response.status().as_u16()) => {
+                    return Ok(response)
+                }
+                Ok(response) if attempt >= self.max_retries => return Ok(response),
+                Ok(response) => (
+                    format!("server returned {}", response.status().as_u16()),
+                    server_delay(response.headers()).unwrap_or_else(|| self.backoff(attempt)),
+                ),
+                Err(e) if (e.is_connect() || e.is_timeout()) && attempt < self.max_retries => {
+                    ("request failed".to_string(), self.backoff(attempt))
+                }
+                Err(e) => return Err(e.into()),
+            };
+
+            let delay = delay.min(self.max_wait);
+            attempt += 1;
+            eprintln!(
+                "cgip: {}, retrying in {:.1}s ({}/{})",
+                reason,
+                delay.as_secs_f64(),
+                attempt,
+                self.max_retries
+            );
+            thread::sleep(delay);
+        }
+    }
+
+    /// Exponential backoff with jitter, picking a delay between half and all
+    /// of `BASE_DELAY * 2^attempt` so parallel jobs don't retry in lockstep.
+    fn backoff(&self, attempt: u32) -> Duration {
+        let ceiling = BASE_DELAY.saturating_mul(2u32.saturating_pow(attempt));
+        let ceiling = ceiling.min(self.max_wait);
+        ceiling / 2 + ceiling.mul_f64(jitter() / 2.0)
+    }
+}
+
+fn is_retryable_status(status: u16) -> bool {
+    // 529 is Anthropic's "overloaded" status
+    matches!(status, 408 | 429 | 500 | 502 | 503 | 504 | 529)
+}
+
+/// A random number in `0.0..1.0`, seeded from the per process hasher keys
+/// so no extra dependency is needed.
+fn jitter() -> f64 {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or_default(),
+    );
+    (hasher.finish() >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// The wait the server asked for, from `retry-after-ms`, `Retry-After` or
+/// the OpenAI style `x-ratelimit-reset-requests`/`x-ratelimit-reset-tokens`
+/// headers. When both reset headers are present the longer one wins.
+fn server_delay(headers: &HeaderMap) -> Option<Duration> {
+    let header = |name: &str| headers.get(name).and_then(|v| v.to_str().ok());
+
+    if let Some(ms) = header("retry-after-ms").and_then(|v| v.trim().parse::<f64>().ok()) {
+        return Some(Duration::from_secs_f64(ms.max(0.0) / 1000.0));
+    }
+    if let Some(secs) = header("retry-after").and_then(|v| v.trim().parse::<f64>().ok()) {
+        return Some(Duration::from_secs_f64(secs.max(0.0)));
+    }
+
+    ["x-ratelimit-reset-requests", "x-ratelimit-reset-tokens"]
+        .iter()
+        .filter_map(|name| header(name).and_then(parse_reset))
+        .max()
+}
+
+/// Parses reset durations such as `20ms`, `1.5s`, `6m0s` or `1h2m3s`. A
+/// bare number is taken as seconds.
+fn parse_reset(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(secs) = value.parse::<f64>() {
+        return Some(Duration::from_secs_f64(secs.max(0.0)));
+    }
+
+    let mut total = 0.0;
+    let mut rest = value;
+    while !rest.is_empty() {
+        let split = rest
+            .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+            .unwrap_or(rest.len());
+        let (number, tail) = rest.split_at(split);
+        let number: f64 = number.parse().ok()?;
+        let unit_len = tail
+            .find(|c: char| c.is_ascii_digit() || c == '.')
+            .unwrap_or(tail.len());
+        let (unit, tail) = tail.split_at(unit_len);
+        total += match unit {
+            "h" => number * 3600.0,
+            "m" => number * 60.0,
+            "s" => number,
+            "ms" => number / 1000.0,
+            _ => return None,
+        };
+        rest = tail;
+    }
+    Some(Duration::from_secs_f64(total))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    #[test]
+    fn test_parse_reset() {
+        assert_eq!(parse_reset("20ms"), Some(Duration::from_millis(20)));
+        assert_eq!(parse_reset("1.5s"), Some(Duration::from_millis(1500)));
+        assert_eq!(parse_reset("6m0s"), Some(Duration::from_secs(360)));
+        assert_eq!(parse_reset("1h2m3s"), Some(Duration::from_secs(3723)));
+        assert_eq!(parse_reset("7"), Some(Duration::from_secs(7)));
+        assert_eq!(parse_reset("soon"), None);
+    }
+
+    #[test]
+    fn test_server_delay_prefers_retry_after() {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-ratelimit-reset-requests", HeaderValue::from_static("1s"));
+        headers.insert("x-ratelimit-reset-tokens", HeaderValue::from_static("6m0s"));
+        assert_eq!(server_delay(&headers), Some(Duration::from_secs(360)));
+
+        headers.insert("retry-after", HeaderValue::from_static("2"));
+        assert_eq!(server_delay(&headers), Some(Duration::from_secs(2)));
+
+        assert_eq!(server_delay(&HeaderMap::new()), None);
+    }
+
+    #[test]
+    fn test_backoff_stays_within_bounds() {
+        let policy = RetryPolicy {
+            max_retries: 5,
+            max_wait: Duration::from_secs(3),
+        };
+        for attempt in 0..5 {
+            let ceiling = BASE_DELAY
+                .saturating_mul(2u32.pow(attempt))
+                .min(policy.max_wait);
+            let delay = policy.backoff(attempt);
+            assert!(delay >= ceiling / 2 && delay <= ceiling);
+        }
+    }
+}
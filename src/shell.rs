@@ -0,0 +1,48 @@
+use std::env;
+use std::path::Path;
+use std::process::Command;
+
+/// A shell that commands are run through.
+#[derive(Debug, Clone)]
+pub struct Shell {
+    program: String,
+}
+
+impl Shell {
+    /// The POSIX shell, which the agent writes its commands for.
+    pub fn sh() -> Self {
+        Shell {
+            program: "sh".to_string(),
+        }
+    }
+
+    /// The user's shell: `$SHELL`, or `%COMSPEC%` on Windows, falling back
+    /// to `sh`.
+    pub fn current() -> Self {
+        let var = if cfg!(windows) { "COMSPEC" } else { "SHELL" };
+        match env::var(var) {
+            Ok(program) if !program.trim().is_empty() => Shell { program },
+            _ => Shell::sh(),
+        }
+    }
+
+    /// The shell's name without its directory, such as `bash` or `zsh`.
+    pub fn name(&self) -> String {
+        Path::new(&self.program)
+            .file_stem()
+            .map(|name| name.to_string_lossy().to_lowercase())
+            .unwrap_or_else(|| self.program.clone())
+    }
+
+    /// A process that runs `script` in this shell.
+    pub fn command(&self, script: &str) -> Command {
+        let flag = match self.name().as_str() {
+            "cmd" => "/C",
+            "powershell" | "pwsh" => "-Command",
+            _ => "-c",
+        };
+        let mut command = Command::new(&self.program);
+        command.arg(flag).arg(script);
+        command
+    }
+}
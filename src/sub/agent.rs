@@ -1,12 +1,13 @@
 use std::env;
-use std::process::Command;
 
 use crate::args::AgentSubCommand;
-use crate::chatgpt::{GptClient, Message, MessageContent, Role};
+use crate::chatgpt::{GptClient, Message, Role};
+use crate::error::{CgipError, Result};
+use crate::shell::Shell;
 use crate::utils::get_file_contents_from_path;
 
 fn run_shell_command(cmd: &str) -> String {
-    match Command::new("sh").arg("-c").arg(cmd).output() {
+    match Shell::sh().command(cmd).output() {
         Ok(output) => {
             let mut text = String::new();
             text.push_str(&String::from_utf8_lossy(&output.stdout));
@@ -21,10 +22,12 @@ fn run_shell_command(cmd: &str) -> String {
     }
 }
 
-pub fn run(args: &AgentSubCommand, client: &mut GptClient) {
+pub fn run(args: &AgentSubCommand, client: &mut GptClient) -> Result<()> {
     if let Err(e) = env::set_current_dir(&args.directory) {
-        eprintln!("Failed to change directory: {}", e);
-        std::process::exit(1);
+        return Err(CgipError::Io(format!(
+            "Failed to change directory to '{}': {}",
+            args.directory, e
+        )));
     }
 
     let system_message = "You are a shell agent with access to the `execute` tool for running commands.\
@@ -41,7 +44,7 @@ pub fn run(args: &AgentSubCommand, client: &mut GptClient) {
 
     if let Some(files) = &args.input {
         for file in files {
-            let content = get_file_contents_from_path(file.to_string());
+            let content = get_file_contents_from_path(file.to_string())?;
             client.add_message(Role::User, content);
         }
     }
@@ -71,41 +74,27 @@ pub fn run(args: &AgentSubCommand, client: &mut GptClient) {
     let max_actions = args.max_actions;
 
     loop {
-        let resp = client.complete_with_tools(tools.clone());
-        let choice = &resp["choices"][0];
-        let finish_reason = choice["finish_reason"].as_str().unwrap_or("");
-        if let Some(text) = choice["message"]["content"].as_str() {
-            if !text.trim().is_empty() {
-                println!("{}", text.trim());
-            }
+        let completion = client.complete_with_tools(tools.clone())?;
+        if !completion.content.trim().is_empty() {
+            println!("{}", completion.content.trim());
         }
 
-        if finish_reason == "tool_calls" {
-            if let Some(calls) = choice["message"]["tool_calls"].as_array() {
-                for call in calls {
-                    if let Some(args_str) = call["function"]["arguments"].as_str() {
-                        let parsed: serde_json::Value =
-                            serde_json::from_str(args_str).unwrap_or_default();
-                        let command = parsed["command"].as_str().unwrap_or("");
-                        let output = run_shell_command(command);
-                        executed.push((command.to_string(), output.clone()));
-                        if !output.trim().is_empty() {
-                            println!("{}", output.trim());
-                        }
-                        let call_id = call["id"].as_str().unwrap_or("");
-                        client.messages.push(Message {
-                            role: "tool".to_string(),
-                            name: None,
-                            tool_call_id: Some(call_id.to_string()),
-                            tool_calls: None,
-                            content: MessageContent::Text(output),
-                        });
-                        actions += 1;
-                        if actions >= max_actions {
-                            println!("Reached maximum actions ({})", max_actions);
-                            break;
-                        }
-                    }
+        if completion.finish_reason.as_deref() == Some("tool_calls") {
+            for call in completion.tool_calls.iter().flatten() {
+                let arguments = call.arguments();
+                let command = arguments["command"].as_str().unwrap_or("");
+                let output = run_shell_command(command);
+                executed.push((command.to_string(), output.clone()));
+                if !output.trim().is_empty() {
+                    println!("{}", output.trim());
+                }
+                let mut result = Message::new(Role::Tool, output);
+                result.tool_call_id = Some(call.id.clone());
+                client.messages.push(result);
+                actions += 1;
+                if actions >= max_actions {
+                    println!("Reached maximum actions ({})", max_actions);
+                    break;
                 }
             }
             if actions >= max_actions {
@@ -129,4 +118,5 @@ pub fn run(args: &AgentSubCommand, client: &mut GptClient) {
             break;
         }
     }
+    Ok(())
 }
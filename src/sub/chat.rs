@@ -0,0 +1,379 @@
+use std::path::PathBuf;
+
+use atty::Stream;
+use rustyline::{error::ReadlineError, Config, DefaultEditor};
+
+use crate::{
+    args::{Args, ChatSubCommand},
+    blocks, chat,
+    chatgpt::{GptClient, Message, Role},
+    directives::{self, RequestOptions},
+    error::{CgipError, Result},
+    sub::session::{
+        delete_tty_context, last_user_index, read_from_tty_context, replace_last_reply,
+        save_to_tty_context, SessionStore,
+    },
+    tokens,
+    utils::get_file_contents_from_path,
+};
+
+const HELP: &str = "\
+Commands:
+  /model [name]     Show or change the model
+  /system [prompt]  Show or replace the system prompt
+  /file <path>      Add a file to the conversation
+  /search <query>   Ask with web search enabled
+  /clear            Start over, clearing the session
+  /save <name>      Save the conversation as a new session and continue in it
+  /retry            Ask the last question again
+  /exit             Leave, as does Ctrl-D
+
+Directives such as /json or /temp 0.2 can start a question and apply to it
+only, `cgip help directives` lists them.
+
+End a line with \\ to continue on the next one, or put \"\"\" on a line of its
+own to start and end a block of several lines.";
+
+/// A delimiter line for multi-line input.
+const BLOCK_DELIMITER: &str = "\"\"\"";
+
+fn history_path() -> Option<PathBuf> {
+    Some(dirs::data_dir()?.join("cgip").join("chat_history"))
+}
+
+/// Reads one entry, which may span several lines. Returns `None` at the end
+/// of input.
+fn read_input(editor: &mut DefaultEditor) -> Result<Option<String>> {
+    let mut lines: Vec<String> = Vec::new();
+    let mut in_block = false;
+    loop {
+        let prompt = if lines.is_empty() && !in_block {
+            "> "
+        } else {
+            ". "
+        };
+        match editor.readline(prompt) {
+            Ok(line) if line.trim() == BLOCK_DELIMITER => {
+                if in_block {
+                    break;
+                }
+                in_block = true;
+            }
+            Ok(line) if in_block => lines.push(line),
+            Ok(line) => match line.strip_suffix('\\') {
+                Some(line) => lines.push(line.to_string()),
+                None => {
+                    lines.push(line);
+                    break;
+                }
+            },
+            // Ctrl-C drops what was typed so far
+            Err(ReadlineError::Interrupted) => {
+                lines.clear();
+                in_block = false;
+            }
+            Err(ReadlineError::Eof) => return Ok(None),
+            Err(e) => return Err(CgipError::Io(format!("Could not read input: {}", e))),
+        }
+    }
+
+    let input = lines.join("\n");
+    if !input.trim().is_empty() {
+        let _ = editor.add_history_entry(input.as_str());
+    }
+    Ok(Some(input))
+}
+
+enum Flow {
+    Continue,
+    Exit,
+}
+
+struct Repl<'a> {
+    args: &'a Args,
+    client: &'a mut GptClient,
+    /// Whether the conversation is saved to the current session.
+    use_session: bool,
+    /// Messages added since the last reply, saved together with it.
+    pending: Vec<Message>,
+}
+
+impl Repl<'_> {
+    fn session_name(&self) -> Option<String> {
+        if !self.use_session {
+            return None;
+        }
+        SessionStore::open_default().ok()?.current()
+    }
+
+    fn command(&mut self, input: &str) -> Result<Flow> {
+        let (command, rest) = match input.split_once(char::is_whitespace) {
+            Some((command, rest)) => (command, rest.trim()),
+            None => (input, ""),
+        };
+
+        match command {
+            "/exit" | "/quit" => return Ok(Flow::Exit),
+            "/help" => println!("{}", HELP),
+            "/model" if rest.is_empty() => println!("{}", self.client.config_manager.config.model),
+            "/model" => {
+                self.client.config_manager.config.model = rest.to_string();
+                println!("Using {}", rest);
+            }
+            "/system" if rest.is_empty() => match self.client.messages.first() {
+                Some(msg) if msg.role == Role::System => println!("{}", msg.content),
+                _ => println!("No system prompt"),
+            },
+            "/system" => {
+                let prompt = Message::new(Role::System, rest.to_string());
+                match self.client.messages.first_mut() {
+                    Some(msg) if msg.role == Role::System => *msg = prompt,
+                    _ => self.client.messages.insert(0, prompt),
+                }
+                println!("System prompt replaced");
+            }
+            "/file" if rest.is_empty() => println!("Usage: /file <path>"),
+            "/file" => {
+                let contents = get_file_contents_from_path(rest.to_string())?;
+                let msg = Message::new(Role::User, contents);
+                self.client.messages.push(msg.clone());
+                self.pending.push(msg);
+                println!("Added {}, it is sent with your next message", rest);
+            }
+            "/search" if rest.is_empty() => println!("Usage: /search <query>"),
+            "/search" => {
+                let options = RequestOptions {
+                    web_search: true,
+                    ..Default::default()
+                };
+                self.ask(rest.to_string(), options)?
+            }
+            "/clear" => {
+                self.client.messages.retain(|msg| msg.role == Role::System);
+                self.pending.clear();
+                if self.use_session {
+                    delete_tty_context()?;
+                }
+                println!("Conversation cleared");
+            }
+            "/save" if rest.is_empty() => match self.session_name() {
+                Some(name) => println!("The conversation is saved in session '{}'", name),
+                None => println!("Usage: /save <name>"),
+            },
+            "/save" => self.save(rest)?,
+            "/retry" => self.retry()?,
+            _ => return self.directives(input),
+        }
+        Ok(Flow::Continue)
+    }
+
+    /// Asks a question that starts with directives such as `/temp 0.2`, which
+    /// apply to that question only.
+    fn directives(&mut self, input: &str) -> Result<Flow> {
+        let (mut options, text) = directives::parse(input)?;
+        if options == RequestOptions::default() {
+            let command = input.split_whitespace().next().unwrap_or(input);
+            println!("Unknown command {}, /help lists the commands", command);
+        } else if text.is_empty() {
+            println!("Directives go before a question, as in /json list three colors");
+        } else {
+            for path in std::mem::take(&mut options.files) {
+                let msg = Message::new(Role::User, get_file_contents_from_path(path)?);
+                self.client.messages.push(msg.clone());
+                self.pending.push(msg);
+            }
+            self.ask(text, options)?;
+        }
+        Ok(Flow::Continue)
+    }
+
+    /// Sends `text` with `options` set for this request only, and prints the
+    /// reply as it streams in.
+    fn ask(&mut self, text: String, options: RequestOptions) -> Result<()> {
+        let save = self.use_session && !options.no_session;
+        self.client.options = options;
+        let model = self.client.model().to_string();
+        self.client
+            .messages
+            .push(Message::new(Role::User, text.clone()));
+        self.pending.push(Message::new(Role::User, text));
+
+        let reply = self.reply();
+        self.client.options = RequestOptions::default();
+        let mut reply = match reply {
+            Ok(Some(reply)) => reply,
+            Ok(None) => return Ok(()),
+            Err(e) => {
+                // Leave the question out so it can simply be asked again
+                self.client.messages.pop();
+                self.pending.pop();
+                return Err(e);
+            }
+        };
+
+        let mut messages = std::mem::take(&mut self.pending);
+        reply.meta.model = Some(model);
+        let text = reply.content.to_string();
+        messages.push(reply);
+        if save {
+            save_to_tty_context(
+                &self.client.config_manager,
+                messages,
+                self.client.last_usage.as_ref(),
+            );
+        }
+        blocks::run(self.args, &text)
+    }
+
+    /// Gets a reply to the conversation so far and adds it to the client.
+    fn reply(&mut self) -> Result<Option<Message>> {
+        let model = self.client.model().to_string();
+        // Earlier messages may be dropped to fit the budget, the ones not
+        // yet answered never are.
+        let unanswered = self.client.messages.len() - self.pending.len();
+        let start = usize::from(
+            self.client
+                .messages
+                .first()
+                .is_some_and(|m| m.role == Role::System),
+        );
+        tokens::fit_to_budget(
+            &mut self.client.messages,
+            start..unanswered.max(start),
+            &model,
+            self.client.config_manager.config.max_input_tokens,
+        );
+
+        let text = match chat::respond(self.args, self.client)? {
+            Some(text) => text,
+            None => return Ok(None),
+        };
+        let reply = Message::new(Role::Assistant, text);
+        self.client.messages.push(reply.clone());
+        Ok(Some(reply))
+    }
+
+    fn retry(&mut self) -> Result<()> {
+        // Files added since the last reply belong to the next question
+        if !self.pending.is_empty() {
+            let answered = self.client.messages.len() - self.pending.len();
+            self.client.messages.truncate(answered);
+            self.pending.clear();
+            println!("Dropped the files added since the last reply");
+        }
+
+        let last_user = match last_user_index(&self.client.messages) {
+            Some(i) => i,
+            None => {
+                println!("Nothing to retry");
+                return Ok(());
+            }
+        };
+        self.client.messages.truncate(last_user + 1);
+
+        let reply = match self.reply()? {
+            Some(reply) => reply,
+            None => return Ok(()),
+        };
+        if let Some(name) = self.session_name() {
+            let stored = SessionStore::open_default()?.load(&name)?;
+            if let Some(last_user) = last_user_index(&stored) {
+                replace_last_reply(
+                    &self.client.config_manager,
+                    &name,
+                    last_user + 1,
+                    reply,
+                    false,
+                    self.client.last_usage.as_ref(),
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Stores the conversation as session `name`, which becomes the current
+    /// session for the rest of the chat and for later `cgip` calls.
+    fn save(&mut self, name: &str) -> Result<()> {
+        let store = SessionStore::open_default()?;
+        let messages: Vec<Message> = self
+            .client
+            .messages
+            .iter()
+            .filter(|msg| msg.role != Role::System)
+            .cloned()
+            .collect();
+        store.create(name, &messages)?;
+        store.set_current(Some(name))?;
+        self.use_session = true;
+        self.pending.clear();
+        println!("Saved the conversation as session '{}'", name);
+        if std::env::var("CGIP_SESSION_NAME").is_ok() {
+            eprintln!("Warning: CGIP_SESSION_NAME is set and takes precedence in this shell");
+        }
+        Ok(())
+    }
+}
+
+pub fn run(_subcmd: &ChatSubCommand, args: &Args, client: &mut GptClient) -> Result<()> {
+    if !atty::is(Stream::Stdin) {
+        return Err(CgipError::Usage(
+            "cgip chat needs an interactive terminal".into(),
+        ));
+    }
+
+    chat::apply_overrides(args, client);
+    let use_session = !args.no_session;
+    if use_session {
+        client.messages.extend(read_from_tty_context());
+    }
+
+    let config = Config::builder().auto_add_history(false).build();
+    let mut editor = DefaultEditor::with_config(config)
+        .map_err(|e| CgipError::Io(format!("Could not start the line editor: {}", e)))?;
+    let history = history_path();
+    if let Some(path) = &history {
+        let _ = editor.load_history(path);
+    }
+
+    let mut repl = Repl {
+        args,
+        client,
+        use_session,
+        pending: Vec::new(),
+    };
+    let session = repl
+        .session_name()
+        .map(|name| format!(" in session '{}'", name))
+        .unwrap_or_default();
+    println!(
+        "Chatting with {}{}. /help lists the commands, Ctrl-D quits.",
+        repl.client.config_manager.config.model, session
+    );
+
+    while let Some(input) = read_input(&mut editor)? {
+        let input = input.trim();
+        if input.is_empty() {
+            continue;
+        }
+        let result = if input.starts_with('/') {
+            repl.command(input)
+        } else {
+            repl.ask(input.to_string(), RequestOptions::default())
+                .map(|_| Flow::Continue)
+        };
+        match result {
+            Ok(Flow::Continue) => {}
+            Ok(Flow::Exit) => break,
+            // A failed request shouldn't end the chat
+            Err(e) => eprintln!("cgip: {}", e),
+        }
+    }
+
+    if let Some(path) = &history {
+        if let Some(dir) = path.parent() {
+            let _ = std::fs::create_dir_all(dir);
+        }
+        let _ = editor.save_history(path);
+    }
+    Ok(())
+}
@@ -0,0 +1,567 @@
+use std::{
+    env, fmt,
+    io::{self, BufRead, Write},
+    str::FromStr,
+};
+
+use atty::Stream;
+use serde::Deserialize;
+
+use crate::{
+    args::{Args, CmdSubCommand},
+    chat,
+    chatgpt::GptClient,
+    error::{CgipError, Result},
+    render,
+    shell::Shell,
+};
+
+/// What running a command could do, from least to most dangerous.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Risk {
+    ReadOnly,
+    Network,
+    ModifiesFiles,
+    Destructive,
+}
+
+impl fmt::Display for Risk {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let label = match self {
+            Risk::ReadOnly => "read-only",
+            Risk::Network => "network",
+            Risk::ModifiesFiles => "modifies files",
+            Risk::Destructive => "destructive",
+        };
+        write!(f, "{}", label)
+    }
+}
+
+impl FromStr for Risk {
+    type Err = CgipError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().replace(['_', ' '], "-").as_str() {
+            "read-only" | "readonly" => Ok(Risk::ReadOnly),
+            "network" => Ok(Risk::Network),
+            "modifies-files" | "modifies" => Ok(Risk::ModifiesFiles),
+            "destructive" => Ok(Risk::Destructive),
+            _ => Err(CgipError::BadResponse(format!("Unknown risk '{}'", s))),
+        }
+    }
+}
+
+const DESTRUCTIVE: &[&str] = &[
+    "rm", "rmdir", "shred", "srm", "dd", "fdisk", "parted", "wipefs", "truncate", "kill",
+    "killall", "pkill", "shutdown", "reboot", "halt", "poweroff",
+];
+
+const NETWORK: &[&str] = &[
+    "curl",
+    "ssh",
+    "scp",
+    "sftp",
+    "ftp",
+    "nc",
+    "ncat",
+    "telnet",
+    "ping",
+    "dig",
+    "nslookup",
+    "host",
+    "traceroute",
+    "nmap",
+    "http",
+    "https",
+];
+
+/// Programs known only to read. Anything not listed here or above is
+/// assumed to modify files.
+const READ_ONLY: &[&str] = &[
+    "ls",
+    "cat",
+    "less",
+    "more",
+    "head",
+    "tail",
+    "grep",
+    "egrep",
+    "fgrep",
+    "rg",
+    "ag",
+    "fd",
+    "wc",
+    "sort",
+    "uniq",
+    "cut",
+    "tr",
+    "column",
+    "nl",
+    "tac",
+    "rev",
+    "paste",
+    "join",
+    "comm",
+    "fold",
+    "fmt",
+    "echo",
+    "printf",
+    "pwd",
+    "cd",
+    "whoami",
+    "id",
+    "groups",
+    "hostname",
+    "uname",
+    "date",
+    "cal",
+    "uptime",
+    "du",
+    "df",
+    "free",
+    "ps",
+    "top",
+    "htop",
+    "lsof",
+    "lsblk",
+    "lscpu",
+    "lspci",
+    "lsusb",
+    "stat",
+    "file",
+    "which",
+    "whereis",
+    "type",
+    "tree",
+    "jq",
+    "yq",
+    "diff",
+    "cmp",
+    "basename",
+    "dirname",
+    "realpath",
+    "readlink",
+    "md5sum",
+    "sha1sum",
+    "sha256sum",
+    "base64",
+    "xxd",
+    "hexdump",
+    "od",
+    "strings",
+    "zcat",
+    "zgrep",
+    "bat",
+    "exa",
+    "eza",
+    "journalctl",
+    "dmesg",
+    "printenv",
+    "seq",
+    "true",
+    "false",
+    "test",
+    "[",
+    "man",
+    "tldr",
+    "locate",
+    "ss",
+    "netstat",
+    "who",
+    "w",
+    "last",
+    "bc",
+    "expr",
+    "sleep",
+];
+
+/// Shells and interpreters, which run whatever code they are given. A
+/// command string passed with `-c` is classified as well.
+const INTERPRETERS: &[&str] = &[
+    "sh",
+    "bash",
+    "zsh",
+    "dash",
+    "ksh",
+    "fish",
+    "eval",
+    "source",
+    ".",
+    "python",
+    "python2",
+    "python3",
+    "node",
+    "deno",
+    "bun",
+    "perl",
+    "ruby",
+    "php",
+    "lua",
+    "osascript",
+    "pwsh",
+];
+
+/// Words that run the command that follows them.
+const WRAPPERS: &[&str] = &[
+    "env", "nohup", "time", "timeout", "nice", "ionice", "stdbuf", "xargs", "command", "exec",
+];
+
+fn classify_git(args: &[&str]) -> Risk {
+    let has = |flag: &str| args.contains(&flag);
+    match args.first().copied().unwrap_or("") {
+        "reset" if has("--hard") => Risk::Destructive,
+        "clean" => Risk::Destructive,
+        "push" if has("-f") || has("--force") => Risk::Destructive,
+        "clone" | "pull" | "fetch" | "push" | "ls-remote" => Risk::Network,
+        "status" | "log" | "diff" | "show" | "blame" | "grep" | "ls-files" | "rev-parse"
+        | "describe" | "shortlog" | "reflog" => Risk::ReadOnly,
+        "" => Risk::ReadOnly,
+        _ => Risk::ModifiesFiles,
+    }
+}
+
+/// The program name of a word, without its directory.
+fn basename(word: &str) -> &str {
+    word.rsplit('/').next().unwrap_or(word)
+}
+
+/// Classifies one simple command, given as its words.
+fn classify_words(words: &[&str]) -> Risk {
+    let mut words = words;
+    let mut risk = Risk::ReadOnly;
+    let mut wrapped = false;
+    // Skip variable assignments and wrappers such as `sudo` or `xargs -0`
+    while let Some(first) = words.first() {
+        let name = basename(first);
+        if name == "sudo" || name == "doas" {
+            risk = risk.max(Risk::ModifiesFiles);
+            wrapped = true;
+        } else if WRAPPERS.contains(&name) {
+            wrapped = true;
+        } else if !(first.starts_with('-') || first.contains('=')) {
+            break;
+        }
+        words = &words[1..];
+    }
+    if wrapped {
+        // Wrapper flags take values (`sudo -u root`, `timeout 5`), so the
+        // program may be any of the remaining words
+        for word in words {
+            let name = basename(word);
+            if DESTRUCTIVE.contains(&name) || name.starts_with("mkfs") {
+                risk = risk.max(Risk::Destructive);
+            } else if NETWORK.contains(&name) {
+                risk = risk.max(Risk::Network);
+            }
+        }
+    }
+
+    let (program, args) = match words.split_first() {
+        Some((program, args)) => (basename(program), args),
+        None => return risk,
+    };
+    let has = |flag: &str| args.contains(&flag);
+    let own = if DESTRUCTIVE.contains(&program) || program.starts_with("mkfs") {
+        Risk::Destructive
+    } else if program == "git" {
+        classify_git(args)
+    } else if program == "find" {
+        let runs_rm = args.iter().any(|arg| DESTRUCTIVE.contains(&basename(arg)));
+        if has("-delete") || (has("-exec") || has("-execdir")) && runs_rm {
+            Risk::Destructive
+        } else if has("-exec") || has("-execdir") {
+            Risk::ModifiesFiles
+        } else {
+            Risk::ReadOnly
+        }
+    } else if program == "curl" {
+        let saves = args
+            .iter()
+            .any(|arg| matches!(*arg, "-o" | "-O" | "--output" | "--remote-name"));
+        if saves {
+            Risk::ModifiesFiles
+        } else {
+            Risk::Network
+        }
+    } else if INTERPRETERS.contains(&program) {
+        // `sh -c 'rm -rf x'` is as risky as what it runs
+        Risk::ModifiesFiles.max(classify_words(args))
+    } else if matches!(program, "awk" | "gawk" | "mawk") {
+        let runs_commands = args
+            .iter()
+            .any(|arg| arg.contains("system") || arg.contains('>'));
+        if runs_commands {
+            Risk::ModifiesFiles
+        } else {
+            Risk::ReadOnly
+        }
+    } else if program == "sed" {
+        let in_place = args
+            .iter()
+            .any(|arg| arg.starts_with("-i") || *arg == "--in-place");
+        if in_place {
+            Risk::ModifiesFiles
+        } else {
+            Risk::ReadOnly
+        }
+    } else if NETWORK.contains(&program) {
+        Risk::Network
+    } else if READ_ONLY.contains(&program) {
+        Risk::ReadOnly
+    } else {
+        Risk::ModifiesFiles
+    };
+    risk.max(own)
+}
+
+/// Whether `command` redirects output into a file other than `/dev/null`.
+fn writes_file(command: &str) -> bool {
+    command.match_indices('>').any(|(i, _)| {
+        let target = command[i + 1..].trim_start_matches('>').trim_start();
+        !(target.starts_with('&') || target.starts_with("/dev/null") || target.is_empty())
+    })
+}
+
+/// Estimates what `command` may do from the programs it runs. The estimate
+/// is cautious: anything that looks like deleting data counts as
+/// destructive, and unknown programs, shells and interpreters are assumed to
+/// modify files.
+pub fn classify(command: &str) -> Risk {
+    let separated = command.replace(['|', ';', '&', '\n', '`', '(', ')'], " ; ");
+    let mut risk = separated
+        .split(" ; ")
+        .map(|part| {
+            let words: Vec<&str> = part
+                .split_whitespace()
+                .map(|word| word.trim_matches(|c| c == '"' || c == '\'' || c == '$'))
+                .filter(|word| !word.is_empty())
+                .collect();
+            classify_words(&words)
+        })
+        .max()
+        .unwrap_or(Risk::ReadOnly);
+    if writes_file(command) {
+        risk = risk.max(Risk::ModifiesFiles);
+    }
+    risk
+}
+
+#[derive(Debug, Deserialize)]
+struct Suggestion {
+    command: String,
+    #[serde(default)]
+    explanation: String,
+    #[serde(default)]
+    risk: Option<String>,
+}
+
+/// Reads the model's suggestion, which may be wrapped in a code fence.
+fn parse_suggestion(reply: &str) -> Result<Suggestion> {
+    let json = match (reply.find('{'), reply.rfind('}')) {
+        (Some(start), Some(end)) if start < end => &reply[start..=end],
+        _ => reply,
+    };
+    let suggestion: Suggestion = serde_json::from_str(json).map_err(|e| {
+        CgipError::BadResponse(format!("Could not read the suggested command: {}", e))
+    })?;
+    let command = suggestion.command.trim();
+    if command.is_empty() {
+        return Err(CgipError::BadResponse(
+            "The model did not suggest a command".into(),
+        ));
+    }
+    if command.contains('\n') {
+        return Err(CgipError::BadResponse(format!(
+            "The model suggested more than one command:\n{}",
+            command
+        )));
+    }
+    Ok(Suggestion {
+        command: command.to_string(),
+        ..suggestion
+    })
+}
+
+fn system_prompt(shell: &Shell) -> String {
+    format!(
+        "You turn requests into a single shell command for {os} using the {shell} shell. \
+Reply with a JSON object with these keys: \"command\", exactly one command line without code \
+fences, joining steps with pipes or && when needed; \"explanation\", one or two sentences on \
+what the command does; \"risk\", one of \"read-only\", \"network\", \"modifies-files\" or \
+\"destructive\". Prefer commands that only read when the request allows it.",
+        os = env::consts::OS,
+        shell = shell.name(),
+    )
+}
+
+fn risk_label(risk: Risk) -> String {
+    if !render::is_enabled() {
+        return risk.to_string();
+    }
+    let color = match risk {
+        Risk::ReadOnly => "\x1b[32m",
+        Risk::Network => "\x1b[34m",
+        Risk::ModifiesFiles => "\x1b[33m",
+        Risk::Destructive => "\x1b[1;31m",
+    };
+    format!("{}{}\x1b[0m", color, risk)
+}
+
+fn ask(prompt: &str) -> Result<String> {
+    eprint!("{}", prompt);
+    io::stderr().flush()?;
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer)?;
+    Ok(answer.trim().to_lowercase())
+}
+
+/// Asks before running the command. Destructive commands need `yes` typed
+/// out, even with `--yes`.
+fn confirm(risk: Risk, yes: bool) -> Result<bool> {
+    if yes && risk < Risk::Destructive {
+        return Ok(true);
+    }
+    if !atty::is(Stream::Stdin) {
+        let hint = if risk == Risk::Destructive {
+            "use --print to only show it"
+        } else {
+            "use --yes to run it anyway or --print to only show it"
+        };
+        return Err(CgipError::Usage(format!(
+            "Not running a {} command without confirmation, {}",
+            risk, hint
+        )));
+    }
+    if risk == Risk::Destructive {
+        Ok(ask("This command is destructive. Type 'yes' to run it: ")? == "yes")
+    } else {
+        Ok(matches!(ask("Run it? [y/N] ")?.as_str(), "y" | "yes"))
+    }
+}
+
+pub fn run(subcmd: &CmdSubCommand, args: &Args, client: &mut GptClient) -> Result<()> {
+    let request = subcmd.request.join(" ");
+    if request.trim().is_empty() {
+        return Err(CgipError::Usage("Describe the command you need".into()));
+    }
+
+    let shell = Shell::current();
+    chat::apply_overrides(args, client);
+    client.options.system = Some(system_prompt(&shell));
+    client.options.json = true;
+    client.add_message(crate::chatgpt::Role::User, request);
+    let suggestion = parse_suggestion(&client.complete()?)?;
+
+    // The model's own rating can raise the estimate but never lower it
+    let mut risk = classify(&suggestion.command);
+    if let Some(rated) = suggestion.risk.as_deref().and_then(|r| r.parse().ok()) {
+        risk = risk.max(rated);
+    }
+
+    if subcmd.print {
+        println!("{}", suggestion.command);
+        eprintln!("{}", suggestion.explanation.trim());
+        eprintln!("Risk: {}", risk);
+        return Ok(());
+    }
+
+    println!("{}", suggestion.command);
+    if !suggestion.explanation.trim().is_empty() {
+        println!("{}", suggestion.explanation.trim());
+    }
+    println!("Risk: {}", risk_label(risk));
+
+    if !confirm(risk, subcmd.yes)? {
+        eprintln!("Not run");
+        return Ok(());
+    }
+    let status = shell
+        .command(&suggestion.command)
+        .status()
+        .map_err(|e| CgipError::Io(format!("Could not run {}: {}", shell.name(), e)))?;
+    if !status.success() {
+        eprintln!("cgip: the command exited with {}", status);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classify_commands() {
+        assert_eq!(
+            classify("find ~ -type f -size +1G -mtime -7"),
+            Risk::ReadOnly
+        );
+        assert_eq!(classify("ls -la | grep foo 2>/dev/null"), Risk::ReadOnly);
+        assert_eq!(
+            classify("curl -s https://example.com | jq ."),
+            Risk::Network
+        );
+        assert_eq!(classify("git pull && cargo build"), Risk::ModifiesFiles);
+        assert_eq!(classify("sed -i 's/a/b/' file.txt"), Risk::ModifiesFiles);
+        assert_eq!(classify("echo hi > notes.txt"), Risk::ModifiesFiles);
+        assert_eq!(classify("sudo apt-get update"), Risk::ModifiesFiles);
+        assert_eq!(classify("find . -name '*.tmp' -delete"), Risk::Destructive);
+        assert_eq!(classify("ls | xargs -0 rm -f"), Risk::Destructive);
+        assert_eq!(classify("git reset --hard HEAD~1"), Risk::Destructive);
+        assert_eq!(classify("echo $(rm -rf build)"), Risk::Destructive);
+        assert_eq!(classify("/bin/rm foo"), Risk::Destructive);
+        assert_eq!(classify("sudo -u root rm -rf /"), Risk::Destructive);
+        assert_eq!(classify("nice -n 10 rm -rf ~"), Risk::Destructive);
+        assert_eq!(classify("ls | xargs -n 1 rm -rf"), Risk::Destructive);
+        assert_eq!(classify("timeout 5 rm -rf x"), Risk::Destructive);
+        assert_eq!(classify("stdbuf -oL rm -rf x"), Risk::Destructive);
+        assert_eq!(classify("ionice -c 3 rm -rf x"), Risk::Destructive);
+        assert_eq!(classify("find . -exec /bin/rm {} +"), Risk::Destructive);
+    }
+
+    #[test]
+    fn test_classify_shells_and_unknown_programs() {
+        assert_eq!(classify("sh -c 'echo hi'"), Risk::ModifiesFiles);
+        assert_eq!(classify("bash -c 'rm -rf ~/build'"), Risk::Destructive);
+        assert_eq!(classify("eval \"$PAYLOAD\""), Risk::ModifiesFiles);
+        assert_eq!(
+            classify("python3 -c 'import os; os.remove(\"x\")'"),
+            Risk::ModifiesFiles
+        );
+        assert_eq!(classify("perl -e 'unlink glob \"*\"'"), Risk::ModifiesFiles);
+        assert_eq!(
+            classify("awk '{ system(\"touch \" $1) }' list.txt"),
+            Risk::ModifiesFiles
+        );
+        assert_eq!(classify("awk '{ print $1 }' list.txt"), Risk::ReadOnly);
+        assert_eq!(classify("./deploy.sh"), Risk::ModifiesFiles);
+        assert_eq!(classify("frobnicate --all"), Risk::ModifiesFiles);
+    }
+
+    #[test]
+    fn test_parse_suggestion() {
+        let reply = "```json\n{\"command\": \"du -sh *\", \"explanation\": \"Sizes\", \"risk\": \"read-only\"}\n```";
+        let suggestion = parse_suggestion(reply).unwrap();
+        assert_eq!(suggestion.command, "du -sh *");
+        assert_eq!(suggestion.risk.as_deref(), Some("read-only"));
+
+        assert!(parse_suggestion("{\"command\": \"\"}").is_err());
+        assert!(parse_suggestion("{\"command\": \"ls\\nrm x\"}").is_err());
+        assert!(parse_suggestion("just run ls").is_err());
+    }
+
+    #[test]
+    fn test_risk_labels_round_trip() {
+        for risk in [
+            Risk::ReadOnly,
+            Risk::Network,
+            Risk::ModifiesFiles,
+            Risk::Destructive,
+        ] {
+            assert_eq!(risk.to_string().parse::<Risk>().unwrap(), risk);
+        }
+        assert_eq!(
+            "modifies_files".parse::<Risk>().unwrap(),
+            Risk::ModifiesFiles
+        );
+    }
+}
@@ -4,14 +4,18 @@ use std::fs::File;
 use std::io::Write;
 
 use crate::args::EmbeddingSubCommand;
+use crate::config_manager::AppConfig;
+use crate::error::{CgipError, Result};
+use crate::retry::RetryPolicy;
 use crate::utils::get_stdin;
 
-pub fn run(args: &EmbeddingSubCommand) -> Result<(), Box<dyn std::error::Error>> {
+pub fn run(args: &EmbeddingSubCommand, config: &AppConfig) -> Result<()> {
     let stdin_text = get_stdin();
     let text = match (stdin_text.is_empty(), &args.text) {
         (true, None) => {
-            eprintln!("Error: No text provided. Please provide text as an argument or via stdin.");
-            std::process::exit(1);
+            return Err(CgipError::Usage(
+                "No text provided. Please provide text as an argument or via stdin.".to_string(),
+            ));
         }
         (true, Some(arg_text)) => arg_text.clone(),
         (false, None) => stdin_text,
@@ -19,7 +23,7 @@ pub fn run(args: &EmbeddingSubCommand) -> Result<(), Box<dyn std::error::Error>>
     };
 
     let api_key = std::env::var("OPENAI_API_KEY")
-        .map_err(|_| "Missing OPENAI_API_KEY environment variable")?;
+        .map_err(|_| CgipError::Config("Missing OPENAI_API_KEY environment variable".into()))?;
 
     let payload = json!({
         "input": text,
@@ -38,17 +42,18 @@ pub fn run(args: &EmbeddingSubCommand) -> Result<(), Box<dyn std::error::Error>>
         format!("{}/v1/embeddings", base_url.trim_end_matches('/'))
     };
 
-    let response = client
-        .post(&url)
-        .header("Authorization", format!("Bearer {}", api_key))
-        .header("Content-Type", "application/json")
-        .json(&payload)
-        .send()?;
+    let response = RetryPolicy::from_config(config).send(|| {
+        client
+            .post(&url)
+            .header("Authorization", format!("Bearer {}", api_key))
+            .header("Content-Type", "application/json")
+            .json(&payload)
+    })?;
 
     if !response.status().is_success() {
+        let status = response.status().as_u16();
         let error_text = response.text()?;
-        eprintln!("API Error: {}", error_text);
-        std::process::exit(1);
+        return Err(CgipError::from_status(status, error_text));
     }
 
     let resp_json: serde_json::Value = response.json()?;
@@ -59,8 +64,9 @@ pub fn run(args: &EmbeddingSubCommand) -> Result<(), Box<dyn std::error::Error>>
             .collect::<Vec<_>>()
             .join(","),
         None => {
-            eprintln!("Unexpected response format");
-            std::process::exit(1);
+            return Err(CgipError::BadResponse(
+                "Unexpected response format".to_string(),
+            ));
         }
     };
 
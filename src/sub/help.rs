@@ -0,0 +1,35 @@
+use clap::CommandFactory;
+
+use crate::{
+    args::{Args, HelpSubCommand},
+    directives,
+    error::{CgipError, Result},
+};
+
+pub fn run(subcmd: &HelpSubCommand) -> Result<()> {
+    let mut command = Args::command();
+    command.build();
+    let topic = match &subcmd.topic {
+        None => {
+            command.print_long_help()?;
+            return Ok(());
+        }
+        Some(topic) => topic,
+    };
+
+    if topic == "directives" {
+        println!("{}", directives::help());
+        return Ok(());
+    }
+
+    match command.find_subcommand_mut(topic) {
+        Some(subcommand) => {
+            subcommand.print_long_help()?;
+            Ok(())
+        }
+        None => Err(CgipError::Usage(format!(
+            "No help for '{}', expected a subcommand or `directives`",
+            topic
+        ))),
+    }
+}
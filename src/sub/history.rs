@@ -0,0 +1,53 @@
+use crate::{
+    args::{HistoryAction, HistorySubCommand},
+    config_manager::ConfigManager,
+    error::Result,
+    history::HistoryStore,
+    sub::session::{append_to_session, SessionStore},
+};
+
+pub fn run(subcmd: &HistorySubCommand, config_manager: &ConfigManager) -> Result<()> {
+    let history = HistoryStore::open_default()?;
+
+    match &subcmd.action {
+        HistoryAction::Search { terms, limit } => {
+            for hit in history.search(terms, *limit)? {
+                println!(
+                    "{:>6}  {}  {:<12} {:<10} {}",
+                    hit.id,
+                    hit.created_at.replace('T', " ").trim_end_matches('Z'),
+                    hit.session.as_deref().unwrap_or("-"),
+                    hit.role,
+                    hit.snippet.split_whitespace().collect::<Vec<_>>().join(" ")
+                );
+            }
+        }
+        HistoryAction::Show { id } => {
+            let entries = history.exchange(*id)?;
+            for entry in &entries {
+                println!(
+                    "#{} {} [{}] {} ({})",
+                    entry.id,
+                    entry.created_at,
+                    entry.session.as_deref().unwrap_or("no session"),
+                    entry.message.role,
+                    entry.model.as_deref().unwrap_or("unknown model")
+                );
+                println!("{}\n", entry.message.content);
+            }
+
+            let session = SessionStore::open_default()?.current();
+            match session {
+                Some(name) => {
+                    append_to_session(
+                        config_manager,
+                        entries.into_iter().map(|e| e.message).collect(),
+                    );
+                    eprintln!("Added to session '{}'", name);
+                }
+                None => eprintln!("No active session, nothing was added"),
+            }
+        }
+    }
+    Ok(())
+}
@@ -1,7 +1,13 @@
+pub mod chat;
+pub mod cmd;
 pub mod config;
 pub mod embedding;
+pub mod help;
+pub mod history;
 pub mod image;
+pub mod retry;
 pub mod session;
+pub mod tokens;
 pub mod tts;
 pub mod view;
 pub mod agent;
@@ -0,0 +1,111 @@
+use crate::{
+    args::{Args, RetrySubCommand},
+    blocks, chat,
+    chatgpt::{GptClient, Message, Role},
+    error::{CgipError, Result},
+    output::{self, OutputFormat},
+    sub::session::{last_user_index, replace_last_reply, SessionStore},
+    tokens,
+};
+
+fn describe(msg: &Message) -> String {
+    let parts: Vec<&str> = msg
+        .meta
+        .model
+        .as_deref()
+        .into_iter()
+        .chain(msg.meta.timestamp.as_deref())
+        .collect();
+    if parts.is_empty() {
+        String::new()
+    } else {
+        format!(" ({})", parts.join(", "))
+    }
+}
+
+/// Prints the last reply of the session followed by the alternatives kept
+/// for it.
+fn list(messages: &[Message], format: OutputFormat) -> Result<()> {
+    let reply = match messages.last() {
+        Some(msg) if msg.role == Role::Assistant => msg,
+        _ => {
+            return Err(CgipError::Usage(
+                "The session doesn't end with a reply".into(),
+            ))
+        }
+    };
+
+    if format.is_json() {
+        let replies: Vec<serde_json::Value> = std::iter::once(reply)
+            .chain(&reply.meta.alternatives)
+            .map(|msg| {
+                serde_json::json!({
+                    "content": msg.content.to_string(),
+                    "model": msg.meta.model,
+                    "timestamp": msg.meta.timestamp,
+                })
+            })
+            .collect();
+        output::print_all(format, &replies);
+        return Ok(());
+    }
+
+    println!("Current reply{}:", describe(reply));
+    println!("{}", reply.content);
+    for (i, alternative) in reply.meta.alternatives.iter().enumerate() {
+        println!("\nAlternative {}{}:", i + 1, describe(alternative));
+        println!("{}", alternative.content);
+    }
+    Ok(())
+}
+
+pub fn run(subcmd: &RetrySubCommand, args: &Args, client: &mut GptClient) -> Result<()> {
+    let store = SessionStore::open_default()?;
+    let name = store
+        .current()
+        .ok_or_else(|| CgipError::Usage("No active session".into()))?;
+    let messages = store.load(&name)?;
+
+    if subcmd.list {
+        return list(&messages, args.output);
+    }
+
+    let context_len = last_user_index(&messages)
+        .map(|i| i + 1)
+        .ok_or_else(|| CgipError::Usage(format!("Nothing to retry in session '{}'", name)))?;
+
+    let session_start = client.messages.len();
+    client
+        .messages
+        .extend(messages[..context_len].iter().map(Message::without_meta));
+
+    chat::apply_overrides(args, client);
+    if let Some(model) = &subcmd.model {
+        client.config_manager.config.model = model.clone();
+    }
+    let config = &client.config_manager.config;
+    // The question being retried is never trimmed
+    let session_end = client.messages.len() - 1;
+    tokens::fit_to_budget(
+        &mut client.messages,
+        session_start..session_end,
+        &config.model,
+        config.max_input_tokens,
+    );
+
+    let response_text = match chat::respond(args, client)? {
+        Some(text) => text,
+        None => return Ok(()),
+    };
+    let reply = Message::new(Role::Assistant, response_text.clone());
+
+    replace_last_reply(
+        &client.config_manager,
+        &name,
+        context_len,
+        reply,
+        subcmd.keep,
+        client.last_usage.as_ref(),
+    )?;
+    blocks::run(args, &response_text)
+}
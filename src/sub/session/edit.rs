@@ -0,0 +1,170 @@
+use std::{
+    env, fs,
+    io::{self, BufRead, Write},
+    path::Path,
+    process::Command,
+};
+
+use atty::Stream;
+use tempfile::NamedTempFile;
+
+use super::export::{self, Format};
+use super::SessionStore;
+use crate::chatgpt::Message;
+use crate::error::{CgipError, Result};
+
+/// `$VISUAL`, then `$EDITOR`, then the platform's basic editor.
+fn editor() -> String {
+    ["VISUAL", "EDITOR"]
+        .iter()
+        .filter_map(|var| env::var(var).ok())
+        .find(|editor| !editor.trim().is_empty())
+        .unwrap_or_else(|| {
+            if cfg!(windows) {
+                "notepad".to_string()
+            } else {
+                "vi".to_string()
+            }
+        })
+}
+
+/// Runs `editor` on `path`. The editor may include arguments, as in
+/// `code --wait`.
+fn run_editor(editor: &str, path: &Path) -> Result<()> {
+    let mut parts = editor.split_whitespace();
+    let program = parts
+        .next()
+        .ok_or_else(|| CgipError::Config("No editor configured".into()))?;
+    let status = Command::new(program)
+        .args(parts)
+        .arg(path)
+        .status()
+        .map_err(|e| CgipError::Config(format!("Could not run editor '{}': {}", editor, e)))?;
+    if !status.success() {
+        return Err(CgipError::Usage(format!(
+            "Editor '{}' exited with {}, the session was not changed",
+            editor, status
+        )));
+    }
+    Ok(())
+}
+
+/// Reads the edited YAML back, rejecting messages `cgip` couldn't send.
+/// A file with nothing but comments empties the session.
+fn parse(text: &str) -> Result<Vec<Message>> {
+    let is_blank = text
+        .lines()
+        .all(|line| line.trim().is_empty() || line.trim_start().starts_with('#'));
+    if is_blank {
+        return Ok(Vec::new());
+    }
+
+    // Unknown roles are rejected while parsing
+    export::import(text, Format::Yaml)
+}
+
+fn ask_to_reopen() -> Result<bool> {
+    if !atty::is(Stream::Stdin) {
+        return Ok(false);
+    }
+    eprint!("Open the editor again to fix it? [Y/n] ");
+    io::stderr().flush()?;
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer)?;
+    Ok(!answer.trim().eq_ignore_ascii_case("n"))
+}
+
+/// Opens session `name` as YAML in the user's editor and saves the result
+/// once it parses. Images are shown as their `cgip-image:` references
+/// rather than as base64.
+pub fn run(store: &SessionStore, name: &str) -> Result<()> {
+    let original = store.load_stashed(name)?;
+    let mut initial = format!(
+        "# Session '{}'. Edit, save and quit to apply; delete every message to\n# empty the session.\n",
+        name
+    );
+    if !original.is_empty() {
+        initial.push_str(&export::export(name, &original, Format::Yaml)?);
+    }
+
+    let mut file = tempfile::Builder::new()
+        .prefix("cgip-session-")
+        .suffix(".yaml")
+        .tempfile()?;
+    file.write_all(initial.as_bytes())?;
+    file.flush()?;
+
+    let editor = editor();
+    let edited = loop {
+        run_editor(&editor, file.path())?;
+        let text = fs::read_to_string(file.path())?;
+        if text == initial {
+            println!("No changes to session '{}'", name);
+            return Ok(());
+        }
+        match parse(&text) {
+            Ok(messages) => break messages,
+            Err(e) => {
+                eprintln!("cgip: {}", e);
+                if !ask_to_reopen()? {
+                    return Err(CgipError::Usage(format!(
+                        "Session '{}' was not changed",
+                        name
+                    )));
+                }
+            }
+        }
+    };
+
+    let count = edited.len();
+    let unchanged = store.update(name, |messages| {
+        // Compare the stored form, which the edit started from
+        let to_value = |messages: &[Message]| {
+            serde_json::to_value(messages)
+                .map_err(|e| CgipError::Io(format!("Could not read session '{}': {}", name, e)))
+        };
+        let unchanged = to_value(&store.load_stashed(name)?)? == to_value(&original)?;
+        if unchanged {
+            *messages = edited;
+        }
+        Ok(unchanged)
+    })?;
+    if !unchanged {
+        return Err(keep_edit(file, name));
+    }
+
+    println!("Saved {} messages to session '{}'", count, name);
+    Ok(())
+}
+
+/// Keeps the edited file when the session changed underneath it, so the
+/// edit isn't lost.
+fn keep_edit(file: NamedTempFile, name: &str) -> CgipError {
+    match file.keep() {
+        Ok((_, path)) => CgipError::Usage(format!(
+            "Session '{}' changed while it was being edited, your edit is in {}",
+            name,
+            path.display()
+        )),
+        Err(e) => CgipError::Io(format!(
+            "Session '{}' changed while it was being edited and the edit could not be kept: {}",
+            name, e
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_edited_yaml() {
+        let messages = parse("# comment\n- role: user\n  content: fixed question\n").unwrap();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].content.to_string(), "fixed question");
+
+        assert!(parse("# only comments\n\n").unwrap().is_empty());
+        assert!(parse("- role: robot\n  content: hi\n").is_err());
+        assert!(parse("- role: user\n  content: [unclosed\n").is_err());
+    }
+}
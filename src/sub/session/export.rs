@@ -0,0 +1,462 @@
+use std::path::Path;
+use std::str::FromStr;
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde_json::Value;
+
+use crate::chatgpt::message::ContentPart;
+use crate::chatgpt::{Message, MessageContent, Role};
+use crate::error::{CgipError, Result};
+use crate::utils::{escape_html, markdown_to_html};
+
+/// Starts the comment in front of every message of a Markdown export. It
+/// holds the message as JSON, minus plain text content, so an import can
+/// restore roles, timestamps and models exactly.
+const MARKDOWN_MARKER: &str = "<!-- cgip ";
+/// Opens the script element of an HTML export that holds the messages.
+const HTML_DATA_START: &str = r#"<script type="application/json" id="cgip-session">"#;
+const HTML_DATA_END: &str = "</script>";
+
+const HTML_STYLE: &str = r#"
+:root { color-scheme: light dark; --fg: #1f2328; --bg: #ffffff; --muted: #656d76;
+  --border: #d0d7de; --user: #ddf4ff; --assistant: #f6f8fa; --system: #fff8c5; --code: #eff1f3; }
+@media (prefers-color-scheme: dark) {
+  :root { --fg: #e6edf3; --bg: #0d1117; --muted: #8d96a0; --border: #30363d;
+    --user: #0c2d4b; --assistant: #161b22; --system: #3b2e00; --code: #1f242c; }
+}
+body { margin: 0; background: var(--bg); color: var(--fg);
+  font: 16px/1.55 -apple-system, BlinkMacSystemFont, "Segoe UI", Helvetica, Arial, sans-serif; }
+header, main { max-width: 52rem; margin: 0 auto; padding: 0 1rem; }
+header { padding-top: 2rem; }
+header h1 { margin-bottom: 0.25rem; }
+header p, .meta { color: var(--muted); font-size: 0.875rem; }
+article { margin: 1rem 0; padding: 0.75rem 1rem; border: 1px solid var(--border); border-radius: 8px; }
+article.user { background: var(--user); }
+article.assistant { background: var(--assistant); }
+article.system, article.developer { background: var(--system); }
+.meta { display: flex; gap: 0.75rem; margin-bottom: 0.5rem; }
+.meta .role { font-weight: 600; color: var(--fg); }
+.content > :first-child { margin-top: 0; }
+.content > :last-child { margin-bottom: 0; }
+.plain { white-space: pre-wrap; margin: 0; font: inherit; }
+pre, code { font-family: ui-monospace, SFMono-Regular, Menlo, Consolas, monospace; font-size: 0.875em; }
+pre:not(.plain) { background: var(--code); padding: 0.75rem; border-radius: 6px; overflow-x: auto; }
+:not(pre) > code { background: var(--code); padding: 0.1em 0.3em; border-radius: 4px; }
+img { max-width: 100%; }
+table { border-collapse: collapse; }
+th, td { border: 1px solid var(--border); padding: 0.25rem 0.5rem; }
+"#;
+
+/// A document format for `cgip session export` and `import`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Markdown,
+    Json,
+    Yaml,
+    Html,
+}
+
+impl FromStr for Format {
+    type Err = CgipError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "md" | "markdown" => Ok(Format::Markdown),
+            "json" => Ok(Format::Json),
+            "yaml" | "yml" => Ok(Format::Yaml),
+            "html" | "htm" => Ok(Format::Html),
+            _ => Err(CgipError::Usage(format!(
+                "Unknown format '{}', expected md, json, yaml or html",
+                s
+            ))),
+        }
+    }
+}
+
+impl Format {
+    /// The format implied by the extension of `path`.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        path.extension()?.to_str()?.parse().ok()
+    }
+}
+
+/// Writes the messages of session `name` as a document in `format`.
+pub fn export(name: &str, messages: &[Message], format: Format) -> Result<String> {
+    match format {
+        Format::Markdown => Ok(to_markdown(name, messages)),
+        Format::Json => serde_json::to_string_pretty(messages)
+            .map_err(|e| CgipError::Io(format!("Could not write JSON: {}", e))),
+        Format::Yaml => serde_yaml::to_string(messages)
+            .map_err(|e| CgipError::Io(format!("Could not write YAML: {}", e))),
+        Format::Html => to_html(name, messages),
+    }
+}
+
+/// Reads back the messages of a document written by [`export`].
+pub fn import(text: &str, format: Format) -> Result<Vec<Message>> {
+    let invalid =
+        |e: &dyn std::fmt::Display| CgipError::Usage(format!("Invalid session file: {}", e));
+    match format {
+        Format::Markdown => from_markdown(text),
+        Format::Json => serde_json::from_str(text).map_err(|e| invalid(&e)),
+        Format::Yaml => serde_yaml::from_str(text).map_err(|e| invalid(&e)),
+        Format::Html => {
+            let start = text
+                .find(HTML_DATA_START)
+                .map(|i| i + HTML_DATA_START.len())
+                .ok_or_else(|| invalid(&"no cgip session data in the HTML"))?;
+            let end = text[start..]
+                .find(HTML_DATA_END)
+                .ok_or_else(|| invalid(&"unterminated session data in the HTML"))?;
+            serde_json::from_str(&text[start..start + end]).map_err(|e| invalid(&e))
+        }
+    }
+}
+
+fn now() -> String {
+    Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+/// RFC 3339 timestamps are shown as `2024-05-01 12:30:00 UTC`; anything
+/// else is shown as is.
+fn display_time(timestamp: &str) -> String {
+    DateTime::parse_from_rfc3339(timestamp)
+        .map(|t| {
+            t.with_timezone(&Utc)
+                .format("%Y-%m-%d %H:%M:%S UTC")
+                .to_string()
+        })
+        .unwrap_or_else(|_| timestamp.to_string())
+}
+
+fn role_title(role: &str) -> String {
+    let mut chars = role.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+/// The timestamp and model of a message, for the line under its heading.
+fn meta_line(msg: &Message) -> Option<String> {
+    let parts: Vec<String> = msg
+        .meta
+        .timestamp
+        .as_deref()
+        .map(display_time)
+        .into_iter()
+        .chain(msg.meta.model.clone())
+        .collect();
+    (!parts.is_empty()).then(|| parts.join(" · "))
+}
+
+fn to_markdown(name: &str, messages: &[Message]) -> String {
+    let mut md = format!(
+        "# Session {}\n\nExported {} · {} messages\n",
+        name,
+        display_time(&now()),
+        messages.len()
+    );
+
+    for msg in messages {
+        // Plain text lives in the body; anything richer stays in the marker
+        // so images survive a round trip.
+        let mut header = serde_json::to_value(msg).unwrap_or(Value::Null);
+        let body = match &msg.content {
+            MessageContent::Text(text) => {
+                if let Value::Object(map) = &mut header {
+                    map.remove("content");
+                }
+                text.clone()
+            }
+            MessageContent::Multi(parts) => parts
+                .iter()
+                .map(|part| match part {
+                    ContentPart::Text { text } => text.clone(),
+                    ContentPart::ImageUrl { image_url } if image_url.url.starts_with("data:") => {
+                        "*[image]*".to_string()
+                    }
+                    ContentPart::ImageUrl { image_url } => format!("![image]({})", image_url.url),
+                })
+                .collect::<Vec<_>>()
+                .join("\n\n"),
+        };
+
+        md.push_str(&format!("\n{}{} -->\n", MARKDOWN_MARKER, header));
+        md.push_str(&format!("## {}\n\n", role_title(msg.role.as_str())));
+        if let Some(meta) = meta_line(msg) {
+            md.push_str(&format!("*{}*\n\n", meta));
+        }
+        md.push_str(&body);
+        md.push('\n');
+    }
+    md
+}
+
+fn from_markdown(text: &str) -> Result<Vec<Message>> {
+    // A marker line must hold a JSON message header, so a reply that happens
+    // to contain a similar comment stays part of the body.
+    let mut blocks: Vec<(Value, String)> = Vec::new();
+    for line in text.split_inclusive('\n') {
+        let header = line
+            .trim_end()
+            .strip_prefix(MARKDOWN_MARKER)
+            .and_then(|rest| rest.strip_suffix("-->"))
+            .and_then(|header| serde_json::from_str::<Value>(header.trim()).ok())
+            .filter(|header| header.get("role").is_some());
+        match header {
+            Some(header) => {
+                // `to_markdown` puts a blank line before each marker
+                if let Some((_, body)) = blocks.last_mut() {
+                    body.pop();
+                }
+                blocks.push((header, String::new()));
+            }
+            None => {
+                if let Some((_, body)) = blocks.last_mut() {
+                    body.push_str(line);
+                }
+            }
+        }
+    }
+    if blocks.is_empty() {
+        return Err(CgipError::Usage(
+            "Invalid session file: no cgip messages in the Markdown".to_string(),
+        ));
+    }
+
+    blocks
+        .into_iter()
+        .map(|(mut header, body)| {
+            if header.get("content").is_none() {
+                // Skip the role heading and the timestamp line written by
+                // `to_markdown`, each followed by a blank line, and the
+                // newline after the content. The rest is kept as it was.
+                let skip_line = |text: &str| -> String {
+                    let rest = text.split_once('\n').map_or("", |(_, rest)| rest);
+                    rest.strip_prefix('\n').unwrap_or(rest).to_string()
+                };
+                let mut content = skip_line(&body);
+                if header.get("timestamp").is_some() || header.get("model").is_some() {
+                    content = skip_line(&content);
+                }
+                if content.ends_with('\n') {
+                    content.pop();
+                }
+                if let Value::Object(map) = &mut header {
+                    map.insert("content".to_string(), Value::String(content));
+                }
+            }
+
+            serde_json::from_value(header).map_err(|e| {
+                CgipError::Usage(format!("Invalid session file: bad message header: {}", e))
+            })
+        })
+        .collect()
+}
+
+/// Replies are rendered as Markdown. Other messages are often piped logs or
+/// code, so they are shown as preformatted text.
+fn content_html(msg: &Message) -> String {
+    let render = |text: &str| {
+        if msg.role == Role::Assistant {
+            markdown_to_html(text)
+        } else {
+            format!("<pre class=\"plain\">{}</pre>\n", escape_html(text))
+        }
+    };
+    match &msg.content {
+        MessageContent::Text(text) => render(text),
+        MessageContent::Multi(parts) => parts
+            .iter()
+            .map(|part| match part {
+                ContentPart::Text { text } => render(text),
+                ContentPart::ImageUrl { image_url } => {
+                    format!(
+                        "<p><img src=\"{}\" alt=\"image\"></p>\n",
+                        escape_html(&image_url.url)
+                    )
+                }
+            })
+            .collect(),
+    }
+}
+
+fn to_html(name: &str, messages: &[Message]) -> Result<String> {
+    let mut body = String::new();
+    for msg in messages {
+        let role = escape_html(msg.role.as_str());
+        body.push_str(&format!(
+            "<article class=\"message {}\">\n<div class=\"meta\">",
+            role
+        ));
+        body.push_str(&format!(
+            "<span class=\"role\">{}</span>",
+            escape_html(&role_title(msg.role.as_str()))
+        ));
+        if let Some(timestamp) = &msg.meta.timestamp {
+            body.push_str(&format!(
+                "<time datetime=\"{}\">{}</time>",
+                escape_html(timestamp),
+                escape_html(&display_time(timestamp))
+            ));
+        }
+        if let Some(model) = &msg.meta.model {
+            body.push_str(&format!(
+                "<span class=\"model\">{}</span>",
+                escape_html(model)
+            ));
+        }
+        body.push_str("</div>\n<div class=\"content\">\n");
+        body.push_str(&content_html(msg));
+        body.push_str("</div>\n</article>\n");
+    }
+
+    // `<` only occurs inside JSON strings, so escaping it keeps the data from
+    // closing the script element early.
+    let data = serde_json::to_string(messages)
+        .map_err(|e| CgipError::Io(format!("Could not write HTML: {}", e)))?
+        .replace('<', "\\u003c");
+    let title = escape_html(name);
+
+    Ok(format!(
+        "<!DOCTYPE html>
+<html lang=\"en\">
+<head>
+<meta charset=\"utf-8\">
+<meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">
+<title>Session {title}</title>
+<style>{style}</style>
+</head>
+<body>
+<header>
+<h1>Session {title}</h1>
+<p>Exported {exported} · {count} messages</p>
+</header>
+<main>
+{body}</main>
+{data_start}{data}{data_end}
+</body>
+</html>
+",
+        title = title,
+        style = HTML_STYLE,
+        exported = display_time(&now()),
+        count = messages.len(),
+        body = body,
+        data_start = HTML_DATA_START,
+        data = data,
+        data_end = HTML_DATA_END,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chatgpt::message::{ImageUrl, MessageMeta, ToolCall};
+
+    fn session() -> Vec<Message> {
+        let mut question = Message::new(Role::User, "How do I print in Rust?".to_string());
+        question.meta.timestamp = Some("2024-05-01T12:30:00Z".to_string());
+        let mut answer = Message::new(Role::Assistant, "Use a macro:\n\n```rust\nprintln!(\"<hi>\");\n```\n\n<!-- cgip is not a marker here -->".to_string());
+        answer.meta = MessageMeta {
+            timestamp: Some("2024-05-01T12:30:04Z".to_string()),
+            model: Some("gpt-4o".to_string()),
+            alternatives: Vec::new(),
+        };
+        let mut image = Message::new(Role::User, String::new());
+        image.content = MessageContent::Multi(vec![
+            ContentPart::Text {
+                text: "What is this?".to_string(),
+            },
+            ContentPart::ImageUrl {
+                image_url: ImageUrl {
+                    url: "data:image/png;base64,AAAA".to_string(),
+                    detail: None,
+                },
+            },
+        ]);
+        let mut call = Message::new(Role::Assistant, String::new());
+        call.tool_calls = Some(vec![ToolCall::new(
+            "call_1",
+            "execute",
+            &serde_json::json!({"command": "date"}),
+        )]);
+        let mut result = Message::new(Role::Tool, "Wed May  1 12:31:00 UTC 2024".to_string());
+        result.tool_call_id = Some("call_1".to_string());
+        vec![
+            Message::new(Role::Developer, "be brief".to_string()),
+            question,
+            answer,
+            image,
+            call,
+            result,
+        ]
+    }
+
+    fn assert_same(imported: &[Message], original: &[Message]) {
+        assert_eq!(
+            serde_json::to_value(imported).unwrap(),
+            serde_json::to_value(original).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_round_trips() {
+        let messages = session();
+        for format in [Format::Markdown, Format::Json, Format::Yaml, Format::Html] {
+            let document = export("demo", &messages, format).unwrap();
+            let imported = import(&document, format).unwrap();
+            assert_same(&imported, &messages);
+        }
+    }
+
+    #[test]
+    fn test_markdown_keeps_content_verbatim() {
+        let messages: Vec<Message> = ["\n\nindented\n", "\n", "", "a\n\n\nb\n\n"]
+            .iter()
+            .map(|text| Message::new(Role::User, text.to_string()))
+            .collect();
+        let document = export("demo", &messages, Format::Markdown).unwrap();
+        assert_same(&import(&document, Format::Markdown).unwrap(), &messages);
+    }
+
+    #[test]
+    fn test_markdown_keeps_fences_and_metadata() {
+        let md = export("demo", &session(), Format::Markdown).unwrap();
+        assert!(md.starts_with("# Session demo\n"));
+        assert!(md.contains("## Assistant\n\n*2024-05-01 12:30:04 UTC · gpt-4o*\n\n"));
+        assert!(md.contains("```rust\nprintln!(\"<hi>\");\n```"));
+    }
+
+    #[test]
+    fn test_html_escapes_content() {
+        let html = export("<demo>", &session(), Format::Html).unwrap();
+        assert!(html.contains("<title>Session &lt;demo&gt;</title>"));
+        assert!(html.contains("<code class=\"language-rust\">println!(\"&lt;hi&gt;\");"));
+        assert!(html.contains("&lt;!-- cgip is not a marker here --&gt;"));
+        assert_eq!(html.matches("</script>").count(), 1);
+        assert!(html.contains("<time datetime=\"2024-05-01T12:30:00Z\">"));
+    }
+
+    #[test]
+    fn test_yaml_matches_stdin_format() {
+        let yaml = "- role: user\n  content: hello\n- role: assistant\n  content: hi\n";
+        let messages = import(yaml, Format::Yaml).unwrap();
+        assert_eq!(messages.len(), 2);
+        assert!(
+            crate::utils::is_valid_yaml(&export("demo", &messages, Format::Yaml).unwrap()).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_format_from_path() {
+        assert_eq!(
+            Format::from_path(Path::new("chat.md")),
+            Some(Format::Markdown)
+        );
+        assert_eq!(Format::from_path(Path::new("chat.YML")), Some(Format::Yaml));
+        assert_eq!(Format::from_path(Path::new("chat")), None);
+        assert!("pdf".parse::<Format>().is_err());
+    }
+}
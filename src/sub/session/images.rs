@@ -0,0 +1,228 @@
+use std::{
+    collections::HashSet,
+    fs,
+    io::{self, ErrorKind},
+    path::Path,
+};
+
+use sha2::{Digest, Sha256};
+
+use super::store::write_atomic;
+use crate::chatgpt::{message::ContentPart, Message, MessageContent};
+
+/// Directory in the session directory holding the images of all sessions.
+const IMAGES_DIR: &str = ".images";
+/// Prefix of the image URLs that refer to a stored image by its hash.
+const REF_PREFIX: &str = "cgip-image:";
+
+/// Text put in place of a stored image that can't be found.
+const MISSING_IMAGE: &str = "[Image no longer available]";
+
+/// Whether `hash` is a SHA-256 hash in the form `stash` names images by.
+/// Anything else in a reference, such as `../`, could point outside the
+/// image directory.
+fn is_hash(hash: &str) -> bool {
+    hash.len() == 64 && hash.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+}
+
+/// The image parts of `messages`, including those of kept alternatives.
+fn image_parts(messages: &mut [Message]) -> Vec<&mut ContentPart> {
+    let mut images = Vec::new();
+    for msg in messages {
+        if let MessageContent::Multi(parts) = &mut msg.content {
+            images.extend(
+                parts
+                    .iter_mut()
+                    .filter(|part| matches!(part, ContentPart::ImageUrl { .. })),
+            );
+        }
+        images.extend(image_parts(&mut msg.meta.alternatives));
+    }
+    images
+}
+
+/// Moves inline `data:` images out of `messages` into `dir`, named by the
+/// hash of their content, and leaves a reference in their place. The same
+/// image is only stored once however often it is sent.
+pub fn stash(messages: &mut [Message], dir: &Path) -> io::Result<()> {
+    for part in image_parts(messages) {
+        let ContentPart::ImageUrl { image_url } = part else {
+            continue;
+        };
+        if !image_url.url.starts_with("data:") {
+            continue;
+        }
+        let hash = format!("{:x}", Sha256::digest(image_url.url.as_bytes()));
+        let path = dir.join(IMAGES_DIR).join(&hash);
+        if !path.exists() {
+            fs::create_dir_all(dir.join(IMAGES_DIR))?;
+            write_atomic(&path, image_url.url.as_bytes())?;
+        }
+        image_url.url = format!("{}{}", REF_PREFIX, hash);
+    }
+    Ok(())
+}
+
+/// Puts the images stored by `stash` back into `messages`. An image that
+/// can't be read is replaced by a note, with a warning, so the rest of the
+/// session can still be used.
+pub fn restore(messages: &mut [Message], dir: &Path) {
+    for part in image_parts(messages) {
+        let ContentPart::ImageUrl { image_url } = part else {
+            continue;
+        };
+        let Some(hash) = image_url.url.strip_prefix(REF_PREFIX) else {
+            continue;
+        };
+        if !is_hash(hash) {
+            eprintln!(
+                "Warning: ignoring invalid image reference '{}'",
+                image_url.url
+            );
+            *part = ContentPart::Text {
+                text: MISSING_IMAGE.to_string(),
+            };
+            continue;
+        }
+        match fs::read_to_string(dir.join(IMAGES_DIR).join(hash)) {
+            Ok(data) => image_url.url = data,
+            Err(e) => {
+                if e.kind() == ErrorKind::NotFound {
+                    eprintln!("Warning: image {} is missing from the session store", hash);
+                } else {
+                    eprintln!("Warning: could not read image {}: {}", hash, e);
+                }
+                *part = ContentPart::Text {
+                    text: MISSING_IMAGE.to_string(),
+                };
+            }
+        }
+    }
+}
+
+/// The hashes of the stored images `messages` refer to.
+pub fn references(messages: &mut [Message]) -> HashSet<String> {
+    image_parts(messages)
+        .into_iter()
+        .filter_map(|part| match part {
+            ContentPart::ImageUrl { image_url } => image_url.url.strip_prefix(REF_PREFIX),
+            _ => None,
+        })
+        .map(str::to_string)
+        .collect()
+}
+
+/// Removes the stored images in `dir` that aren't in `keep`. Returns how
+/// many were removed.
+pub fn prune(dir: &Path, keep: &HashSet<String>) -> io::Result<usize> {
+    let entries = match fs::read_dir(dir.join(IMAGES_DIR)) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e),
+    };
+    let mut removed = 0;
+    for entry in entries {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        if is_hash(&name) && !keep.contains(&name) {
+            fs::remove_file(entry.path())?;
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chatgpt::message::ImageUrl;
+    use crate::chatgpt::Role;
+
+    fn image_message(url: &str) -> Message {
+        let mut msg = Message::new(Role::User, String::new());
+        msg.content = MessageContent::Multi(vec![
+            ContentPart::Text {
+                text: "what is this?".to_string(),
+            },
+            ContentPart::ImageUrl {
+                image_url: ImageUrl {
+                    url: url.to_string(),
+                    detail: None,
+                },
+            },
+        ]);
+        msg
+    }
+
+    fn url(msg: &Message) -> &str {
+        match &msg.content {
+            MessageContent::Multi(parts) => match &parts[1] {
+                ContentPart::ImageUrl { image_url } => &image_url.url,
+                _ => panic!("expected an image"),
+            },
+            _ => panic!("expected parts"),
+        }
+    }
+
+    #[test]
+    fn test_stash_and_restore() {
+        let dir = tempfile::tempdir().unwrap();
+        let data = "data:image/png;base64,iVBORw0KGgo=";
+        let remote = "https://example.com/cat.png";
+        let mut messages = vec![
+            image_message(data),
+            image_message(data),
+            image_message(remote),
+        ];
+
+        stash(&mut messages, dir.path()).unwrap();
+        assert!(url(&messages[0]).starts_with(REF_PREFIX));
+        assert_eq!(url(&messages[0]), url(&messages[1]));
+        assert_eq!(url(&messages[2]), remote);
+        assert_eq!(
+            fs::read_dir(dir.path().join(IMAGES_DIR)).unwrap().count(),
+            1
+        );
+
+        restore(&mut messages, dir.path());
+        assert_eq!(url(&messages[0]), data);
+        assert_eq!(url(&messages[1]), data);
+
+        stash(&mut messages, dir.path()).unwrap();
+        fs::remove_dir_all(dir.path().join(IMAGES_DIR)).unwrap();
+        restore(&mut messages, dir.path());
+        assert_eq!(
+            messages[0].content.to_string(),
+            format!("what is this? {}", MISSING_IMAGE)
+        );
+    }
+
+    #[test]
+    fn test_restore_rejects_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join(IMAGES_DIR)).unwrap();
+        fs::write(dir.path().join("secret"), "data:image/png;base64,c2VjcmV0").unwrap();
+
+        let mut messages = vec![image_message(&format!("{}../secret", REF_PREFIX))];
+        restore(&mut messages, dir.path());
+        assert_eq!(
+            messages[0].content.to_string(),
+            format!("what is this? {}", MISSING_IMAGE)
+        );
+    }
+
+    #[test]
+    fn test_prune_keeps_referenced_images() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut kept = vec![image_message("data:image/png;base64,a2VwdA==")];
+        let mut dropped = vec![image_message("data:image/png;base64,ZHJvcHBlZA==")];
+        stash(&mut kept, dir.path()).unwrap();
+        stash(&mut dropped, dir.path()).unwrap();
+
+        assert_eq!(prune(dir.path(), &references(&mut kept)).unwrap(), 1);
+        restore(&mut kept, dir.path());
+        assert_eq!(url(&kept[0]), "data:image/png;base64,a2VwdA==");
+        restore(&mut dropped, dir.path());
+        assert!(dropped[0].content.to_string().ends_with(MISSING_IMAGE));
+    }
+}
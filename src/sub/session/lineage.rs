@@ -0,0 +1,151 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+/// Where a forked session came from.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Fork {
+    pub parent: String,
+    /// Number of messages copied from the parent.
+    pub at: usize,
+}
+
+/// The fork records of all sessions, keyed by the name of the fork.
+/// Sessions that weren't forked have no entry.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Lineage {
+    forks: BTreeMap<String, Fork>,
+}
+
+impl Lineage {
+    pub fn get(&self, name: &str) -> Option<&Fork> {
+        self.forks.get(name)
+    }
+
+    pub fn insert(&mut self, name: &str, fork: Fork) {
+        self.forks.insert(name.to_string(), fork);
+    }
+
+    /// Moves the record of `name` and the parent links of its forks to
+    /// `new_name`.
+    pub fn rename(&mut self, name: &str, new_name: &str) {
+        if let Some(fork) = self.forks.remove(name) {
+            self.forks.insert(new_name.to_string(), fork);
+        }
+        for fork in self.forks.values_mut() {
+            if fork.parent == name {
+                fork.parent = new_name.to_string();
+            }
+        }
+    }
+
+    /// Forgets `name`. Its forks become top level sessions, so a later
+    /// session with the same name isn't mistaken for their parent.
+    pub fn remove(&mut self, name: &str) {
+        self.forks
+            .retain(|fork_name, fork| fork_name != name && fork.parent != name);
+    }
+
+    /// Draws `names` as a tree, with each fork below its parent. Forks whose
+    /// parent no longer exists are shown at the top level. `label` gives the
+    /// text shown for each session. Returns each session name with its line.
+    pub fn render(
+        &self,
+        names: &[String],
+        label: impl Fn(&str) -> String,
+    ) -> Vec<(String, String)> {
+        let is_root = |name: &String| {
+            self.forks
+                .get(name)
+                .is_none_or(|fork| !names.contains(&fork.parent))
+        };
+
+        let mut lines = Vec::new();
+        for root in names.iter().filter(|name| is_root(name)) {
+            lines.push((root.clone(), label(root)));
+            self.render_children(root, names, "", &label, &mut lines);
+        }
+        lines
+    }
+
+    fn render_children(
+        &self,
+        parent: &str,
+        names: &[String],
+        indent: &str,
+        label: &impl Fn(&str) -> String,
+        lines: &mut Vec<(String, String)>,
+    ) {
+        let children: Vec<&String> = names
+            .iter()
+            .filter(|name| {
+                self.forks
+                    .get(*name)
+                    .is_some_and(|fork| fork.parent == parent)
+            })
+            .collect();
+
+        for (i, child) in children.iter().enumerate() {
+            let last = i + 1 == children.len();
+            let branch = if last { "└── " } else { "├── " };
+            let line = format!("{}{}{}", indent, branch, label(child));
+            lines.push((child.to_string(), line));
+            let indent = format!("{}{}", indent, if last { "    " } else { "│   " });
+            self.render_children(child, names, &indent, label, lines);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fork(parent: &str, at: usize) -> Fork {
+        Fork {
+            parent: parent.to_string(),
+            at,
+        }
+    }
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn test_render_tree() {
+        let mut lineage = Lineage::default();
+        lineage.insert("a", fork("main", 4));
+        lineage.insert("a2", fork("a", 6));
+        lineage.insert("b", fork("main", 4));
+        lineage.insert("orphan", fork("deleted", 2));
+
+        let lines: Vec<String> = lineage
+            .render(
+                &names(&["a", "a2", "b", "main", "orphan", "other"]),
+                |name| name.to_string(),
+            )
+            .into_iter()
+            .map(|(_, line)| line)
+            .collect();
+        assert_eq!(
+            lines,
+            vec!["main", "├── a", "│   └── a2", "└── b", "orphan", "other"]
+        );
+    }
+
+    #[test]
+    fn test_rename_and_remove() {
+        let mut lineage = Lineage::default();
+        lineage.insert("a", fork("main", 4));
+        lineage.insert("a2", fork("a", 6));
+
+        lineage.rename("a", "first");
+        assert_eq!(lineage.get("first"), Some(&fork("main", 4)));
+        assert_eq!(lineage.get("a2"), Some(&fork("first", 6)));
+
+        lineage.remove("first");
+        assert_eq!(lineage.get("first"), None);
+        assert_eq!(lineage.get("a2"), None);
+    }
+}
@@ -0,0 +1,468 @@
+use std::{fs, path::Path};
+
+use chrono::{SecondsFormat, Utc};
+
+use crate::{
+    args::{SessionAction, SessionSubCommand},
+    chatgpt::{Message, Role},
+    printer::Printer,
+};
+
+use crate::chatgpt::response::Usage;
+use crate::config_manager::ConfigManager;
+use crate::error::{CgipError, Result};
+use crate::history::HistoryStore;
+use export::Format;
+pub use window::group_turns;
+use window::WindowPolicy;
+
+pub use store::SessionStore;
+
+mod edit;
+mod export;
+mod images;
+mod lineage;
+mod store;
+mod window;
+
+/// The store and name of the active session, if there is one.
+fn current_session() -> Option<(SessionStore, String)> {
+    let store = SessionStore::open_default().ok()?;
+    let name = store.current()?;
+    Some((store, name))
+}
+
+pub fn delete_tty_context() -> Result<()> {
+    let (store, name) = match current_session() {
+        Some(val) => val,
+        None => {
+            return Ok(()); //just silently exit this function
+        }
+    };
+
+    let _lock = store.lock(&name)?;
+    if store.exists(&name) {
+        store.save(&name, &[])?;
+    }
+    Ok(())
+}
+
+/// Records `messages` in the history database and appends them to the
+/// current session.
+pub fn save_to_tty_context(
+    config_manager: &ConfigManager,
+    messages: Vec<Message>,
+    usage: Option<&Usage>,
+) {
+    record_history(config_manager, &messages, usage);
+    append_to_session(config_manager, messages);
+}
+
+fn record_history(config_manager: &ConfigManager, messages: &[Message], usage: Option<&Usage>) {
+    let config = &config_manager.config;
+    if config.history {
+        let store = SessionStore::open_default().ok();
+        let session = store.as_ref().and_then(|store| store.current());
+        // Images are recorded by reference to the copy in the session store
+        let mut messages = messages.to_vec();
+        if let Some(store) = &store {
+            if let Err(e) = store.stash_images(&mut messages) {
+                eprintln!("Warning: {}", e);
+            }
+        }
+        // A reply names its model when a directive picked another one
+        let model = messages
+            .iter()
+            .rev()
+            .find_map(|msg| msg.meta.model.as_deref())
+            .unwrap_or(&config.model);
+        let recorded = HistoryStore::open_default()
+            .and_then(|history| history.record(session.as_deref(), &messages, model, usage));
+        if let Err(e) = recorded {
+            eprintln!("Warning: could not record history: {}", e);
+        }
+    }
+}
+
+/// Notes when each message was added and which model wrote each reply.
+fn stamp(messages: &mut [Message], model: &str) {
+    let now = Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true);
+    for msg in messages {
+        msg.meta.timestamp.get_or_insert_with(|| now.clone());
+        if msg.role == Role::Assistant {
+            msg.meta.model.get_or_insert_with(|| model.to_string());
+        }
+    }
+}
+
+/// Appends `messages` to the current session without recording them in the
+/// history database.
+pub fn append_to_session(config_manager: &ConfigManager, mut messages: Vec<Message>) {
+    let (store, name) = match current_session() {
+        Some(val) => val,
+        None => {
+            return; //just silently exit this function
+        }
+    };
+
+    let config = &config_manager.config;
+    stamp(&mut messages, &config.model);
+
+    // Other cgip processes may share the session, so the read, append and
+    // write happen under one lock.
+    let saved = store.update(&name, |tty_context| {
+        tty_context.extend(messages);
+        let policy = WindowPolicy::from_config(config);
+        *tty_context = policy.apply(std::mem::take(tty_context), &config.model);
+        Ok(())
+    });
+    if let Err(e) = saved {
+        eprintln!("Warning: could not save session '{}': {}", name, e);
+    }
+}
+
+/// Index of the last user message, the end of the turn `cgip retry` sends
+/// again. Everything after it is the reply being replaced.
+pub fn last_user_index(messages: &[Message]) -> Option<usize> {
+    messages.iter().rposition(|msg| msg.role == Role::User)
+}
+
+/// Replaces the reply to the last user message of session `name` with
+/// `reply`. `context_len` is the number of messages `reply` answers; if the
+/// session no longer ends with that turn it is left alone. With `keep` the
+/// old reply is kept as alternatives of the new one.
+pub fn replace_last_reply(
+    config_manager: &ConfigManager,
+    name: &str,
+    context_len: usize,
+    mut reply: Message,
+    keep: bool,
+    usage: Option<&Usage>,
+) -> Result<()> {
+    let store = SessionStore::open_default()?;
+    record_history(config_manager, std::slice::from_ref(&reply), usage);
+    stamp(
+        std::slice::from_mut(&mut reply),
+        &config_manager.config.model,
+    );
+
+    store.update(name, |messages| {
+        if !replace_reply(messages, context_len, reply, keep) {
+            return Err(CgipError::Usage(format!(
+                "Session '{}' changed while retrying, the new reply was not saved",
+                name
+            )));
+        }
+        Ok(())
+    })
+}
+
+/// Puts `reply` after the first `context_len` messages in place of the old
+/// reply. Returns false, changing nothing, when those messages don't end
+/// with the last user message.
+fn replace_reply(
+    messages: &mut Vec<Message>,
+    context_len: usize,
+    mut reply: Message,
+    keep: bool,
+) -> bool {
+    if context_len == 0 || last_user_index(messages) != Some(context_len - 1) {
+        return false;
+    }
+    let old_reply = messages.split_off(context_len);
+    if keep {
+        reply.meta.alternatives = old_reply
+            .into_iter()
+            .filter(|msg| msg.role == Role::Assistant)
+            .flat_map(|mut msg| {
+                let mut alternatives = std::mem::take(&mut msg.meta.alternatives);
+                alternatives.push(msg);
+                alternatives
+            })
+            .collect();
+    }
+    messages.push(reply);
+    true
+}
+
+pub fn read_from_tty_context() -> Vec<Message> {
+    match current_session() {
+        Some((store, name)) => store.load(&name).unwrap_or_else(|e| {
+            eprintln!("Warning: {}", e);
+            Vec::new()
+        }),
+        None => Vec::new(),
+    }
+}
+
+pub fn print_messages(messages: &[Message], printer: &mut dyn Printer) {
+    for msg in messages.iter().filter(|msg| !msg.role.is_instruction()) {
+        printer.print_message(msg);
+    }
+    printer.finish();
+}
+
+/// Removes images left behind by `delete` or `pop`. The session change has
+/// been made either way, so a failure is only a warning.
+fn prune_images(store: &SessionStore) {
+    if let Err(e) = store.prune_images() {
+        eprintln!("Warning: could not remove unused images: {}", e);
+    }
+}
+
+/// `switch` and `new` only move the pointer, which the environment overrides.
+fn warn_if_env_overrides() {
+    if let Ok(name) = std::env::var("CGIP_SESSION_NAME") {
+        eprintln!(
+            "Warning: CGIP_SESSION_NAME is set to '{}' and takes precedence in this shell",
+            name
+        );
+    }
+}
+
+/// `name` when given, otherwise the current session. Either must exist.
+fn existing_or_current(name: &Option<String>, store: &SessionStore) -> Result<String> {
+    let name = match name.clone().or_else(|| store.current()) {
+        Some(name) => name,
+        None => return Err(CgipError::Usage("No active session".to_string())),
+    };
+    store.require(&name)?;
+    Ok(name)
+}
+
+fn run_action(action: &SessionAction, store: &SessionStore, printer: &mut dyn Printer) -> Result<()> {
+    match action {
+        SessionAction::List => {
+            let current = store.current();
+            for name in store.list()? {
+                let marker = if current.as_deref() == Some(name.as_str()) {
+                    "*"
+                } else {
+                    " "
+                };
+                let count = store.load_stashed(&name).map(|m| m.len()).unwrap_or(0);
+                println!("{} {} ({} messages)", marker, name, count);
+            }
+        }
+        SessionAction::New { name } => {
+            store.create(name, &[])?;
+            store.set_current(Some(name))?;
+            println!("Created and switched to session '{}'", name);
+            warn_if_env_overrides();
+        }
+        SessionAction::Switch { name } => {
+            store.require(name)?;
+            store.set_current(Some(name))?;
+            println!("Switched to session '{}'", name);
+            warn_if_env_overrides();
+        }
+        SessionAction::Rename { name, new_name } => {
+            store.rename(name, new_name)?;
+            println!("Renamed session '{}' to '{}'", name, new_name);
+        }
+        SessionAction::Delete { name } => {
+            store.delete(name)?;
+            println!("Deleted session '{}'", name);
+            prune_images(store);
+        }
+        SessionAction::Show { name } => {
+            let name = existing_or_current(name, store)?;
+            print_messages(&store.load(&name)?, printer);
+        }
+        SessionAction::Edit { name } => {
+            let name = existing_or_current(name, store)?;
+            edit::run(store, &name)?;
+            prune_images(store);
+        }
+        SessionAction::Pop { count } => {
+            let name = existing_or_current(&None, store)?;
+            let removed = store.update(&name, |messages| {
+                let removed = (*count).min(messages.len());
+                messages.truncate(messages.len() - removed);
+                Ok(removed)
+            })?;
+            println!("Removed {} messages from session '{}'", removed, name);
+            prune_images(store);
+        }
+        SessionAction::Fork { new_name, at } => {
+            let name = existing_or_current(&None, store)?;
+            let copied = store.fork(&name, new_name, *at)?;
+            store.set_current(Some(new_name))?;
+            println!(
+                "Forked session '{}' at message {} into '{}' and switched to it",
+                name, copied, new_name
+            );
+            warn_if_env_overrides();
+        }
+        SessionAction::Tree => {
+            let current = store.current();
+            let lineage = store.lineage()?;
+            let lines = lineage.render(&store.list()?, |name| {
+                let count = store.load_stashed(name).map(|m| m.len()).unwrap_or(0);
+                match lineage.get(name) {
+                    Some(fork) => format!("{} ({} messages, forked at {})", name, count, fork.at),
+                    None => format!("{} ({} messages)", name, count),
+                }
+            });
+            for (name, line) in lines {
+                let marker = if current.as_deref() == Some(name.as_str()) {
+                    "*"
+                } else {
+                    " "
+                };
+                println!("{} {}", marker, line);
+            }
+        }
+        SessionAction::Export { name, format, out } => {
+            let name = existing_or_current(name, store)?;
+            let format = match (format, out) {
+                (Some(format), _) => format.parse()?,
+                (None, Some(out)) => Format::from_path(Path::new(out)).unwrap_or(Format::Markdown),
+                (None, None) => Format::Markdown,
+            };
+            let document = export::export(&name, &store.load(&name)?, format)?;
+            match out {
+                Some(out) => {
+                    fs::write(out, document)
+                        .map_err(|e| CgipError::Io(format!("Could not write '{}': {}", out, e)))?;
+                    println!("Exported session '{}' to {}", name, out);
+                }
+                None => print!("{}", document),
+            }
+        }
+        SessionAction::Import { file, format, name } => {
+            let path = Path::new(file);
+            let format = match format {
+                Some(format) => format.parse()?,
+                None => Format::from_path(path).ok_or_else(|| {
+                    CgipError::Usage(format!(
+                        "Could not tell the format of '{}', pass --format",
+                        file
+                    ))
+                })?,
+            };
+            let text = fs::read_to_string(path)
+                .map_err(|e| CgipError::Io(format!("Could not read '{}': {}", file, e)))?;
+            let messages = export::import(&text, format)?;
+
+            let name = match name.clone().or_else(|| {
+                path.file_stem()
+                    .map(|stem| stem.to_string_lossy().to_string())
+            }) {
+                Some(name) => name,
+                None => return Err(CgipError::Usage("Pass a session name with --name".into())),
+            };
+            store.create(&name, &messages)?;
+            println!(
+                "Imported {} messages into session '{}'",
+                messages.len(),
+                name
+            );
+        }
+    }
+    Ok(())
+}
+
+pub fn run(subcmd: &SessionSubCommand, messages: &[Message], printer: &mut dyn Printer) -> Result<()> {
+    if let Some(action) = &subcmd.action {
+        return run_action(action, &SessionStore::open_default()?, printer);
+    }
+    if subcmd.view {
+        print_messages(messages, printer);
+        return Ok(());
+    }
+    if subcmd.clear {
+        delete_tty_context()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        chatgpt::{GptClient, Role},
+        printer::MockPrinter,
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_run_view() {
+        let mut client = GptClient::new(false).unwrap();
+        client.add_message(Role::System, "system message".to_string());
+        client.add_message(Role::User, "user message".to_string());
+        client.add_message(Role::Assistant, "assistant message".to_string());
+
+        let subcmd = SessionSubCommand {
+            view: true,
+            clear: false,
+            action: None,
+        };
+        let mut mp = MockPrinter::new();
+        run(&subcmd, &client.messages, &mut mp).unwrap();
+
+        assert_eq!(mp.messages.len(), 2);
+        assert_eq!(mp.messages[0].0, "user");
+        assert_eq!(mp.messages[0].1, "user message");
+        assert_eq!(mp.messages[1].0, "assistant");
+        assert_eq!(mp.messages[1].1, "assistant message");
+    }
+
+    #[test]
+    fn test_replace_reply_keeps_alternatives() {
+        let mut messages = vec![
+            Message::new(Role::User, "question".to_string()),
+            Message::new(Role::Assistant, "first".to_string()),
+        ];
+        assert!(replace_reply(
+            &mut messages,
+            1,
+            Message::new(Role::Assistant, "second".to_string()),
+            true
+        ));
+        assert!(replace_reply(
+            &mut messages,
+            1,
+            Message::new(Role::Assistant, "third".to_string()),
+            true
+        ));
+
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[1].content.to_string(), "third");
+        let alternatives: Vec<String> = messages[1]
+            .meta
+            .alternatives
+            .iter()
+            .map(|alt| alt.content.to_string())
+            .collect();
+        assert_eq!(alternatives, vec!["first", "second"]);
+        assert!(messages[1].meta.alternatives[1]
+            .meta
+            .alternatives
+            .is_empty());
+
+        assert!(replace_reply(
+            &mut messages,
+            1,
+            Message::new(Role::Assistant, "fourth".to_string()),
+            false
+        ));
+        assert!(messages[1].meta.alternatives.is_empty());
+    }
+
+    #[test]
+    fn test_replace_reply_refuses_changed_session() {
+        let mut messages = vec![
+            Message::new(Role::User, "question".to_string()),
+            Message::new(Role::Assistant, "answer".to_string()),
+            Message::new(Role::User, "newer question".to_string()),
+        ];
+        assert!(!replace_reply(
+            &mut messages,
+            1,
+            Message::new(Role::Assistant, "retry".to_string()),
+            false
+        ));
+        assert_eq!(messages.len(), 3);
+    }
+}
@@ -0,0 +1,512 @@
+use std::{
+    collections::HashSet,
+    env,
+    fs::{self, File, OpenOptions},
+    io::{ErrorKind, Write},
+    path::{Path, PathBuf},
+};
+
+use chrono::Utc;
+use tempfile::NamedTempFile;
+
+use super::images;
+use super::lineage::{Fork, Lineage};
+use crate::chatgpt::Message;
+use crate::error::{CgipError, Result};
+
+/// File in the session directory holding the name of the current session.
+const CURRENT_FILE: &str = ".current";
+/// File in the session directory recording which session each fork came from.
+const LINEAGE_FILE: &str = ".lineage.json";
+/// Lock held while images are stored or pruned, so pruning can't remove an
+/// image a session is about to refer to.
+const IMAGES_LOCK: &str = ".images.lock";
+
+/// An exclusive advisory lock, released when dropped.
+pub struct SessionLock {
+    _file: File,
+}
+
+/// Named sessions, each stored as a JSON array of messages in one directory.
+pub struct SessionStore {
+    dir: PathBuf,
+}
+
+impl SessionStore {
+    pub fn new(dir: PathBuf) -> Self {
+        SessionStore { dir }
+    }
+
+    /// The store under `<cache dir>/cgip`.
+    pub fn open_default() -> Result<Self> {
+        let dir = dirs::cache_dir()
+            .ok_or_else(|| CgipError::Io("Could not find the cache directory".into()))?
+            .join("cgip");
+        fs::create_dir_all(&dir)?;
+        Ok(SessionStore::new(dir))
+    }
+
+    /// The active session: `CGIP_SESSION_NAME` when it is set, otherwise the
+    /// session last selected with `cgip session switch` or `new`.
+    pub fn current(&self) -> Option<String> {
+        if let Ok(name) = env::var("CGIP_SESSION_NAME") {
+            return Some(name);
+        }
+        self.current_pointer()
+    }
+
+    /// The session selected with `switch` or `new`, ignoring the environment.
+    pub fn current_pointer(&self) -> Option<String> {
+        let name = fs::read_to_string(self.dir.join(CURRENT_FILE)).ok()?;
+        let name = name.trim();
+        (!name.is_empty()).then(|| name.to_string())
+    }
+
+    pub fn set_current(&self, name: Option<&str>) -> Result<()> {
+        let path = self.dir.join(CURRENT_FILE);
+        match name {
+            Some(name) => write_atomic(&path, name.as_bytes())?,
+            None => match fs::remove_file(path) {
+                Err(e) if e.kind() != ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            },
+        }
+        Ok(())
+    }
+
+    /// The file of session `name`. Names that could point outside the store
+    /// or at its bookkeeping files are rejected.
+    pub fn path(&self, name: &str) -> Result<PathBuf> {
+        validate_name(name)?;
+        Ok(self.dir.join(name))
+    }
+
+    pub fn exists(&self, name: &str) -> bool {
+        self.path(name).is_ok_and(|path| path.is_file())
+    }
+
+    /// Lock files are never removed. Unlinking one that another process
+    /// holds would let a third lock a new file at the same path.
+    fn lock_path(&self, name: &str) -> PathBuf {
+        self.dir.join(format!(".{}.lock", name))
+    }
+
+    /// Blocks until no other cgip process holds the lock on `name`. Hold it
+    /// across a load and save so concurrent appends don't drop messages.
+    pub fn lock(&self, name: &str) -> Result<SessionLock> {
+        validate_name(name)?;
+        self.lock_file(self.lock_path(name))
+    }
+
+    fn lock_file(&self, path: PathBuf) -> Result<SessionLock> {
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(path)?;
+        file.lock()?;
+        Ok(SessionLock { _file: file })
+    }
+
+    /// Loads `name`, lets `change` modify its messages and saves them, all
+    /// while holding the session lock.
+    pub fn update<T>(
+        &self,
+        name: &str,
+        change: impl FnOnce(&mut Vec<Message>) -> Result<T>,
+    ) -> Result<T> {
+        let _lock = self.lock(name)?;
+        let mut messages = self.load(name)?;
+        let result = change(&mut messages)?;
+        self.save(name, &messages)?;
+        Ok(result)
+    }
+
+    /// Locks two sessions in name order, so two processes locking the same
+    /// pair can't deadlock. Locking a name twice would block on itself.
+    fn lock_pair(&self, a: &str, b: &str) -> Result<Vec<SessionLock>> {
+        let (first, second) = if a <= b { (a, b) } else { (b, a) };
+        let mut locks = vec![self.lock(first)?];
+        if first != second {
+            locks.push(self.lock(second)?);
+        }
+        Ok(locks)
+    }
+
+    /// The messages of `name`, empty when it doesn't exist. A file that
+    /// isn't valid JSON, say from a crash in an older version, is moved aside
+    /// with a warning and the session starts over.
+    pub fn load(&self, name: &str) -> Result<Vec<Message>> {
+        let path = self.path(name)?;
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => {
+                return Err(CgipError::Io(format!(
+                    "Could not read session '{}': {}",
+                    name, e
+                )))
+            }
+        };
+
+        match serde_json::from_str::<Vec<Message>>(&text) {
+            Ok(mut messages) => {
+                images::restore(&mut messages, &self.dir);
+                Ok(messages)
+            }
+            Err(e) => {
+                let quarantine = self.dir.join(format!(
+                    ".{}.corrupt-{}",
+                    name,
+                    Utc::now().format("%Y%m%dT%H%M%SZ")
+                ));
+                match fs::rename(&path, &quarantine) {
+                    // Another process may have moved it already
+                    Err(err) if err.kind() != ErrorKind::NotFound => return Err(err.into()),
+                    _ => {}
+                }
+                eprintln!(
+                    "Warning: session '{}' was corrupt ({}) and has been moved to {}",
+                    name,
+                    e,
+                    quarantine.display()
+                );
+                Ok(Vec::new())
+            }
+        }
+    }
+
+    /// Reads `name` as stored, with images left as references. Unlike
+    /// `load` this changes nothing, a corrupt file is an error.
+    pub fn load_stashed(&self, name: &str) -> Result<Vec<Message>> {
+        let text = match fs::read_to_string(self.path(name)?) {
+            Ok(text) => text,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        serde_json::from_str(&text)
+            .map_err(|e| CgipError::Io(format!("Could not read session '{}': {}", name, e)))
+    }
+
+    /// Replaces the session file in one step, so readers see either the old
+    /// or the new messages and never a partial write. Inline images are kept
+    /// apart by their hash, see `images::stash`.
+    pub fn save(&self, name: &str, messages: &[Message]) -> Result<()> {
+        let mut messages = messages.to_vec();
+        let _lock = self.lock_file(self.dir.join(IMAGES_LOCK))?;
+        self.stash_images(&mut messages)?;
+        let json = serde_json::to_vec(&messages)
+            .map_err(|e| CgipError::Io(format!("Could not write session '{}': {}", name, e)))?;
+        write_atomic(&self.path(name)?, &json)
+            .map_err(|e| CgipError::Io(format!("Could not write session '{}': {}", name, e)))
+    }
+
+    /// Moves the inline images of `messages` into the store and leaves
+    /// references to them in their place.
+    pub fn stash_images(&self, messages: &mut [Message]) -> Result<()> {
+        images::stash(messages, &self.dir)
+            .map_err(|e| CgipError::Io(format!("Could not store images: {}", e)))
+    }
+
+    /// Removes the stored images no session refers to any more. Nothing is
+    /// removed when a session can't be read, as its images may still be in
+    /// use.
+    pub fn prune_images(&self) -> Result<usize> {
+        let _lock = self.lock_file(self.dir.join(IMAGES_LOCK))?;
+        let mut keep = HashSet::new();
+        for name in self.list()? {
+            keep.extend(images::references(&mut self.load_stashed(&name)?));
+        }
+        images::prune(&self.dir, &keep)
+            .map_err(|e| CgipError::Io(format!("Could not remove images: {}", e)))
+    }
+
+    /// Names of all stored sessions, sorted.
+    pub fn list(&self) -> Result<Vec<String>> {
+        let mut names = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().to_string();
+            if entry.file_type()?.is_file() && !name.starts_with('.') {
+                names.push(name);
+            }
+        }
+        names.sort();
+        Ok(names)
+    }
+
+    /// Creates `name` holding `messages`, failing if it already exists.
+    pub fn create(&self, name: &str, messages: &[Message]) -> Result<()> {
+        validate_name(name)?;
+        let _lock = self.lock(name)?;
+        if self.exists(name) {
+            return Err(CgipError::Usage(format!(
+                "Session '{}' already exists",
+                name
+            )));
+        }
+        self.save(name, messages)
+    }
+
+    pub fn rename(&self, name: &str, new_name: &str) -> Result<()> {
+        validate_name(new_name)?;
+        let _locks = self.lock_pair(name, new_name)?;
+        self.require(name)?;
+        if self.exists(new_name) {
+            return Err(CgipError::Usage(format!(
+                "Session '{}' already exists",
+                new_name
+            )));
+        }
+        fs::rename(self.path(name)?, self.path(new_name)?)?;
+        if self.current_pointer().as_deref() == Some(name) {
+            self.set_current(Some(new_name))?;
+        }
+        self.update_lineage(|lineage| lineage.rename(name, new_name))
+    }
+
+    pub fn delete(&self, name: &str) -> Result<()> {
+        let _lock = self.lock(name)?;
+        self.require(name)?;
+        fs::remove_file(self.path(name)?)?;
+        if self.current_pointer().as_deref() == Some(name) {
+            self.set_current(None)?;
+        }
+        self.update_lineage(|lineage| lineage.remove(name))
+    }
+
+    /// Creates `new_name` from the first `at` messages of `name`, or all of
+    /// them, and records where it came from. Returns the number of messages
+    /// copied.
+    pub fn fork(&self, name: &str, new_name: &str, at: Option<usize>) -> Result<usize> {
+        validate_name(new_name)?;
+        let _locks = self.lock_pair(name, new_name)?;
+        self.require(name)?;
+        if self.exists(new_name) {
+            return Err(CgipError::Usage(format!(
+                "Session '{}' already exists",
+                new_name
+            )));
+        }
+
+        let mut messages = self.load(name)?;
+        let at = at.unwrap_or(messages.len());
+        if at > messages.len() {
+            return Err(CgipError::Usage(format!(
+                "Session '{}' only has {} messages",
+                name,
+                messages.len()
+            )));
+        }
+        messages.truncate(at);
+        self.save(new_name, &messages)?;
+
+        self.update_lineage(|lineage| {
+            lineage.insert(
+                new_name,
+                Fork {
+                    parent: name.to_string(),
+                    at,
+                },
+            )
+        })?;
+        Ok(at)
+    }
+
+    pub fn lineage(&self) -> Result<Lineage> {
+        match fs::read_to_string(self.dir.join(LINEAGE_FILE)) {
+            Ok(text) => serde_json::from_str(&text)
+                .map_err(|e| CgipError::Io(format!("Could not read session lineage: {}", e))),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(Lineage::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn update_lineage(&self, update: impl FnOnce(&mut Lineage)) -> Result<()> {
+        let _lock = self.lock_file(self.lock_path(LINEAGE_FILE))?;
+        let mut lineage = self.lineage()?;
+        update(&mut lineage);
+        let json = serde_json::to_string_pretty(&lineage)
+            .map_err(|e| CgipError::Io(format!("Could not write session lineage: {}", e)))?;
+        write_atomic(&self.dir.join(LINEAGE_FILE), json.as_bytes())?;
+        Ok(())
+    }
+
+    /// Fails with a usage error when the name is invalid or the session
+    /// doesn't exist.
+    pub fn require(&self, name: &str) -> Result<()> {
+        if self.path(name)?.is_file() {
+            Ok(())
+        } else {
+            Err(CgipError::Usage(format!("No session named '{}'", name)))
+        }
+    }
+}
+
+/// Writes `contents` to a temporary file next to `path` and renames it over
+/// `path` once it is safely on disk.
+pub fn write_atomic(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    let dir = path.parent().unwrap_or_else(|| Path::new("."));
+    let mut file = NamedTempFile::new_in(dir)?;
+    file.write_all(contents)?;
+    file.as_file().sync_all()?;
+    file.persist(path).map_err(|e| e.error)?;
+    Ok(())
+}
+
+/// Session names become file names, so they can't contain path separators
+/// or start with a dot.
+fn validate_name(name: &str) -> Result<()> {
+    if name.trim().is_empty()
+        || name.starts_with('.')
+        || name.contains(|c: char| c == '/' || c == '\\' || c.is_control())
+    {
+        return Err(CgipError::Usage(format!("Invalid session name '{}'", name)));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chatgpt::Role;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_create_rename_delete() {
+        let temp_dir = TempDir::new().unwrap();
+        let store = SessionStore::new(temp_dir.path().to_path_buf());
+
+        store.create("bug-123", &[]).unwrap();
+        store.set_current(Some("bug-123")).unwrap();
+        assert!(store.create("bug-123", &[]).is_err());
+        assert_eq!(store.list().unwrap(), vec!["bug-123"]);
+
+        store.rename("bug-123", "bug-124").unwrap();
+        assert_eq!(store.current_pointer().as_deref(), Some("bug-124"));
+        assert_eq!(store.list().unwrap(), vec!["bug-124"]);
+        // Lock files stay, another process may be waiting on them
+        assert!(temp_dir.path().join(".bug-123.lock").exists());
+
+        store.delete("bug-124").unwrap();
+        assert_eq!(store.current_pointer(), None);
+        assert!(temp_dir.path().join(".bug-124.lock").exists());
+        assert!(store.list().unwrap().is_empty());
+        assert!(store.delete("bug-124").is_err());
+    }
+
+    #[test]
+    fn test_fork() {
+        let temp_dir = TempDir::new().unwrap();
+        let store = SessionStore::new(temp_dir.path().to_path_buf());
+
+        let messages: Vec<Message> = (0..4)
+            .map(|i| Message::new(Role::User, format!("message {}", i)))
+            .collect();
+        store.save("main", &messages).unwrap();
+
+        assert_eq!(store.fork("main", "idea", Some(2)).unwrap(), 2);
+        assert_eq!(store.load("idea").unwrap().len(), 2);
+        assert_eq!(store.fork("main", "copy", None).unwrap(), 4);
+        assert!(store.fork("main", "too-far", Some(5)).is_err());
+        assert!(store.fork("main", "idea", None).is_err());
+        assert!(store.fork("main", "main", None).is_err());
+
+        let lineage = store.lineage().unwrap();
+        assert_eq!(
+            lineage.get("idea").map(|fork| fork.parent.as_str()),
+            Some("main")
+        );
+        assert_eq!(lineage.get("idea").map(|fork| fork.at), Some(2));
+
+        store.rename("main", "trunk").unwrap();
+        assert_eq!(
+            store
+                .lineage()
+                .unwrap()
+                .get("idea")
+                .map(|fork| fork.parent.as_str()),
+            Some("trunk")
+        );
+        // The lineage file is hidden from the session list
+        assert_eq!(store.list().unwrap(), vec!["copy", "idea", "trunk"]);
+    }
+
+    #[test]
+    fn test_quarantines_corrupt_session() {
+        let temp_dir = TempDir::new().unwrap();
+        let store = SessionStore::new(temp_dir.path().to_path_buf());
+
+        fs::write(
+            store.path("broken").unwrap(),
+            "[{\"role\": \"user\", \"cont",
+        )
+        .unwrap();
+        // Reading for a listing leaves the file alone
+        assert!(store.load_stashed("broken").is_err());
+        assert!(store.exists("broken"));
+
+        assert!(store.load("broken").unwrap().is_empty());
+        assert!(!store.exists("broken"));
+
+        let quarantined: Vec<String> = fs::read_dir(temp_dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .filter(|name| name.starts_with(".broken.corrupt-"))
+            .collect();
+        assert_eq!(quarantined.len(), 1);
+        assert!(store.list().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_concurrent_appends_keep_every_message() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir = temp_dir.path().to_path_buf();
+
+        let threads: Vec<_> = (0..8)
+            .map(|i| {
+                let store = SessionStore::new(dir.clone());
+                std::thread::spawn(move || {
+                    for j in 0..10 {
+                        let _lock = store.lock("shared").unwrap();
+                        let mut messages = store.load("shared").unwrap();
+                        messages.push(Message::new(Role::User, format!("{} {}", i, j)));
+                        store.save("shared", &messages).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        let store = SessionStore::new(dir);
+        assert_eq!(store.load("shared").unwrap().len(), 80);
+        assert_eq!(store.list().unwrap(), vec!["shared"]);
+    }
+
+    #[test]
+    fn test_rejects_invalid_names() {
+        let temp_dir = TempDir::new().unwrap();
+        let store = SessionStore::new(temp_dir.path().to_path_buf());
+
+        assert!(store.create("../escape", &[]).is_err());
+        assert!(store.create(".current", &[]).is_err());
+        assert!(store.create("", &[]).is_err());
+
+        // Lookups can't reach files outside the store or its bookkeeping
+        let outside = temp_dir.path().join("outside");
+        fs::create_dir(&outside).unwrap();
+        let store = SessionStore::new(outside.join("sessions"));
+        fs::create_dir(outside.join("sessions")).unwrap();
+        fs::write(outside.join("victim"), "keep").unwrap();
+        store.set_current(Some("main")).unwrap();
+        assert!(store.delete("../victim").is_err());
+        assert!(outside.join("victim").exists());
+        assert!(store.delete(".current").is_err());
+        assert_eq!(store.current_pointer().as_deref(), Some("main"));
+        assert!(store.load(".lineage.json").is_err());
+        assert!(store.require("../victim").is_err());
+        assert!(!store.exists("../victim"));
+    }
+}
@@ -0,0 +1,226 @@
+use crate::chatgpt::{Message, MessageContent, Role};
+use crate::config_manager::AppConfig;
+use crate::tokens::TokenCounter;
+
+/// Marks the system message that holds the summary of evicted turns.
+const SUMMARY_HEADER: &str = "Earlier conversation (summarized):";
+/// Characters kept from each evicted message in the summary.
+const SUMMARY_LINE_CHARS: usize = 120;
+/// The summary keeps only the most recent lines so it can't grow forever.
+const SUMMARY_MAX_LINES: usize = 40;
+
+/// Decides which messages a session keeps. Whole turns, a user message and
+/// the replies that follow it, are evicted oldest first until the session
+/// fits both the message and the token limit.
+pub struct WindowPolicy {
+    pub max_messages: usize,
+    pub max_tokens: Option<usize>,
+    pub summarize: bool,
+}
+
+impl WindowPolicy {
+    pub fn from_config(config: &AppConfig) -> Self {
+        WindowPolicy {
+            max_messages: config.stored_context_length,
+            max_tokens: (config.stored_context_tokens > 0).then_some(config.stored_context_tokens),
+            summarize: config.summarize_evicted,
+        }
+    }
+
+    pub fn apply(&self, messages: Vec<Message>, model: &str) -> Vec<Message> {
+        let (mut summary, messages) = split_summary(messages);
+        let mut turns = group_turns(messages);
+        let counter = self.max_tokens.map(|_| TokenCounter::new(model));
+
+        let fits = |summary: &[String], turns: &[Vec<Message>]| {
+            let messages: Vec<&Message> = turns.iter().flatten().collect();
+            if messages.len() > self.max_messages {
+                return false;
+            }
+            match (&counter, self.max_tokens) {
+                (Some(counter), Some(max_tokens)) => {
+                    let summary_tokens =
+                        summary_message(summary).map_or(0, |msg| counter.count_message(&msg));
+                    let tokens: usize = messages.iter().map(|m| counter.count_message(m)).sum();
+                    summary_tokens + tokens <= max_tokens
+                }
+                _ => true,
+            }
+        };
+
+        // The latest turn is always kept, even when it is over the limits on
+        // its own, so a question never loses its answer.
+        while turns.len() > 1 && !fits(&summary, &turns) {
+            let evicted = turns.remove(0);
+            if self.summarize {
+                summary.extend(evicted.iter().filter_map(summary_line));
+                let excess = summary.len().saturating_sub(SUMMARY_MAX_LINES);
+                summary.drain(..excess);
+            }
+        }
+
+        let mut window = Vec::new();
+        if self.summarize {
+            window.extend(summary_message(&summary));
+        }
+        window.extend(turns.into_iter().flatten());
+        window
+    }
+}
+
+/// Separates an existing summary message at the start of the session into
+/// its lines.
+fn split_summary(mut messages: Vec<Message>) -> (Vec<String>, Vec<Message>) {
+    let is_summary = messages
+        .first()
+        .map(|m| m.role == Role::System && m.content.as_str().starts_with(SUMMARY_HEADER))
+        .unwrap_or(false);
+    if !is_summary {
+        return (Vec::new(), messages);
+    }
+
+    let summary = messages.remove(0).content.to_string();
+    let lines = summary
+        .lines()
+        .skip(1)
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| line.to_string())
+        .collect();
+    (lines, messages)
+}
+
+/// Groups messages into turns. A turn starts with the first of one or more
+/// consecutive user messages and runs until the next one.
+pub fn group_turns(messages: Vec<Message>) -> Vec<Vec<Message>> {
+    let mut turns: Vec<Vec<Message>> = Vec::new();
+    for msg in messages {
+        let follows_user = turns
+            .last()
+            .and_then(|turn| turn.last())
+            .is_some_and(|prev| prev.role == Role::User);
+        let starts_turn = msg.role == Role::User && !follows_user;
+        match turns.last_mut() {
+            Some(turn) if !starts_turn => turn.push(msg),
+            _ => turns.push(vec![msg]),
+        }
+    }
+    turns
+}
+
+fn summary_line(msg: &Message) -> Option<String> {
+    let text = match &msg.content {
+        MessageContent::Text(text) => text.clone(),
+        content => content.to_string(),
+    };
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    if text.is_empty() {
+        return None;
+    }
+
+    let mut line: String = text.chars().take(SUMMARY_LINE_CHARS).collect();
+    if line.len() < text.len() {
+        line.push_str("...");
+    }
+    Some(format!("- {}: {}", msg.role, line))
+}
+
+fn summary_message(lines: &[String]) -> Option<Message> {
+    if lines.is_empty() {
+        return None;
+    }
+    Some(Message::new(
+        Role::System,
+        format!("{}\n{}", SUMMARY_HEADER, lines.join("\n")),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn conversation(turns: usize) -> Vec<Message> {
+        (0..turns)
+            .flat_map(|i| {
+                vec![
+                    Message::new(Role::User, format!("question {}", i)),
+                    Message::new(Role::Assistant, format!("answer {}", i)),
+                ]
+            })
+            .collect()
+    }
+
+    fn policy(max_messages: usize) -> WindowPolicy {
+        WindowPolicy {
+            max_messages,
+            max_tokens: None,
+            summarize: false,
+        }
+    }
+
+    #[test]
+    fn test_trims_several_messages_at_once() {
+        let window = policy(4).apply(conversation(5), "gpt-4o");
+        assert_eq!(window.len(), 4);
+        assert_eq!(window[0].content.to_string(), "question 3");
+    }
+
+    #[test]
+    fn test_keeps_turn_pairs_together() {
+        // An odd limit must not leave an answer without its question
+        let window = policy(5).apply(conversation(5), "gpt-4o");
+        assert_eq!(window.len(), 4);
+        assert_eq!(window[0].role, Role::User);
+    }
+
+    #[test]
+    fn test_consecutive_user_messages_form_one_turn() {
+        let mut messages = vec![
+            Message::new(Role::User, "stdin".to_string()),
+            Message::new(Role::User, "query".to_string()),
+        ];
+        messages.push(Message::new(Role::Assistant, "reply".to_string()));
+        messages.extend(conversation(1));
+
+        let window = policy(4).apply(messages, "gpt-4o");
+        assert_eq!(window.len(), 2);
+        assert_eq!(window[0].content.to_string(), "question 0");
+    }
+
+    #[test]
+    fn test_trims_by_tokens() {
+        let mut messages = conversation(1);
+        messages.insert(1, Message::new(Role::User, "long ".repeat(500)));
+        messages.extend(conversation(2));
+
+        let window = WindowPolicy {
+            max_messages: 100,
+            max_tokens: Some(100),
+            summarize: false,
+        }
+        .apply(messages, "gpt-4o");
+        assert_eq!(window.len(), 4);
+    }
+
+    #[test]
+    fn test_summarizes_evicted_turns() {
+        let policy = WindowPolicy {
+            max_messages: 2,
+            max_tokens: None,
+            summarize: true,
+        };
+        let window = policy.apply(conversation(2), "gpt-4o");
+        assert_eq!(window.len(), 3);
+        assert_eq!(window[0].role, Role::System);
+        assert_eq!(
+            window[0].content.to_string(),
+            "Earlier conversation (summarized):\n- user: question 0\n- assistant: answer 0"
+        );
+
+        // The next eviction is merged into the same summary message
+        let mut messages = window;
+        messages.extend(conversation(1));
+        let window = policy.apply(messages, "gpt-4o");
+        assert_eq!(window.len(), 3);
+        assert_eq!(window[0].content.to_string().lines().count(), 5);
+    }
+}
@@ -0,0 +1,33 @@
+use crate::{chatgpt::Message, tokens::TokenCounter};
+
+/// Prints the token count of every message in the context and the total,
+/// together with the budget it is trimmed to.
+pub fn run(messages: &[Message], model: &str, budget: Option<usize>) {
+    let counter = TokenCounter::new(model);
+
+    println!("{:>3}  {:<10} {:>7}  content", "#", "role", "tokens");
+    for (i, msg) in messages.iter().enumerate() {
+        let preview: String = msg
+            .content
+            .to_string()
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ")
+            .chars()
+            .take(50)
+            .collect();
+        println!(
+            "{:>3}  {:<10} {:>7}  {}",
+            i + 1,
+            msg.role,
+            counter.count_message(msg),
+            preview
+        );
+    }
+
+    let total = counter.count_messages(messages);
+    match budget {
+        Some(budget) => println!("total: {} of {} tokens ({})", total, budget, model),
+        None => println!("total: {} tokens ({})", total, model),
+    }
+}
@@ -4,15 +4,19 @@ use std::fs::File;
 use std::io::Write;
 
 use crate::args::TtsSubCommand;
+use crate::config_manager::AppConfig;
+use crate::error::{CgipError, Result};
+use crate::retry::RetryPolicy;
 use crate::utils::get_stdin;
 
-pub fn run(args: &TtsSubCommand) -> Result<(), Box<dyn std::error::Error>> {
+pub fn run(args: &TtsSubCommand, config: &AppConfig) -> Result<()> {
     // Get text input from args or stdin
     let stdin_text = get_stdin();
     let text = match (stdin_text.is_empty(), &args.text) {
         (true, None) => {
-            eprintln!("Error: No text provided. Please provide text as an argument or via stdin.");
-            std::process::exit(1);
+            return Err(CgipError::Usage(
+                "No text provided. Please provide text as an argument or via stdin.".to_string(),
+            ));
         }
         (true, Some(arg_text)) => arg_text.clone(),
         (false, None) => stdin_text,
@@ -21,20 +25,22 @@ pub fn run(args: &TtsSubCommand) -> Result<(), Box<dyn std::error::Error>> {
 
     // Validate speed parameter
     if args.speed < 0.25 || args.speed > 4.0 {
-        eprintln!("Error: Speed must be between 0.25 and 4.0");
-        std::process::exit(1);
+        return Err(CgipError::Usage(
+            "Speed must be between 0.25 and 4.0".to_string(),
+        ));
     }
 
     // Validate format
     let valid_formats = ["mp3", "opus", "aac", "flac"];
     if !valid_formats.contains(&args.format.as_str()) {
-        eprintln!("Error: Format must be one of: mp3, opus, aac, flac");
-        std::process::exit(1);
+        return Err(CgipError::Usage(
+            "Format must be one of: mp3, opus, aac, flac".to_string(),
+        ));
     }
 
     // Get API key
     let api_key = std::env::var("OPENAI_API_KEY")
-        .map_err(|_| "Missing OPENAI_API_KEY environment variable")?;
+        .map_err(|_| CgipError::Config("Missing OPENAI_API_KEY environment variable".into()))?;
 
     // Build request payload
     let mut payload = json!({
@@ -68,17 +74,18 @@ pub fn run(args: &TtsSubCommand) -> Result<(), Box<dyn std::error::Error>> {
     println!("Generating speech...");
 
     // Make API request
-    let response = client
-        .post(&url)
-        .header("Authorization", format!("Bearer {}", api_key))
-        .header("Content-Type", "application/json")
-        .json(&payload)
-        .send()?;
+    let response = RetryPolicy::from_config(config).send(|| {
+        client
+            .post(&url)
+            .header("Authorization", format!("Bearer {}", api_key))
+            .header("Content-Type", "application/json")
+            .json(&payload)
+    })?;
 
     if !response.status().is_success() {
+        let status = response.status().as_u16();
         let error_text = response.text()?;
-        eprintln!("API Error: {}", error_text);
-        std::process::exit(1);
+        return Err(CgipError::from_status(status, error_text));
     }
 
     // Get audio data
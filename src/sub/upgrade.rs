@@ -1,14 +1,17 @@
 use self_update::cargo_crate_version;
 
-pub fn run() -> Result<(), Box<dyn std::error::Error>> {
+use crate::error::{CgipError, Result};
+
+pub fn run() -> Result<()> {
     let status = self_update::backends::github::Update::configure()
         .repo_owner("divanvisagie")
         .repo_name("chat-gipitty")
         .bin_name("cgip")
         .show_download_progress(true)
         .current_version(cargo_crate_version!())
-        .build()?
-        .update()?;
+        .build()
+        .and_then(|update| update.update())
+        .map_err(|e| CgipError::Network(format!("Upgrade failed: {}", e)))?;
     println!("Updated to version: {}", status.version());
     Ok(())
 }
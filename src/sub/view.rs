@@ -1,11 +1,5 @@
-use crate::{chatgpt::Message, utils::markdown_from_messages};
+use crate::{chatgpt::Message, printer::Printer, sub::session::print_messages};
 
-pub fn run(messages: &Vec<Message>) {
-    let visible_messages = messages
-        .iter()
-        .cloned()
-        .filter(|msg| msg.role != "system")
-        .collect();
-    let md = markdown_from_messages(visible_messages);
-    println!("{}", md);
+pub fn run(messages: &[Message], printer: &mut dyn Printer) {
+    print_messages(messages, printer);
 }
@@ -0,0 +1,218 @@
+use tiktoken_rs::{get_bpe_from_model, model::get_context_size, tokenizer::get_tokenizer, CoreBPE};
+
+use crate::chatgpt::message::{ContentPart, Message, MessageContent};
+use crate::sub::session::group_turns;
+
+/// Every message is wrapped in `<|start|>{role}\n{content}<|end|>\n`.
+const TOKENS_PER_MESSAGE: usize = 3;
+/// Every reply is primed with `<|start|>assistant<|message|>`.
+const TOKENS_PER_REPLY: usize = 3;
+/// Flat estimate for an image, the cost of a low detail image on OpenAI.
+const TOKENS_PER_IMAGE: usize = 85;
+/// Tokens left free for the reply when the budget is the context window. It
+/// is at most a quarter of the window, for models with small windows.
+const REPLY_RESERVE: usize = 4096;
+
+/// Counts tokens the way the OpenAI chat API does. Models tiktoken doesn't
+/// know, such as Claude or Llama, are estimated with `cl100k_base`.
+pub struct TokenCounter {
+    bpe: CoreBPE,
+}
+
+impl TokenCounter {
+    pub fn new(model: &str) -> Self {
+        let bpe = get_bpe_from_model(model)
+            .or_else(|_| tiktoken_rs::cl100k_base())
+            .expect("Failed to load the cl100k_base tokenizer");
+        TokenCounter { bpe }
+    }
+
+    pub fn count_text(&self, text: &str) -> usize {
+        self.bpe.encode_with_special_tokens(text).len()
+    }
+
+    pub fn count_message(&self, message: &Message) -> usize {
+        let content = match &message.content {
+            MessageContent::Text(text) => self.count_text(text),
+            MessageContent::Multi(parts) => parts
+                .iter()
+                .map(|part| match part {
+                    ContentPart::Text { text } => self.count_text(text),
+                    ContentPart::ImageUrl { .. } => TOKENS_PER_IMAGE,
+                })
+                .sum(),
+        };
+        let name = message
+            .name
+            .as_deref()
+            .map_or(0, |n| self.count_text(n) + 1);
+        let tool_calls = message.tool_calls.as_ref().map_or(0, |calls| {
+            self.count_text(&serde_json::to_string(calls).unwrap_or_default())
+        });
+
+        TOKENS_PER_MESSAGE + self.count_text(message.role.as_str()) + content + name + tool_calls
+    }
+
+    pub fn count_messages(&self, messages: &[Message]) -> usize {
+        messages
+            .iter()
+            .map(|m| self.count_message(m))
+            .sum::<usize>()
+            + TOKENS_PER_REPLY
+    }
+}
+
+/// The input token budget for a request. A `max_input_tokens` of 0 means the
+/// model's context window less room for the reply, which is only known for
+/// OpenAI models; for other models `None` is returned and nothing is trimmed.
+pub fn input_budget(model: &str, max_input_tokens: usize) -> Option<usize> {
+    if max_input_tokens > 0 {
+        Some(max_input_tokens)
+    } else if get_tokenizer(model).is_some() {
+        let context = get_context_size(model);
+        Some(context - REPLY_RESERVE.min(context / 4))
+    } else {
+        None
+    }
+}
+
+/// Drops the oldest turns in `messages[range]` until the whole context fits
+/// `budget`, returning how many messages were removed. Whole turns go at
+/// once, so a tool result never loses the call it answers. Messages outside
+/// the range, like the system prompt and the new query, are never removed.
+pub fn trim_to_budget(
+    messages: &mut Vec<Message>,
+    range: std::ops::Range<usize>,
+    budget: usize,
+    counter: &TokenCounter,
+) -> usize {
+    let mut total = counter.count_messages(messages);
+    if total <= budget {
+        return 0;
+    }
+    let session: Vec<Message> = messages.drain(range.clone()).collect();
+    let mut turns = group_turns(session).into_iter();
+    let mut removed = 0;
+    let mut kept = Vec::new();
+    for turn in turns.by_ref() {
+        if total <= budget {
+            kept = turn;
+            break;
+        }
+        total -= turn.iter().map(|m| counter.count_message(m)).sum::<usize>();
+        removed += turn.len();
+    }
+    kept.extend(turns.flatten());
+    messages.splice(range.start..range.start, kept);
+    removed
+}
+
+/// Trims the session messages in `messages[range]` to the input budget of
+/// `model`, telling the user when anything was dropped. Returns the budget.
+pub fn fit_to_budget(
+    messages: &mut Vec<Message>,
+    range: std::ops::Range<usize>,
+    model: &str,
+    max_input_tokens: usize,
+) -> Option<usize> {
+    let budget = input_budget(model, max_input_tokens)?;
+    let counter = TokenCounter::new(model);
+    let removed = trim_to_budget(messages, range, budget, &counter);
+    if removed > 0 {
+        eprintln!(
+            "cgip: dropped {} older session messages to fit the {} token budget",
+            removed, budget
+        );
+    }
+    Some(budget)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::chatgpt::message::ToolCall;
+    use crate::chatgpt::Role;
+
+    #[test]
+    fn test_count_messages() {
+        let counter = TokenCounter::new("gpt-4");
+        // Matches the example in the OpenAI cookbook: 3 + "user" (1) + "hello
+        // world" (2) for the message, plus 3 for the reply.
+        assert_eq!(
+            counter.count_messages(&[Message::new(Role::User, "hello world".to_string())]),
+            9
+        );
+    }
+
+    #[test]
+    fn test_unknown_model_falls_back() {
+        let counter = TokenCounter::new("claude-sonnet-4");
+        assert_eq!(counter.count_text("hello world"), 2);
+        assert_eq!(input_budget("claude-sonnet-4", 0), None);
+        assert_eq!(input_budget("claude-sonnet-4", 1000), Some(1000));
+        assert_eq!(input_budget("gpt-4o", 0), Some(128_000 - REPLY_RESERVE));
+        assert_eq!(input_budget("gpt-4", 0), Some(8192 - 2048));
+    }
+
+    #[test]
+    fn test_trim_to_budget_keeps_system_and_query() {
+        let counter = TokenCounter::new("gpt-4o");
+        let mut messages = vec![
+            Message::new(Role::System, "be brief".to_string()),
+            Message::new(
+                Role::User,
+                "first question about something long".to_string(),
+            ),
+            Message::new(
+                Role::Assistant,
+                "first answer about something long".to_string(),
+            ),
+            Message::new(Role::User, "latest question".to_string()),
+        ];
+        let budget = counter.count_messages(&[messages[0].clone(), messages[3].clone()]) + 1;
+
+        let removed = trim_to_budget(&mut messages, 1..3, budget, &counter);
+        assert_eq!(removed, 2);
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].role, Role::System);
+        assert_eq!(messages[1].content.to_string(), "latest question");
+    }
+
+    #[test]
+    fn test_trim_to_budget_keeps_tool_calls_with_results() {
+        let counter = TokenCounter::new("gpt-4o");
+        let mut call = Message::new(Role::Assistant, String::new());
+        call.tool_calls = Some(vec![ToolCall::new(
+            "call_1",
+            "execute",
+            &json!({"command": "ls"}),
+        )]);
+        let mut result = Message::new(Role::Tool, "a.txt b.txt".to_string());
+        result.tool_call_id = Some("call_1".to_string());
+        let mut messages = vec![
+            Message::new(Role::System, "be brief".to_string()),
+            Message::new(Role::User, "what files are here?".to_string()),
+            call,
+            result,
+            Message::new(Role::Assistant, "two text files".to_string()),
+            Message::new(Role::User, "and now?".to_string()),
+            Message::new(Role::Assistant, "still two".to_string()),
+            Message::new(Role::User, "latest question".to_string()),
+        ];
+        // Only room for dropping the first message, which would leave the
+        // call and its result without their question
+        let first = counter.count_message(&messages[1]);
+        let budget = counter.count_messages(&messages) - first;
+
+        let removed = trim_to_budget(&mut messages, 1..7, budget, &counter);
+        assert_eq!(removed, 4);
+        let roles: Vec<Role> = messages.iter().map(|m| m.role).collect();
+        assert_eq!(
+            roles,
+            vec![Role::System, Role::User, Role::Assistant, Role::User]
+        );
+        assert!(messages.iter().all(|m| m.role != Role::Tool));
+    }
+}
@@ -1,15 +1,19 @@
+use std::borrow::Cow;
 use std::fs;
-use std::io::{self, BufRead};
+use std::io::{self, Read};
 
 use atty::Stream;
+use pulldown_cmark::{html, Event, Options, Parser, Tag};
 use serde_yaml::Error;
 
 use crate::chatgpt::Message;
+use crate::error::CgipError;
 
-pub fn new_ensure_config_directory(config_directory: &std::path::PathBuf) {
+pub fn new_ensure_config_directory(config_directory: &std::path::PathBuf) -> std::io::Result<()> {
     if !config_directory.exists() {
-        std::fs::create_dir_all(config_directory).expect("Failed to create config directory");
+        std::fs::create_dir_all(config_directory)?;
     }
+    Ok(())
 }
 
 pub fn markdown_from_messages(messages: Vec<Message>) -> String {
@@ -20,28 +24,108 @@ pub fn markdown_from_messages(messages: Vec<Message>) -> String {
     md
 }
 
-pub fn get_stdin() -> String {
-    let mut lines: Vec<String> = Vec::new();
+pub fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// URL schemes links and images may use. URLs without a scheme, such as
+/// `#notes` or `docs/a.md`, are allowed too.
+const SAFE_SCHEMES: [&str; 3] = ["http", "https", "mailto"];
+
+/// Whether `url` is relative or uses one of the `SAFE_SCHEMES`. Browsers
+/// ignore whitespace and control characters in a scheme, so they are left
+/// out before it is checked.
+fn is_safe_url(url: &str) -> bool {
+    let url: String = url
+        .chars()
+        .filter(|c| !c.is_whitespace() && !c.is_control())
+        .collect();
+    match url.find([':', '/', '?', '#']) {
+        Some(i) if url[i..].starts_with(':') => {
+            SAFE_SCHEMES.contains(&url[..i].to_lowercase().as_str())
+        }
+        _ => true,
+    }
+}
+
+/// Renders Markdown, showing any raw HTML in it as text and pointing links
+/// and images with other than `SAFE_SCHEMES` nowhere, so the output can't
+/// carry markup or scripts from a reply.
+pub fn markdown_to_html(text: &str) -> String {
+    let options = Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH;
+    let events = Parser::new_ext(text, options).map(|event| match event {
+        Event::Html(html) | Event::InlineHtml(html) => Event::Text(html),
+        Event::Start(Tag::Link {
+            link_type,
+            dest_url,
+            title,
+            id,
+        }) if !is_safe_url(&dest_url) => Event::Start(Tag::Link {
+            link_type,
+            dest_url: "#".into(),
+            title,
+            id,
+        }),
+        Event::Start(Tag::Image {
+            link_type,
+            dest_url,
+            title,
+            id,
+        }) if !is_safe_url(&dest_url) => Event::Start(Tag::Image {
+            link_type,
+            dest_url: "#".into(),
+            title,
+            id,
+        }),
+        event => event,
+    });
+    let mut out = String::new();
+    html::push_html(&mut out, events);
+    out
+}
+
+/// Everything piped to stdin, or nothing when stdin is a terminal.
+pub fn read_stdin_bytes() -> Vec<u8> {
+    let mut bytes = Vec::new();
 
     // Check if stdin is attached to a terminal or is being piped from another process
     if !atty::is(Stream::Stdin) {
-        let stdin = io::stdin();
-        for line in stdin.lock().lines() {
-            match line {
-                Ok(line) => lines.push(line),
-                Err(e) => {
-                    eprintln!("Error reading line: {}", e);
-                    break;
-                }
-            }
+        if let Err(e) = io::stdin().lock().read_to_end(&mut bytes) {
+            eprintln!("Error reading stdin: {}", e);
         }
     }
+    bytes
+}
+
+/// Piped input as text, with lines joined by `\n` and no trailing newline.
+/// Input that isn't valid UTF-8 is decoded with the invalid bytes replaced,
+/// and a warning.
+pub fn stdin_text(bytes: &[u8]) -> String {
+    let text = String::from_utf8_lossy(bytes);
+    if let Cow::Owned(_) = text {
+        eprintln!("Warning: stdin is not valid UTF-8, invalid bytes were replaced");
+    }
+    text.lines().collect::<Vec<_>>().join("\n")
+}
 
-    lines.join("\n")
+pub fn get_stdin() -> String {
+    stdin_text(&read_stdin_bytes())
 }
 
-pub fn get_file_contents_from_path(path: String) -> String {
-    fs::read_to_string(path).expect("Something went wrong reading the file")
+pub fn get_file_contents_from_path(path: String) -> Result<String, CgipError> {
+    fs::read_to_string(&path)
+        .map_err(|e| CgipError::Io(format!("Could not read '{}': {}", path, e)))
 }
 
 pub fn is_valid_yaml(yaml_str: &str) -> Result<bool, Error> {
@@ -50,7 +134,7 @@ pub fn is_valid_yaml(yaml_str: &str) -> Result<bool, Error> {
     match messages {
         Ok(msgs) => {
             for msg in msgs {
-                if msg.role.is_empty() || msg.content.is_empty() {
+                if msg.content.is_empty() && msg.tool_calls.is_none() {
                     return Ok(false);
                 }
                 // Add more validation logic if needed
@@ -64,7 +148,7 @@ pub fn is_valid_yaml(yaml_str: &str) -> Result<bool, Error> {
 pub fn ensure_config_file(
     config_directory: &std::path::PathBuf,
 ) -> Result<std::path::PathBuf, std::io::Error> {
-    new_ensure_config_directory(config_directory);
+    new_ensure_config_directory(config_directory)?;
 
     let config_file_path = config_directory.join("config.toml");
 
@@ -77,6 +161,9 @@ pub fn ensure_config_file(
 # Default model to use for completions
 model = "gpt-4o"
 
+# Provider API to talk to: openai, anthropic, ollama or gemini
+provider = "openai"
+
 # Whether to show progress indicators
 show_progress = false
 
@@ -91,6 +178,32 @@ jarjar = false
 
 # Number of context messages to store
 stored_context_length = 20
+
+# Token limit for the stored context, 0 for no limit
+stored_context_tokens = 0
+
+# Keep a short summary of messages dropped from the stored context
+summarize_evicted = false
+
+# Keep every message in the searchable history database
+history = true
+
+# Largest file, in bytes, read from a --file glob or --dir, 0 for no limit
+max_file_bytes = 262144
+
+# Largest total size, in bytes, of the files read for one request, 0 for no limit
+max_total_file_bytes = 1048576
+
+# How often to retry requests that hit rate limits or server errors
+max_retries = 3
+
+# Longest time in seconds to wait before a single retry
+max_retry_wait_secs = 60
+
+# Token budget for the request context. Older session messages are dropped
+# to fit it. 0 uses the model's context window, less room for the reply,
+# when it is known.
+max_input_tokens = 0
 "#;
 
         std::fs::write(&config_file_path, default_config)?;
@@ -102,8 +215,29 @@ stored_context_length = 20
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::chatgpt::Role;
     use tempfile::TempDir;
 
+    #[test]
+    fn test_markdown_to_html_drops_unsafe_urls() {
+        let html = markdown_to_html(
+            "[a](javascript:alert(1)) [b](JavaScript:alert(1)) <data:text/html,x> ![c](vbscript:x)",
+        );
+        assert!(!html.to_lowercase().contains("script:"), "{}", html);
+        assert!(!html.contains(r#"href="data:"#), "{}", html);
+
+        let html = markdown_to_html("[a](https://example.com) [b](mailto:a@b.c) [c](#notes)");
+        assert!(html.contains(r#"href="https://example.com""#));
+        assert!(html.contains(r#"href="mailto:a@b.c""#));
+        assert!(html.contains(r##"href="#notes""##));
+    }
+
+    #[test]
+    fn test_stdin_text() {
+        assert_eq!(stdin_text(b"one\r\ntwo\n"), "one\ntwo");
+        assert_eq!(stdin_text(b"caf\xe9 au lait"), "caf\u{FFFD} au lait");
+    }
+
     #[test]
     fn test_ensure_config_file() {
         let temp_dir = TempDir::new().unwrap();
@@ -127,31 +261,19 @@ mod tests {
 
         std::fs::write(&file_path, test_content).unwrap();
 
-        let result = get_file_contents_from_path(file_path.to_string_lossy().to_string());
+        let result = get_file_contents_from_path(file_path.to_string_lossy().to_string()).unwrap();
         assert_eq!(result, test_content);
     }
 
     #[test]
     fn test_markdown_from_messages() {
         let messages = vec![
-            Message {
-                role: "user".to_string(),
-                name: None,
-                tool_call_id: None,
-                tool_calls: None,
-                content: crate::chatgpt::MessageContent::Text("Hello".to_string()),
-            },
-            Message {
-                role: "assistant".to_string(),
-                name: None,
-                tool_call_id: None,
-                tool_calls: None,
-                content: crate::chatgpt::MessageContent::Text("Hi there!".to_string()),
-            },
+            Message::new(Role::User, "Hello".to_string()),
+            Message::new(Role::Assistant, "Hi there!".to_string()),
         ];
 
         let markdown = markdown_from_messages(messages);
         assert!(markdown.contains("**user**: Hello"));
         assert!(markdown.contains("**assistant**: Hi there!"));
     }
-}
\ No newline at end of file
+}